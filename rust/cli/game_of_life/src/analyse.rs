@@ -0,0 +1,133 @@
+use std::fmt::Write;
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::conway::analysis::{self, Analysis};
+use crate::conway::boundary::Boundary;
+use crate::conway::engine::{self, Kind};
+use crate::conway::pattern;
+use crate::conway::rule::Rule;
+
+/// Format of the report of a headless run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// a row per generation, the run described in # comments above
+    Csv,
+    /// a single object holding the run, its outcome and every generation
+    Json,
+}
+
+/// Options of a headless run, given on the command line
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+    pub kind: Kind,
+    /// edges of the dense board, the default of the engine if None
+    pub boundary: Option<Boundary>,
+    pub rule: Rule,
+    /// size of the dense board
+    pub rows: usize,
+    pub cols: usize,
+    pub generations: u64,
+    pub format: Format,
+}
+
+/// Report describes a run along with its analysis
+#[derive(Debug, Serialize)]
+struct Report {
+    /// name of the pattern, soup for a random one
+    pattern: String,
+    /// seed the soup was made with, to run it again
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+    rule: String,
+    engine: Kind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    boundary: Option<Boundary>,
+    generations: u64,
+    #[serde(flatten)]
+    analysis: Analysis,
+}
+
+/// runs the pattern for the given generations without drawing it and
+/// returns the report, the pattern is placed with its top left corner at
+/// (row, col), centred on the board by default
+pub fn run(
+    options: Options,
+    name: &str,
+    seed: Option<u64>,
+    start: &pattern::Loaded,
+    row: Option<usize>,
+    col: Option<usize>,
+) -> Result<String, String> {
+    let mut universe = engine::new(
+        options.kind,
+        options.rows,
+        options.cols,
+        options.rule,
+        options.boundary,
+    )?;
+    // nothing is undone, the history would only take up memory
+    universe.disable_history();
+    let (centre_row, centre_col) = start.centred(options.rows, options.cols);
+    let (row, col) = (row.unwrap_or(centre_row), col.unwrap_or(centre_col));
+    for (i, j, state) in &start.states {
+        universe.set_state((row + i) as i64, (col + j) as i64, *state);
+    }
+    for (i, j) in &start.cells {
+        universe.set((row + i) as i64, (col + j) as i64, true);
+    }
+
+    let report = Report {
+        pattern: name.to_string(),
+        seed,
+        rule: options.rule.to_string(),
+        engine: options.kind,
+        boundary: options.boundary.filter(|_| options.kind == Kind::Dense),
+        generations: options.generations,
+        analysis: analysis::analyse(universe.as_mut(), options.generations),
+    };
+
+    match options.format {
+        Format::Csv => Ok(csv(&report)),
+        Format::Json => serde_json::to_string_pretty(&report).map_err(|err| err.to_string()),
+    }
+}
+
+fn csv(report: &Report) -> String {
+    let mut out = String::new();
+
+    // writing to a String never fails
+    let _ = writeln!(out, "# pattern: {}", report.pattern);
+    if let Some(seed) = report.seed {
+        let _ = writeln!(out, "# seed: {}", seed);
+    }
+    let _ = writeln!(out, "# rule: {}", report.rule);
+    let _ = writeln!(
+        out,
+        "# engine: {}",
+        format!("{:?}", report.engine).to_lowercase()
+    );
+    if let Some(boundary) = report.boundary {
+        let _ = writeln!(out, "# boundary: {}", boundary);
+    }
+    let _ = writeln!(out, "# outcome: {}", report.analysis.outcome);
+
+    out.push_str("generation,population,top,left,height,width\n");
+    for sample in &report.analysis.samples {
+        let _ = match sample.bounds {
+            Some(bounds) => writeln!(
+                out,
+                "{},{},{},{},{},{}",
+                sample.generation,
+                sample.population,
+                bounds.top,
+                bounds.left,
+                bounds.height,
+                bounds.width
+            ),
+            None => writeln!(out, "{},{},,,,", sample.generation, sample.population),
+        };
+    }
+    out
+}
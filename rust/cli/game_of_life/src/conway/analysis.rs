@@ -0,0 +1,285 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::mem::size_of;
+
+use serde::Serialize;
+
+use crate::conway::engine::Engine;
+use crate::conway::game::State;
+
+/// most generations a run may be analysed for, each one is stepped
+/// through and sampled
+pub const MAX_GENERATIONS: u64 = 100_000;
+
+/// bytes the latest generations may take up by default to find out
+/// whether the run repeats itself
+pub const MAX_WINDOW_BYTES: usize = 64 << 20;
+
+/// Bounds is the smallest rectangle holding every live cell
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Bounds {
+    pub top: i64,
+    pub left: i64,
+    pub height: u64,
+    pub width: u64,
+}
+
+/// Sample describes the universe at a single generation
+#[derive(Debug, Clone, Serialize)]
+pub struct Sample {
+    pub generation: u64,
+    pub population: u64,
+    /// None once every cell died
+    pub bounds: Option<Bounds>,
+}
+
+/// Outcome is what the pattern settled into, from the generation it first
+/// looked the way it repeats
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Outcome {
+    /// every cell died
+    Extinct { generation: u64 },
+    /// nothing changes any more
+    StillLife { generation: u64 },
+    /// it repeats itself in place every period generations
+    Oscillator { generation: u64, period: u64 },
+    /// it repeats itself every period generations, moved by (rows, cols)
+    Spaceship {
+        generation: u64,
+        period: u64,
+        rows: i64,
+        cols: i64,
+    },
+    /// it did not repeat itself within the generations run
+    Unsettled,
+}
+
+impl std::fmt::Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Outcome::Extinct { generation } => write!(f, "extinct at generation {}", generation),
+            Outcome::StillLife { generation } => {
+                write!(f, "still life from generation {}", generation)
+            }
+            Outcome::Oscillator { generation, period } => write!(
+                f,
+                "oscillator of period {} from generation {}",
+                period, generation
+            ),
+            Outcome::Spaceship {
+                generation,
+                period,
+                rows,
+                cols,
+            } => write!(
+                f,
+                "spaceship moving ({}, {}) every {} generations from generation {}",
+                rows, cols, period, generation
+            ),
+            Outcome::Unsettled => write!(f, "unsettled"),
+        }
+    }
+}
+
+/// Analysis is the history of a run and what it settled into
+#[derive(Debug, Clone, Serialize)]
+pub struct Analysis {
+    pub outcome: Outcome,
+    pub samples: Vec<Sample>,
+}
+
+/// runs the universe for the given generations, sampling every one of them
+/// including the first. A generation repeats an earlier one when its cells,
+/// those in the other states of the rule included, are the same once moved
+/// to the top left corner. Only the latest generations taking up at most
+/// MAX_WINDOW_BYTES are kept to compare with, a pattern repeating itself
+/// over longer periods stays unsettled.
+pub fn analyse(universe: &mut dyn Engine, generations: u64) -> Analysis {
+    analyse_within(universe, generations, MAX_WINDOW_BYTES)
+}
+
+/// analyses the run keeping the latest generations in window_bytes at most
+pub fn analyse_within(
+    universe: &mut dyn Engine,
+    generations: u64,
+    window_bytes: usize,
+) -> Analysis {
+    // grows with the run, generations comes from the user
+    let mut samples = Vec::new();
+    let mut outcome = None;
+    let mut window = Window::new(window_bytes);
+
+    // an inclusive range ends at u64::MAX without overflowing
+    for step in 0..=generations {
+        if step > 0 {
+            universe.step();
+        }
+        let generation = universe.generation();
+        let mut cells = universe.cells();
+        let bounds = bounds(&cells);
+        samples.push(Sample {
+            generation,
+            population: cells.len() as u64,
+            bounds,
+        });
+
+        if outcome.is_some() {
+            continue;
+        }
+        // without live cells, such as Wireworld without electrons, the
+        // others are moved to the corner instead
+        let mut others: Vec<(i64, i64, State)> = universe.other_cells();
+        let Some(bounds) = bounds.or_else(|| {
+            let others: Vec<_> = others.iter().map(|(row, col, _)| (*row, *col)).collect();
+            self::bounds(&others)
+        }) else {
+            outcome = Some(Outcome::Extinct { generation });
+            continue;
+        };
+
+        cells.sort_unstable();
+        others.sort_unstable_by_key(|(row, col, _)| (*row, *col));
+        for (row, col) in cells.iter_mut() {
+            (*row, *col) = (*row - bounds.top, *col - bounds.left);
+        }
+        for (row, col, _) in others.iter_mut() {
+            (*row, *col) = (*row - bounds.top, *col - bounds.left);
+        }
+        let seen = Seen {
+            generation,
+            top: bounds.top,
+            left: bounds.left,
+            cells,
+            others,
+        };
+
+        match window.find(&seen) {
+            Some(first) => {
+                let period = generation - first.generation;
+                let (rows, cols) = (bounds.top - first.top, bounds.left - first.left);
+                outcome = Some(match (period, rows, cols) {
+                    (1, 0, 0) => Outcome::StillLife {
+                        generation: first.generation,
+                    },
+                    (_, 0, 0) => Outcome::Oscillator {
+                        generation: first.generation,
+                        period,
+                    },
+                    _ => Outcome::Spaceship {
+                        generation: first.generation,
+                        period,
+                        rows,
+                        cols,
+                    },
+                });
+                // nothing is compared any more
+                window = Window::new(0);
+            }
+            None => window.push(seen),
+        }
+    }
+
+    Analysis {
+        outcome: outcome.unwrap_or(Outcome::Unsettled),
+        samples,
+    }
+}
+
+/// Seen is a generation as it was moved to the top left corner, along
+/// with where its corner was
+#[derive(Debug)]
+struct Seen {
+    generation: u64,
+    top: i64,
+    left: i64,
+    cells: Vec<(i64, i64)>,
+    others: Vec<(i64, i64, State)>,
+}
+
+impl Seen {
+    fn hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.cells.hash(&mut hasher);
+        self.others.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// bytes the generation takes up, roughly
+    fn size(&self) -> usize {
+        size_of::<Seen>()
+            + self.cells.len() * size_of::<(i64, i64)>()
+            + self.others.len() * size_of::<(i64, i64, State)>()
+    }
+}
+
+/// Window holds the latest generations up to max_bytes, looked up by the
+/// hash of their cells. A hash only picks the generations to compare with,
+/// the cells decide whether they are the same.
+struct Window {
+    generations: VecDeque<Seen>,
+    /// hash of the cells, to the generations in the window with that hash
+    hashes: HashMap<u64, VecDeque<u64>>,
+    max_bytes: usize,
+    bytes: usize,
+}
+
+impl Window {
+    fn new(max_bytes: usize) -> Self {
+        Window {
+            generations: VecDeque::new(),
+            hashes: HashMap::new(),
+            max_bytes,
+            bytes: 0,
+        }
+    }
+
+    /// the earliest generation in the window with the same cells
+    fn find(&self, seen: &Seen) -> Option<&Seen> {
+        let oldest = self.generations.front()?.generation;
+        self.hashes
+            .get(&seen.hash())?
+            .iter()
+            .map(|generation| &self.generations[(generation - oldest) as usize])
+            .find(|earlier| earlier.cells == seen.cells && earlier.others == seen.others)
+    }
+
+    /// remembers the generation, forgetting the oldest ones beyond the limit
+    fn push(&mut self, seen: Seen) {
+        self.bytes += seen.size();
+        self.hashes
+            .entry(seen.hash())
+            .or_default()
+            .push_back(seen.generation);
+        self.generations.push_back(seen);
+
+        while self.bytes > self.max_bytes {
+            let Some(oldest) = self.generations.pop_front() else {
+                break;
+            };
+            self.bytes -= oldest.size();
+            let hash = oldest.hash();
+            if let Some(generations) = self.hashes.get_mut(&hash) {
+                generations.pop_front();
+                if generations.is_empty() {
+                    self.hashes.remove(&hash);
+                }
+            }
+        }
+    }
+}
+
+fn bounds(cells: &[(i64, i64)]) -> Option<Bounds> {
+    let top = cells.iter().map(|(row, _)| *row).min()?;
+    let bottom = cells.iter().map(|(row, _)| *row).max()?;
+    let left = cells.iter().map(|(_, col)| *col).min()?;
+    let right = cells.iter().map(|(_, col)| *col).max()?;
+
+    Some(Bounds {
+        top,
+        left,
+        height: (bottom - top + 1) as u64,
+        width: (right - left + 1) as u64,
+    })
+}
@@ -0,0 +1,59 @@
+use std::fmt;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// Boundary decides what lies beyond the edges of a board
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Boundary {
+    /// leaving an edge enters the opposite one
+    #[default]
+    Torus,
+    /// the cells beyond the edges are dead
+    Dead,
+    /// like a torus, but leaving the left or right edge enters the
+    /// opposite one upside down
+    Klein,
+    /// the board grows whenever a live cell reaches an edge
+    Infinite,
+}
+
+impl Boundary {
+    /// the cell of a rows x cols board at (row, col), which may lie
+    /// beyond the edges, or None if there is no such cell
+    pub fn wrap(self, row: i64, col: i64, rows: usize, cols: usize) -> Option<(usize, usize)> {
+        let (rows, cols) = (rows as i64, cols as i64);
+        match self {
+            Boundary::Torus => Some((row.rem_euclid(rows) as usize, col.rem_euclid(cols) as usize)),
+            Boundary::Klein => {
+                // every crossing of the left or right edge flips the board
+                let row = if col.div_euclid(cols) % 2 != 0 {
+                    rows - 1 - row
+                } else {
+                    row
+                };
+                Some((row.rem_euclid(rows) as usize, col.rem_euclid(cols) as usize))
+            }
+            Boundary::Dead | Boundary::Infinite => {
+                if (0..rows).contains(&row) && (0..cols).contains(&col) {
+                    Some((row as usize, col as usize))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Boundary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Boundary::Torus => "torus",
+            Boundary::Dead => "dead",
+            Boundary::Klein => "klein",
+            Boundary::Infinite => "infinite",
+        };
+        f.write_str(name)
+    }
+}
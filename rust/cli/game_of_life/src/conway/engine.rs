@@ -0,0 +1,134 @@
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::conway::boundary::Boundary;
+use crate::conway::game::{self, State};
+use crate::conway::rule::Rule;
+
+/// Engine advances a Game of Life universe. Cells are addressed by
+/// (row, col); engines with an unbounded universe accept negative
+/// coordinates, the dense engine maps them onto its board as its
+/// boundary says.
+pub trait Engine {
+    /// advances the universe by one generation
+    fn step(&mut self);
+
+    /// advances the universe by n generations. Engines which can
+    /// skip ahead, such as Hashlife, override it.
+    fn step_many(&mut self, n: u64) {
+        for _ in 0..n {
+            self.step();
+        }
+    }
+
+    fn generation(&self) -> u64;
+
+    /// number of live cells
+    fn population(&self) -> u64;
+
+    fn is_alive(&self, row: i64, col: i64) -> bool;
+
+    /// the live cells of the universe, in no particular order
+    fn cells(&self) -> Vec<(i64, i64)>;
+
+    /// the cells in other states than dead or alive, engines of two states
+    /// have none
+    fn other_cells(&self) -> Vec<(i64, i64, State)> {
+        Vec::new()
+    }
+
+    fn set(&mut self, row: i64, col: i64, alive: bool);
+
+    /// the state of the cell, engines of two states only know dead and alive
+    fn state(&self, row: i64, col: i64) -> State {
+        if self.is_alive(row, col) {
+            State::Alive
+        } else {
+            State::Dead
+        }
+    }
+
+    /// puts the cell in the state, engines of two states keep whether it is alive
+    fn set_state(&mut self, row: i64, col: i64, state: State) {
+        self.set(row, col, state.is_alive());
+    }
+
+    /// undoes the latest generation or set cell, returning whether there was
+    /// one. Only the dense engine keeps a history.
+    fn undo(&mut self) -> bool {
+        false
+    }
+
+    /// redoes the latest change undone, returning whether there was one
+    fn redo(&mut self) -> bool {
+        false
+    }
+
+    /// undoes changes back to the generation, returning whether it got there
+    fn rewind(&mut self, _generation: u64) -> bool {
+        false
+    }
+
+    /// stops keeping a history, for runs which are never undone
+    fn disable_history(&mut self) {}
+}
+
+/// Kind selects an engine on the command line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    /// a board of fixed size with a choice of edges, for small boards
+    Dense,
+    /// a set of live cells on an unbounded plane
+    Sparse,
+    /// memoised quadtrees on an unbounded plane, for huge
+    /// patterns and millions of generations
+    Hashlife,
+}
+
+/// creates an empty universe following the rule. The size and the boundary,
+/// a torus by default, are only used by the dense engine. The unbounded
+/// engines are always infinite and refuse rules with B0 or more than two
+/// states.
+pub fn new(
+    kind: Kind,
+    rows: usize,
+    cols: usize,
+    rule: Rule,
+    boundary: Option<Boundary>,
+) -> Result<Box<dyn Engine>, String> {
+    if kind == Kind::Dense && (rows == 0 || cols == 0) {
+        return Err("a dense board has a row and a column at least".to_string());
+    }
+    if kind != Kind::Dense {
+        if rule.births_from_nothing() {
+            return Err(format!(
+                "rule {} fills an unbounded plane, use the dense engine",
+                rule
+            ));
+        }
+        if rule.states() > 2 {
+            return Err(format!(
+                "rule {} has {} states, use the dense engine",
+                rule,
+                rule.states()
+            ));
+        }
+        if let Some(boundary) = boundary.filter(|b| *b != Boundary::Infinite) {
+            return Err(format!(
+                "a {} boundary needs the dense engine, the others are infinite",
+                boundary
+            ));
+        }
+    }
+
+    Ok(match kind {
+        Kind::Dense => {
+            let mut game = game::Game::with_rule(rows, cols, rule);
+            game.boundary = boundary.unwrap_or_default();
+            Box::new(game)
+        }
+        Kind::Sparse => Box::new(super::sparse::Sparse::with_rule(rule)),
+        Kind::Hashlife => Box::new(super::hashlife::Hashlife::with_rule(rule)),
+    })
+}
@@ -1,17 +1,12 @@
 use std::cmp;
 
-use rand::Rng;
-use tokio::sync::broadcast;
-
+use crate::conway::boundary::Boundary;
+use crate::conway::engine::Engine;
+use crate::conway::history::{Board, Change, Entry, History};
+use crate::conway::palette::Palette;
 use crate::conway::pattern;
-
-fn clear_screen() {
-    print!("\x1B[2J");
-}
-
-fn move_cursor_home() {
-    print!("\x1B[H");
-}
+use crate::conway::rule::Rule;
+use rand::Rng;
 
 fn random_foreground_color(placeholder: &str) -> String {
     let colors = [
@@ -22,22 +17,47 @@ fn random_foreground_color(placeholder: &str) -> String {
     format!("\x1B[{}m{}", color_code, placeholder)
 }
 
-#[derive(Clone, Copy, Default, Debug)]
+/// State of a cell, those beyond dead and alive belong to the rules
+/// of more than two states
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, Hash)]
+pub enum State {
+    #[default]
+    Dead,
+    Alive,
+    /// a cell of a Generations rule which stopped being alive step + 1
+    /// generations ago, it is dead after steps generations
+    Dying {
+        step: u8,
+        steps: u8,
+    },
+    /// the head of an electron of Wireworld, it counts as alive
+    Head,
+    /// the tail of an electron of Wireworld
+    Tail,
+    /// a wire of Wireworld which electrons run along
+    Conductor,
+}
+
+impl State {
+    /// whether the cell counts as a live neighbour
+    pub fn is_alive(self) -> bool {
+        matches!(self, State::Alive | State::Head)
+    }
+}
+
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
 pub struct Cell {
-    pub state: Option<()>,
+    pub state: State,
     pub cycles_alive: usize,
 }
 
 impl Cell {
-    fn alive() -> Self {
-        Cell {
-            state: Some(()),
-            cycles_alive: 0,
-        }
+    pub fn is_alive(&self) -> bool {
+        self.state.is_alive()
     }
 
     fn revive(&mut self) {
-        self.state = Some(());
+        self.state = State::Alive;
     }
 
     fn declare_dead(&mut self) {
@@ -56,25 +76,48 @@ impl Cell {
     //
     // Option 2 will be implemeted
     pub fn color(&self) -> String {
-        if self.state.is_none() {
-            return format!("hsl(0, 0%, 100%)"); // color white
-        }
+        Palette::default().colour(self).to_string()
+    }
 
-        // QUESTION: what would be a good way to
-        // color gradient the cell based on the cycles
-        // it stayed alive?
-        //
-        // current issue is that cells start with (0,0,0).
-        // each iteration the b in rgb evolves -> 255
-        format!("hsl(210, 100%, {}%)", cmp::min(self.cycles_alive * 10, 100))
+    // QUESTION: what would be a good way to
+    // color gradient the cell based on the cycles
+    // it stayed alive?
+    //
+    // current issue is that cells start with (0,0,0).
+    // each iteration the b in rgb evolves -> 255
+    fn lightness(&self) -> Option<usize> {
+        (self.state == State::Alive).then(|| cmp::min(self.cycles_alive * 10, 100))
     }
 }
 
+/// largest size an infinite board grows to by default, on either side
+pub const MAX_GROWN_SIZE: usize = 1024;
+
+/// rows or columns an infinite board grows by at once
+const GROWTH: usize = 8;
+
 #[derive(Debug)]
 pub struct Game {
     pub state: Vec<Vec<Cell>>,
     pub cycles: usize,
     pub alive_cells: usize,
+    pub rule: Rule,
+    /// what lies beyond the edges of the board
+    pub boundary: Boundary,
+    /// an infinite board stops growing at this size, on either side, and
+    /// behaves as if surrounded by dead cells from then on
+    pub max_size: usize,
+    /// the live cells the board started with, restored on reset
+    pub start: pattern::Pattern,
+    /// the cells the board started with in other states than dead or alive
+    pub start_states: pattern::States,
+    /// size of the board the game started with, an infinite board grows
+    start_size: (usize, usize),
+    /// the cell of the universe in the top left corner of the board, it
+    /// moves up and left as an infinite board grows that way
+    origin: (i64, i64),
+    /// the latest generations and flips, to step back through them
+    pub history: History,
 }
 
 impl Game {
@@ -83,119 +126,373 @@ impl Game {
             state: vec![vec![Cell::default(); cols]; rows],
             cycles: 0,
             alive_cells: 0,
+            rule: Rule::CONWAY,
+            boundary: Boundary::default(),
+            max_size: MAX_GROWN_SIZE,
+            start: Vec::new(),
+            start_states: Vec::new(),
+            start_size: (rows, cols),
+            origin: (0, 0),
+            history: History::default(),
         }
     }
 
-    pub fn initialise_random(rows: usize, cols: usize) -> Self {
-        let mut game = Game {
-            state: vec![vec![Cell::default(); cols]; rows],
-            cycles: 0,
-            alive_cells: 0,
-        };
+    pub fn with_rule(rows: usize, cols: usize, rule: Rule) -> Self {
+        Game {
+            rule,
+            ..Game::empty(rows, cols)
+        }
+    }
 
-        game.apply_pattern(
-            pattern::copperhead(),
-            game.state.len() / 2,
-            game.state[0].len() / 2,
-        );
-        game
+    /// clears the board and places the pattern with its top left corner
+    /// at (row, col), the pattern wraps around the edges
+    pub fn load(&mut self, pattern: &pattern::Pattern, row: usize, col: usize) {
+        self.load_with_states(pattern, &Vec::new(), row, col);
     }
 
-    fn apply_pattern(&mut self, pattern: pattern::Pattern, start_x: usize, start_y: usize) {
+    /// loads the live cells of a pattern along with its cells in other
+    /// states, such as the wires of Wireworld
+    pub fn load_with_states(
+        &mut self,
+        pattern: &pattern::Pattern,
+        states: &pattern::States,
+        row: usize,
+        col: usize,
+    ) {
         let rows = self.state.len();
-        let cols = self.state[0].len();
+        let cols = self.state.first().map_or(0, Vec::len);
+        // a board without cells has nowhere to put the pattern
+        let wrap = |i: usize, j: usize| {
+            (rows > 0 && cols > 0).then(|| ((row + i) % rows, (col + j) % cols))
+        };
 
-        pattern.iter().for_each(|tuple| {
-            self.state[wrap(start_x + tuple.0, rows)][wrap(start_y + tuple.1, cols)].revive();
-        });
+        self.start = pattern.iter().filter_map(|&(i, j)| wrap(i, j)).collect();
+        self.start_states = states
+            .iter()
+            .filter_map(|&(i, j, state)| wrap(i, j).map(|(i, j)| (i, j, state)))
+            .collect();
+        self.start_size = (rows, cols);
+        self.reset();
     }
 
+    /// restores the board the game started with, its history starts over
     pub fn reset(&mut self) {
-        self.state = vec![vec![Cell::default(); self.state.len()]; self.state[0].len()];
-        self.apply_pattern(
-            pattern::copperhead(),
-            self.state.len() / 2,
-            self.state[0].len() / 2,
-        );
+        let (rows, cols) = self.start_size;
+        self.state = vec![vec![Cell::default(); cols]; rows];
+        self.origin = (0, 0);
+        self.history.clear();
+        self.cycles = 0;
+        self.alive_cells = 0;
+
+        for &(i, j, state) in &self.start_states {
+            self.state[i][j].state = state;
+        }
+        for &(i, j) in &self.start {
+            self.state[i][j].state = self.rule.alive();
+        }
+        self.alive_cells = self.population() as usize;
     }
 
-    pub fn flip(&mut self, i: usize, j: usize) {
-        match self.state[i][j].state {
-            Some(_) => {
-                self.state[i][j].state = None;
-                if self.alive_cells > 0 {
-                    self.alive_cells -= 1;
+    /// the cell at (row, col) of the universe, beyond the edges of a board
+    /// which does not wrap around cells are dead
+    pub fn cell(&self, row: i64, col: i64) -> Cell {
+        self.index(row, col)
+            .map(|(i, j)| self.state[i][j])
+            .unwrap_or_default()
+    }
+
+    /// the live cells of the board
+    pub fn live_cells(&self) -> pattern::Pattern {
+        let mut cells = Vec::new();
+        for (i, row) in self.state.iter().enumerate() {
+            for (j, cell) in row.iter().enumerate() {
+                if cell.is_alive() {
+                    cells.push((i, j));
                 }
             }
-            None => {
-                self.state[i][j].state = Some(());
-                self.alive_cells += 1;
+        }
+        cells
+    }
+
+    /// the cells of the board in other states than dead or alive
+    pub fn other_cells(&self) -> pattern::States {
+        let mut cells = Vec::new();
+        for (i, row) in self.state.iter().enumerate() {
+            for (j, cell) in row.iter().enumerate() {
+                if cell.state != State::Dead && !cell.is_alive() {
+                    cells.push((i, j, cell.state));
+                }
             }
-        };
+        }
+        cells
     }
 
-    pub fn next_cycle(&self) -> Game {
-        let mut next = Game::empty(self.state.len(), self.state[0].len());
+    /// flips the cell at (i, j) to the next state the rule flips it to,
+    /// between alive and dead for two states, it may be undone
+    pub fn flip(&mut self, i: usize, j: usize) {
+        let state = self.rule.flipped(self.state[i][j].state);
+        self.put(i, j, state);
+    }
 
-        next.cycles += self.cycles + 1;
-        next.alive_cells = 0;
+    /// puts the cell at (i, j) in the state, it may be undone
+    fn put(&mut self, i: usize, j: usize, state: State) {
+        self.history.record(Entry {
+            change: Change::Diff(vec![(i, j, self.state[i][j])]),
+            cycles: self.cycles,
+            alive_cells: self.alive_cells,
+        });
 
-        for i in 0..self.state.len() {
-            for j in 0..self.state[i].len() {
-                // copy state from previous iteration to next
-                next.state[i][j] = self.state[i][j].clone();
+        let cell = &mut self.state[i][j];
+        match (cell.is_alive(), state.is_alive()) {
+            (true, false) => self.alive_cells = self.alive_cells.saturating_sub(1),
+            (false, true) => self.alive_cells += 1,
+            _ => {}
+        }
+        cell.state = state;
+        cell.declare_dead();
+    }
 
-                let count = self.count_neigbours(i, j);
+    /// advances the game by a generation and returns the cells which look
+    /// different now, those born or dead and those which aged. An infinite
+    /// board grows first if a live cell touches an edge, the cells are those
+    /// of the grown board.
+    pub fn next_cycle(&mut self) -> Vec<(usize, usize)> {
+        let (cycles, alive_cells) = (self.cycles, self.alive_cells);
+        let grown_from = match self.boundary {
+            Boundary::Infinite => self.grow(),
+            _ => None,
+        };
 
-                // Any live cell with fewer than two live neighbors dies, as if by underpopulation.
-                if count < 2 {
-                    next.state[i][j].state = None;
-                    next.state[i][j].declare_dead();
+        let mut next = self.state.clone();
+        let mut changed = Vec::new();
+        // the cells which changed state, those which only aged are left out
+        let mut diff = Vec::new();
+        let keep_history = self.history.is_enabled();
+
+        self.cycles += 1;
+        self.alive_cells = 0;
+
+        for (i, row) in next.iter_mut().enumerate() {
+            for (j, cell) in row.iter_mut().enumerate() {
+                let current = self.state[i][j].state;
+                let count = self.count_neighbours(i, j);
+
+                match (current, self.rule.next_state(current, count)) {
+                    // the cell survives and ages
+                    (State::Alive, State::Alive) => cell.declear_survival(),
+                    // the cell is born
+                    (_, State::Alive) => cell.revive(),
+                    // the cell dies, of underpopulation or overpopulation, or
+                    // passes through the other states of the rule
+                    (_, state) => {
+                        cell.state = state;
+                        cell.declare_dead();
+                    }
                 }
-                // Any live cell with two or three live neighbors lives on to the next generation.
-                if count >= 4 {
-                    next.state[i][j].state = None;
-                    next.state[i][j].declare_dead();
+
+                if cell.is_alive() {
+                    self.alive_cells += 1;
                 }
-                // Any live cell with more than three live neighbors dies, as if by overpopulation.
-                if count == 3 {
-                    next.state[i][j].state = Some(());
-                    next.state[i][j].declear_survival();
+                if cell.state != current || cell.lightness() != self.state[i][j].lightness() {
+                    changed.push((i, j));
                 }
-
-                if next.state[i][j].state.is_some() {
-                    next.alive_cells += 1;
+                if keep_history && cell.state != current {
+                    diff.push((i, j, self.state[i][j]));
                 }
             }
         }
-        next
+
+        let previous = std::mem::replace(&mut self.state, next);
+        if !keep_history {
+            return changed;
+        }
+
+        let cells = previous.len() * previous[0].len();
+        let change = match grown_from {
+            Some(board) => Change::Snapshot(board),
+            // a cell of a diff takes up about as much as two of a snapshot
+            None if diff.len() * 2 > cells => Change::Snapshot(Board {
+                state: previous,
+                origin: self.origin,
+            }),
+            None => Change::Generation {
+                cells: diff,
+                aged: -1,
+            },
+        };
+        self.history.record(Entry {
+            change,
+            cycles,
+            alive_cells,
+        });
+
+        changed
     }
 
-    fn count_neigbours(&self, i: usize, j: usize) -> usize {
-        let mut count = 0;
+    /// undoes the latest generation or flip and returns the cells which look
+    /// different now, None if there is nothing left to undo
+    pub fn undo(&mut self) -> Option<Vec<(usize, usize)>> {
+        let entry = self.history.pop_undo()?;
+        let (undone, changed) = self.restore(entry);
+        self.history.push_redo(undone);
+        Some(changed)
+    }
+
+    /// redoes the latest change undone and returns the cells which look
+    /// different now, None if there is nothing left to redo
+    pub fn redo(&mut self) -> Option<Vec<(usize, usize)>> {
+        let entry = self.history.pop_redo()?;
+        let (redone, changed) = self.restore(entry);
+        self.history.push_undo(redone);
+        Some(changed)
+    }
+
+    /// undoes changes until the game is back at the generation, or as far
+    /// as its history goes. Returns whether it got there.
+    pub fn rewind(&mut self, generation: usize) -> bool {
+        while self.cycles > generation {
+            if self.undo().is_none() {
+                return false;
+            }
+        }
+        self.cycles == generation
+    }
+
+    /// brings the game to the way the entry describes it, returning the
+    /// entry which brings it back along with the cells which changed
+    fn restore(&mut self, entry: Entry) -> (Entry, Vec<(usize, usize)>) {
+        let (change, changed) = match entry.change {
+            Change::Diff(cells) => {
+                let changed = cells.iter().map(|(i, j, _)| (*i, *j)).collect();
+                let cells = cells
+                    .into_iter()
+                    .map(|(i, j, cell)| (i, j, std::mem::replace(&mut self.state[i][j], cell)))
+                    .collect();
+                (Change::Diff(cells), changed)
+            }
+            Change::Generation { cells, aged } => {
+                let current = cells
+                    .iter()
+                    .map(|(i, j, _)| (*i, *j, self.state[*i][*j]))
+                    .collect();
+                // every live cell ages, those which changed state are
+                // then put back the way they were
+                let mut changed = Vec::new();
+                for (i, row) in self.state.iter_mut().enumerate() {
+                    for (j, cell) in row.iter_mut().enumerate() {
+                        if cell.state == State::Alive {
+                            cell.cycles_alive = cell.cycles_alive.saturating_add_signed(aged);
+                            changed.push((i, j));
+                        }
+                    }
+                }
+                for (i, j, cell) in cells {
+                    self.state[i][j] = cell;
+                    changed.push((i, j));
+                }
+                changed.sort_unstable();
+                changed.dedup();
+                (
+                    Change::Generation {
+                        cells: current,
+                        aged: -aged,
+                    },
+                    changed,
+                )
+            }
+            Change::Snapshot(board) => {
+                let current = Board {
+                    state: std::mem::replace(&mut self.state, board.state),
+                    origin: std::mem::replace(&mut self.origin, board.origin),
+                };
+                let changed = (0..self.state.len())
+                    .flat_map(|i| (0..self.state[0].len()).map(move |j| (i, j)))
+                    .collect();
+                (Change::Snapshot(current), changed)
+            }
+        };
+
+        let back = Entry {
+            change,
+            cycles: std::mem::replace(&mut self.cycles, entry.cycles),
+            alive_cells: std::mem::replace(&mut self.alive_cells, entry.alive_cells),
+        };
+        (back, changed)
+    }
 
-        let b = &self.state;
+    /// number of live cells among the eight around (i, j), the cells beyond
+    /// the edges depend on the boundary
+    fn count_neighbours(&self, i: usize, j: usize) -> usize {
+        let rows = self.state.len();
+        let cols = self.state[0].len();
 
-        let cols = b[0].len();
-        let rows = b.len();
+        let mut count = 0;
         for x in -1..2 {
             for y in -1..2 {
-                let row = ((i as i32 + x + rows as i32) % rows as i32) as usize;
-                let col = ((j as i32 + y + cols as i32) % cols as i32) as usize;
+                if x == 0 && y == 0 {
+                    continue;
+                }
 
-                if b[row][col].state.is_some() {
-                    count += 1;
-                };
+                let neighbour = self.boundary.wrap(i as i64 + x, j as i64 + y, rows, cols);
+                if let Some((row, col)) = neighbour {
+                    if self.state[row][col].is_alive() {
+                        count += 1;
+                    }
+                }
             }
         }
 
         count
     }
 
-    pub fn repaint(&self) {
-        move_cursor_home();
-        clear_screen();
-        print!("{}", self);
+    /// grows the board on every side a live cell touches, so that the cells
+    /// beyond the edges are always dead. Returns the board before it grew.
+    fn grow(&mut self) -> Option<Board> {
+        let rows = self.state.len();
+        let cols = self.state[0].len();
+        // every state but dead, so that the wires of Wireworld do not reach
+        // the edges either
+        let alive = |i: usize, j: usize| self.state[i][j].state != State::Dead;
+        let growth = |touched: bool| if touched { GROWTH } else { 0 };
+
+        let top = growth((0..cols).any(|j| alive(0, j)));
+        let bottom = growth((0..cols).any(|j| alive(rows - 1, j)));
+        let left = growth((0..rows).any(|i| alive(i, 0)));
+        let right = growth((0..rows).any(|i| alive(i, cols - 1)));
+        self.pad(top, bottom, left, right)
+    }
+
+    /// adds dead cells to every side of the board, as many as fit within
+    /// the largest size. Returns the board before it grew, if it did.
+    fn pad(&mut self, top: usize, bottom: usize, left: usize, right: usize) -> Option<Board> {
+        let rows = self.state.len();
+        let cols = self.state[0].len();
+
+        let room = self.max_size.saturating_sub(rows);
+        let top = top.min(room);
+        let bottom = bottom.min(room - top);
+        let room = self.max_size.saturating_sub(cols);
+        let left = left.min(room);
+        let right = right.min(room - left);
+        if top + bottom + left + right == 0 {
+            return None;
+        }
+
+        let cols = left + cols + right;
+        let mut state = vec![vec![Cell::default(); cols]; top];
+        for row in &self.state {
+            let mut padded = vec![Cell::default(); left];
+            padded.extend(row);
+            padded.resize(cols, Cell::default());
+            state.push(padded);
+        }
+        state.resize(state.len() + bottom, vec![Cell::default(); cols]);
+
+        let origin = (self.origin.0 - top as i64, self.origin.1 - left as i64);
+        Some(Board {
+            state: std::mem::replace(&mut self.state, state),
+            origin: std::mem::replace(&mut self.origin, origin),
+        })
     }
 }
 
@@ -205,9 +502,8 @@ impl std::fmt::Display for Game {
 
         self.state.iter().for_each(|row| {
             row.iter().for_each(|cell| match cell.state {
-                None => out.push(' '),
-                Some(_) => out.push_str(&random_foreground_color("X")),
-                _ => unreachable!("cells can only be in the state of dead (=0) or alive (=1)"),
+                State::Dead => out.push(' '),
+                _ => out.push_str(&random_foreground_color("X")),
             });
             out.push('\n');
         });
@@ -216,6 +512,110 @@ impl std::fmt::Display for Game {
     }
 }
 
-fn wrap(i: usize, dimension: usize) -> usize {
-    (i + dimension) % dimension
+/// the dense engine, coordinates outside the board depend on the boundary.
+/// Setting a cell beyond the edges of an infinite board grows it.
+impl Engine for Game {
+    fn step(&mut self) {
+        self.next_cycle();
+    }
+
+    fn generation(&self) -> u64 {
+        self.cycles as u64
+    }
+
+    fn population(&self) -> u64 {
+        self.state
+            .iter()
+            .flatten()
+            .filter(|cell| cell.is_alive())
+            .count() as u64
+    }
+
+    fn is_alive(&self, row: i64, col: i64) -> bool {
+        self.state(row, col).is_alive()
+    }
+
+    fn state(&self, row: i64, col: i64) -> State {
+        self.cell(row, col).state
+    }
+
+    fn cells(&self) -> Vec<(i64, i64)> {
+        self.live_cells()
+            .into_iter()
+            .map(|(i, j)| (self.origin.0 + i as i64, self.origin.1 + j as i64))
+            .collect()
+    }
+
+    fn other_cells(&self) -> Vec<(i64, i64, State)> {
+        Game::other_cells(self)
+            .into_iter()
+            .map(|(i, j, state)| (self.origin.0 + i as i64, self.origin.1 + j as i64, state))
+            .collect()
+    }
+
+    fn set(&mut self, row: i64, col: i64, alive: bool) {
+        let state = if alive {
+            self.rule.alive()
+        } else {
+            State::Dead
+        };
+        self.set_state(row, col, state);
+    }
+
+    fn set_state(&mut self, row: i64, col: i64, state: State) {
+        if state != State::Dead && self.boundary == Boundary::Infinite {
+            let (top, left) = self.origin;
+            let (bottom, right) = (
+                top + self.state.len() as i64 - 1,
+                left + self.state[0].len() as i64 - 1,
+            );
+            let grown_from = self.pad(
+                (top - row).max(0) as usize,
+                (row - bottom).max(0) as usize,
+                (left - col).max(0) as usize,
+                (col - right).max(0) as usize,
+            );
+            if let Some(board) = grown_from {
+                self.history.record(Entry {
+                    change: Change::Snapshot(board),
+                    cycles: self.cycles,
+                    alive_cells: self.alive_cells,
+                });
+            }
+        }
+
+        if let Some((i, j)) = self.index(row, col) {
+            if self.state[i][j].state != state {
+                self.put(i, j, state);
+            }
+        }
+    }
+
+    fn undo(&mut self) -> bool {
+        Game::undo(self).is_some()
+    }
+
+    fn redo(&mut self) -> bool {
+        Game::redo(self).is_some()
+    }
+
+    fn rewind(&mut self, generation: u64) -> bool {
+        Game::rewind(self, generation as usize)
+    }
+
+    fn disable_history(&mut self) {
+        self.history = History::disabled();
+    }
+}
+
+impl Game {
+    /// the cell of the board at (row, col) of the universe
+    fn index(&self, row: i64, col: i64) -> Option<(usize, usize)> {
+        self.boundary.wrap(
+            row - self.origin.0,
+            col - self.origin.1,
+            self.state.len(),
+            self.state[0].len(),
+        )
+    }
 }
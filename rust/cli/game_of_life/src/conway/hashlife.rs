@@ -0,0 +1,435 @@
+use std::collections::HashMap;
+
+use crate::conway::engine::Engine;
+use crate::conway::rule::Rule;
+
+/*
+* Hashlife represents the universe as a quadtree. Every node is a square
+* of 2^level cells made of four nodes one level below, leaves are single
+* cells. Identical squares are stored only once (hash consing), hence a
+* pattern repeating itself in space costs next to nothing.
+*
+* The result of a node, its centre advanced by some generations, is
+* memoised as well. A pattern repeating itself in time is therefore
+* advanced in steps that double in size, which lets it run for millions
+* of generations.
+*/
+
+type Id = u32;
+
+const DEAD: Id = 0;
+const ALIVE: Id = 1;
+/// the root of a fresh universe
+const MIN_LEVEL: u8 = 3;
+/// the plane ends at +-2^61, far beyond any pattern one could run.
+/// Cells which would leave it die.
+const MAX_LEVEL: u8 = 62;
+/// log2 of the most generations the root is advanced by at once, it
+/// has to be three levels above
+const MAX_JUMP: u8 = MAX_LEVEL - 3;
+/// number of nodes after which unreachable nodes and results are dropped
+const MAX_NODES: usize = 1 << 21;
+
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    level: u8,
+    nw: Id,
+    ne: Id,
+    sw: Id,
+    se: Id,
+    population: u64,
+}
+
+/// Hashlife is an unbounded universe centred on (0, 0). Rules with
+/// B0 are not supported as they would fill the plane.
+pub struct Hashlife {
+    nodes: Vec<Node>,
+    lookup: HashMap<[Id; 4], Id>,
+    /// node and log2 of the generations it was advanced by
+    results: HashMap<(Id, u8), Id>,
+    /// the empty node of each level
+    empty: Vec<Id>,
+    root: Id,
+    generation: u64,
+    /// fixed for the life of the universe as the results depend on it
+    rule: Rule,
+}
+
+impl Default for Hashlife {
+    fn default() -> Self {
+        Hashlife::new()
+    }
+}
+
+impl Hashlife {
+    pub fn new() -> Self {
+        Hashlife::with_rule(Rule::CONWAY)
+    }
+
+    pub fn with_rule(rule: Rule) -> Self {
+        let mut life = Hashlife {
+            nodes: Vec::new(),
+            lookup: HashMap::new(),
+            results: HashMap::new(),
+            empty: Vec::new(),
+            root: DEAD,
+            generation: 0,
+            rule,
+        };
+        life.init_leaves();
+        life.root = life.empty(MIN_LEVEL);
+        life
+    }
+
+    fn init_leaves(&mut self) {
+        let leaf = |population| Node {
+            level: 0,
+            nw: DEAD,
+            ne: DEAD,
+            sw: DEAD,
+            se: DEAD,
+            population,
+        };
+        self.nodes = vec![leaf(0), leaf(1)];
+        self.lookup.clear();
+        self.results.clear();
+        self.empty = vec![DEAD];
+    }
+
+    fn node(&self, id: Id) -> Node {
+        self.nodes[id as usize]
+    }
+
+    fn level(&self) -> u8 {
+        self.node(self.root).level
+    }
+
+    /// returns the node made of the four quadrants, which
+    /// have to be of the same level
+    fn join(&mut self, nw: Id, ne: Id, sw: Id, se: Id) -> Id {
+        if let Some(id) = self.lookup.get(&[nw, ne, sw, se]) {
+            return *id;
+        }
+
+        let population = [nw, ne, sw, se]
+            .iter()
+            .map(|id| self.node(*id).population)
+            .sum();
+        let id = self.nodes.len() as Id;
+        self.nodes.push(Node {
+            level: self.node(nw).level + 1,
+            nw,
+            ne,
+            sw,
+            se,
+            population,
+        });
+        self.lookup.insert([nw, ne, sw, se], id);
+        id
+    }
+
+    fn empty(&mut self, level: u8) -> Id {
+        while self.empty.len() <= level as usize {
+            let below = *self.empty.last().unwrap();
+            let id = self.join(below, below, below, below);
+            self.empty.push(id);
+        }
+        self.empty[level as usize]
+    }
+
+    /// the square of half the size in the middle of the node
+    fn centre(&mut self, id: Id) -> Id {
+        let n = self.node(id);
+        let (nw, ne, sw, se) = (
+            self.node(n.nw),
+            self.node(n.ne),
+            self.node(n.sw),
+            self.node(n.se),
+        );
+        self.join(nw.se, ne.sw, sw.ne, se.nw)
+    }
+
+    /// doubles the size of the universe, keeping the pattern in the middle
+    fn expand(&mut self) {
+        let root = self.node(self.root);
+        let e = self.empty(root.level - 1);
+
+        let nw = self.join(e, e, e, root.nw);
+        let ne = self.join(e, e, root.ne, e);
+        let sw = self.join(e, root.sw, e, e);
+        let se = self.join(root.se, e, e, e);
+        self.root = self.join(nw, ne, sw, se);
+    }
+
+    /// advances the 4x4 node by one generation, returning its 2x2 centre
+    fn step_leaf(&mut self, id: Id) -> Id {
+        let n = self.node(id);
+        let mut cells = [[false; 4]; 4];
+        for (quadrant, (row, col)) in [
+            (n.nw, (0, 0)),
+            (n.ne, (0, 2)),
+            (n.sw, (2, 0)),
+            (n.se, (2, 2)),
+        ] {
+            let q = self.node(quadrant);
+            cells[row][col] = q.nw == ALIVE;
+            cells[row][col + 1] = q.ne == ALIVE;
+            cells[row + 1][col] = q.sw == ALIVE;
+            cells[row + 1][col + 1] = q.se == ALIVE;
+        }
+
+        let next = |row: usize, col: usize| {
+            let count = cells[row - 1..=row + 1]
+                .iter()
+                .flat_map(|line| &line[col - 1..=col + 1])
+                .filter(|alive| **alive)
+                .count()
+                - cells[row][col] as usize;
+            if self.rule.next(cells[row][col], count) {
+                ALIVE
+            } else {
+                DEAD
+            }
+        };
+
+        self.join(next(1, 1), next(1, 2), next(2, 1), next(2, 2))
+    }
+
+    /// advances the node of level k by 2^j generations, j <= k - 2,
+    /// and returns its centre of level k - 1
+    fn successor(&mut self, id: Id, j: u8) -> Id {
+        if let Some(result) = self.results.get(&(id, j)) {
+            return *result;
+        }
+
+        let n = self.node(id);
+        let result = if n.population == 0 {
+            self.empty(n.level - 1)
+        } else if n.level == 2 {
+            self.step_leaf(id)
+        } else {
+            let (nw, ne, sw, se) = (
+                self.node(n.nw),
+                self.node(n.ne),
+                self.node(n.sw),
+                self.node(n.se),
+            );
+
+            // the nine overlapping squares of level k - 1
+            let squares = [
+                n.nw,
+                self.join(nw.ne, ne.nw, nw.se, ne.sw),
+                n.ne,
+                self.join(nw.sw, nw.se, sw.nw, sw.ne),
+                self.join(nw.se, ne.sw, sw.ne, se.nw),
+                self.join(ne.sw, ne.se, se.nw, se.ne),
+                n.sw,
+                self.join(sw.ne, se.nw, sw.se, se.sw),
+                n.se,
+            ];
+
+            // at full speed both halves advance by 2^(k-3) generations,
+            // else only the second half advances by 2^j
+            let full = j == n.level - 2;
+            let mut c = [DEAD; 9];
+            for (i, square) in squares.into_iter().enumerate() {
+                c[i] = if full {
+                    self.successor(square, n.level - 3)
+                } else {
+                    self.centre(square)
+                };
+            }
+
+            let j = if full { n.level - 3 } else { j };
+            let quadrants = [
+                self.join(c[0], c[1], c[3], c[4]),
+                self.join(c[1], c[2], c[4], c[5]),
+                self.join(c[3], c[4], c[6], c[7]),
+                self.join(c[4], c[5], c[7], c[8]),
+            ];
+            let [nw, ne, sw, se] = quadrants.map(|quadrant| self.successor(quadrant, j));
+            self.join(nw, ne, sw, se)
+        };
+
+        self.results.insert((id, j), result);
+        result
+    }
+
+    /// advances the universe by 2^j generations, j <= MAX_JUMP
+    fn advance(&mut self, j: u8) {
+        // the pattern has to stay in the centre while it grows by
+        // up to one cell per generation, which the result covers. At the
+        // edge of the plane what does not fit is lost.
+        loop {
+            let inner = self.centre(self.root);
+            let inner = self.centre(inner);
+            if self.level() >= j + 3
+                && (self.node(inner).population == self.node(self.root).population
+                    || self.level() >= MAX_LEVEL)
+            {
+                break;
+            }
+            self.expand();
+        }
+
+        self.root = self.successor(self.root, j);
+        self.generation = self.generation.saturating_add(1 << j);
+
+        if self.nodes.len() > MAX_NODES {
+            self.collect();
+        }
+    }
+
+    /// drops every node the root does not reach along with all results
+    fn collect(&mut self) {
+        let nodes = std::mem::take(&mut self.nodes);
+        self.init_leaves();
+
+        let mut copied = HashMap::new();
+        self.root = self.copy(&nodes, self.root, &mut copied);
+    }
+
+    fn copy(&mut self, from: &[Node], id: Id, copied: &mut HashMap<Id, Id>) -> Id {
+        if id == DEAD || id == ALIVE {
+            return id;
+        }
+        if let Some(new) = copied.get(&id) {
+            return *new;
+        }
+
+        let n = from[id as usize];
+        let nw = self.copy(from, n.nw, copied);
+        let ne = self.copy(from, n.ne, copied);
+        let sw = self.copy(from, n.sw, copied);
+        let se = self.copy(from, n.se, copied);
+        let new = self.join(nw, ne, sw, se);
+        copied.insert(id, new);
+        new
+    }
+
+    /// half the width of the universe
+    fn half(&self) -> i64 {
+        1 << (self.level() - 1)
+    }
+
+    fn contains(&self, row: i64, col: i64) -> bool {
+        let half = self.half();
+        (-half..half).contains(&row) && (-half..half).contains(&col)
+    }
+
+    /// adds the live cells of the node, its top left corner at (row, col)
+    fn cells_in(&self, id: Id, row: i64, col: i64, cells: &mut Vec<(i64, i64)>) {
+        let n = self.node(id);
+        if n.population == 0 {
+            return;
+        }
+        if n.level == 0 {
+            cells.push((row, col));
+            return;
+        }
+
+        let half = 1 << (n.level - 1);
+        self.cells_in(n.nw, row, col, cells);
+        self.cells_in(n.ne, row, col + half, cells);
+        self.cells_in(n.sw, row + half, col, cells);
+        self.cells_in(n.se, row + half, col + half, cells);
+    }
+
+    /// sets the cell at (row, col) relative to the top left corner of the node
+    fn set_in(&mut self, id: Id, row: i64, col: i64, alive: bool) -> Id {
+        let n = self.node(id);
+        if n.level == 0 {
+            return if alive { ALIVE } else { DEAD };
+        }
+
+        let half = 1 << (n.level - 1);
+        let (mut nw, mut ne, mut sw, mut se) = (n.nw, n.ne, n.sw, n.se);
+        match (row < half, col < half) {
+            (true, true) => nw = self.set_in(nw, row, col, alive),
+            (true, false) => ne = self.set_in(ne, row, col - half, alive),
+            (false, true) => sw = self.set_in(sw, row - half, col, alive),
+            (false, false) => se = self.set_in(se, row - half, col - half, alive),
+        }
+        self.join(nw, ne, sw, se)
+    }
+}
+
+impl Engine for Hashlife {
+    fn step(&mut self) {
+        self.advance(0);
+    }
+
+    /// advances in the power of two steps n is made of, those beyond
+    /// 2^MAX_JUMP in several of that size
+    fn step_many(&mut self, n: u64) {
+        for j in 0..u64::BITS as u8 {
+            if n & (1 << j) == 0 {
+                continue;
+            }
+            if j <= MAX_JUMP {
+                self.advance(j);
+            } else {
+                for _ in 0..1u64 << (j - MAX_JUMP) {
+                    self.advance(MAX_JUMP);
+                }
+            }
+        }
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn population(&self) -> u64 {
+        self.node(self.root).population
+    }
+
+    fn is_alive(&self, row: i64, col: i64) -> bool {
+        if !self.contains(row, col) {
+            return false;
+        }
+
+        let (mut id, mut row, mut col) = (self.root, row + self.half(), col + self.half());
+        loop {
+            let n = self.node(id);
+            if n.population == 0 {
+                return false;
+            }
+            if n.level == 0 {
+                return id == ALIVE;
+            }
+
+            let half = 1 << (n.level - 1);
+            id = match (row < half, col < half) {
+                (true, true) => n.nw,
+                (true, false) => n.ne,
+                (false, true) => n.sw,
+                (false, false) => n.se,
+            };
+            if row >= half {
+                row -= half;
+            }
+            if col >= half {
+                col -= half;
+            }
+        }
+    }
+
+    fn cells(&self) -> Vec<(i64, i64)> {
+        let mut cells = Vec::with_capacity(self.population() as usize);
+        self.cells_in(self.root, -self.half(), -self.half(), &mut cells);
+        cells
+    }
+
+    fn set(&mut self, row: i64, col: i64, alive: bool) {
+        while !self.contains(row, col) {
+            if self.level() >= MAX_LEVEL {
+                return;
+            }
+            self.expand();
+        }
+
+        let half = self.half();
+        self.root = self.set_in(self.root, row + half, col + half, alive);
+    }
+}
@@ -0,0 +1,151 @@
+use std::collections::VecDeque;
+use std::mem::size_of;
+
+use crate::conway::game::Cell;
+
+/// bytes the changes of a game may take up by default, the oldest
+/// ones are forgotten beyond
+pub const MAX_BYTES: usize = 64 << 20;
+
+/// Board is the whole state of a board along with where it lies in the
+/// universe, which changes as an infinite board grows
+#[derive(Debug, Clone)]
+pub struct Board {
+    pub state: Vec<Vec<Cell>>,
+    pub origin: (i64, i64),
+}
+
+/// Change is what differs between the board before and after a change
+#[derive(Debug, Clone)]
+pub enum Change {
+    /// the cells which changed, as they were
+    Diff(Vec<(usize, usize, Cell)>),
+    /// the cells of a generation which changed state, as they were. Every
+    /// other live cell survived, hence only aged by `aged` generations
+    Generation {
+        cells: Vec<(usize, usize, Cell)>,
+        aged: isize,
+    },
+    /// the whole board as it was, when it grew or a diff would not be smaller
+    Snapshot(Board),
+}
+
+/// Entry brings a game back to the way it was before a change
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub change: Change,
+    pub cycles: usize,
+    pub alive_cells: usize,
+}
+
+impl Entry {
+    /// bytes the entry takes up, roughly
+    fn size(&self) -> usize {
+        let cell = size_of::<(usize, usize, Cell)>();
+        size_of::<Entry>()
+            + match &self.change {
+                Change::Diff(cells) | Change::Generation { cells, .. } => cells.len() * cell,
+                Change::Snapshot(board) => board
+                    .state
+                    .iter()
+                    .map(|row| size_of::<Vec<Cell>>() + row.len() * size_of::<Cell>())
+                    .sum(),
+            }
+    }
+}
+
+/// History keeps the latest changes of a game to undo them, and those
+/// undone to redo them until the game changes again. Both together take
+/// up at most max_bytes.
+#[derive(Debug, Clone)]
+pub struct History {
+    undo: VecDeque<Entry>,
+    redo: Vec<Entry>,
+    max_bytes: usize,
+    bytes: usize,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History::new(MAX_BYTES)
+    }
+}
+
+impl History {
+    pub fn new(max_bytes: usize) -> Self {
+        History {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            max_bytes,
+            bytes: 0,
+        }
+    }
+
+    /// a history which remembers nothing, for runs which are never undone
+    pub fn disabled() -> Self {
+        History::new(0)
+    }
+
+    /// whether changes are remembered at all
+    pub fn is_enabled(&self) -> bool {
+        self.max_bytes > 0
+    }
+
+    /// remembers a new change, forgetting the oldest ones beyond the
+    /// limit. Changes undone before can no longer be redone.
+    pub fn record(&mut self, entry: Entry) {
+        self.bytes -= self.redo.drain(..).map(|entry| entry.size()).sum::<usize>();
+        self.push_undo(entry);
+    }
+
+    pub fn pop_undo(&mut self) -> Option<Entry> {
+        let entry = self.undo.pop_back()?;
+        self.bytes -= entry.size();
+        Some(entry)
+    }
+
+    /// remembers a change which was redone
+    pub fn push_undo(&mut self, entry: Entry) {
+        if !self.is_enabled() {
+            return;
+        }
+        self.bytes += entry.size();
+        self.undo.push_back(entry);
+        self.forget();
+    }
+
+    pub fn pop_redo(&mut self) -> Option<Entry> {
+        let entry = self.redo.pop()?;
+        self.bytes -= entry.size();
+        Some(entry)
+    }
+
+    /// remembers a change which was undone
+    pub fn push_redo(&mut self, entry: Entry) {
+        if !self.is_enabled() {
+            return;
+        }
+        self.bytes += entry.size();
+        self.redo.push(entry);
+        self.forget();
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.bytes = 0;
+    }
+
+    /// forgets changes until the history fits, the oldest ones to
+    /// undo first and then the ones furthest away to redo
+    fn forget(&mut self) {
+        while self.bytes > self.max_bytes {
+            let entry = match self.undo.pop_front() {
+                Some(entry) => entry,
+                None if !self.redo.is_empty() => self.redo.remove(0),
+                None => break,
+            };
+            self.bytes -= entry.size();
+        }
+    }
+}
@@ -1,2 +1,13 @@
+pub mod analysis;
+pub mod boundary;
+pub mod engine;
 pub mod game;
+pub mod hashlife;
+pub mod history;
+pub mod palette;
 pub mod pattern;
+pub mod rule;
+pub mod sparse;
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,189 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::conway::game::{Cell, State};
+
+/// Colour is written the way CSS writes HSL, the hue in degrees and the
+/// saturation and lightness in percent
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Colour {
+    pub hue: f64,
+    pub saturation: f64,
+    pub lightness: f64,
+}
+
+impl Colour {
+    pub const fn hsl(hue: f64, saturation: f64, lightness: f64) -> Self {
+        Colour {
+            hue,
+            saturation,
+            lightness,
+        }
+    }
+
+    /// the colour as red, green and blue
+    pub fn rgb(&self) -> [u8; 3] {
+        let s = self.saturation / 100.0;
+        let l = self.lightness / 100.0;
+        let a = s * l.min(1.0 - l);
+        let channel = |n: f64| {
+            let k = (n + self.hue.rem_euclid(360.0) / 30.0) % 12.0;
+            let value = l - a * (k - 3.0).min(9.0 - k).clamp(-1.0, 1.0);
+            (value * 255.0).round() as u8
+        };
+        [channel(0.0), channel(8.0), channel(4.0)]
+    }
+
+    fn from_rgb([r, g, b]: [u8; 3]) -> Self {
+        let (r, g, b) = (r as f64 / 255.0, g as f64 / 255.0, b as f64 / 255.0);
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let lightness = (max + min) / 2.0;
+        let delta = max - min;
+        if delta == 0.0 {
+            return Colour::hsl(0.0, 0.0, lightness * 100.0);
+        }
+
+        let saturation = delta / (1.0 - (2.0 * lightness - 1.0).abs());
+        let hue = if max == r {
+            ((g - b) / delta).rem_euclid(6.0)
+        } else if max == g {
+            (b - r) / delta + 2.0
+        } else {
+            (r - g) / delta + 4.0
+        };
+        Colour::hsl(hue * 60.0, saturation * 100.0, lightness * 100.0)
+    }
+
+    /// the colour step steps of the way to the other one
+    fn towards(&self, other: &Colour, step: usize, steps: usize) -> Colour {
+        // multiplied first, whole steps of whole colours stay whole
+        let between = |from: f64, to: f64| from + (to - from) * step as f64 / steps as f64;
+        Colour::hsl(
+            between(self.hue, other.hue),
+            between(self.saturation, other.saturation),
+            between(self.lightness, other.lightness),
+        )
+    }
+}
+
+/// parses #rrggbb, #rgb or hsl(h, s%, l%)
+impl FromStr for Colour {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let text = s.trim().to_lowercase();
+        let invalid = || {
+            format!(
+                "{} is not a colour such as #1e90ff or hsl(210, 100%, 50%)",
+                s
+            )
+        };
+
+        if let Some(hex) = text.strip_prefix('#') {
+            let hex = match hex.len() {
+                3 => hex.chars().flat_map(|digit| [digit, digit]).collect(),
+                6 => hex.to_string(),
+                _ => return Err(invalid()),
+            };
+            let channel = |i: usize| {
+                hex.get(i..i + 2)
+                    .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+            };
+            return match (channel(0), channel(2), channel(4)) {
+                (Some(r), Some(g), Some(b)) => Ok(Colour::from_rgb([r, g, b])),
+                _ => Err(invalid()),
+            };
+        }
+
+        let values = text
+            .strip_prefix("hsl(")
+            .and_then(|rest| rest.strip_suffix(')'))
+            .ok_or_else(invalid)?;
+        let values: Vec<f64> = values
+            .split(',')
+            .map(|value| value.trim().trim_end_matches('%').parse())
+            .collect::<Result<_, _>>()
+            .map_err(|_| invalid())?;
+        match values[..] {
+            [hue, saturation, lightness]
+                if (0.0..=100.0).contains(&saturation) && (0.0..=100.0).contains(&lightness) =>
+            {
+                Ok(Colour::hsl(hue, saturation, lightness))
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for Colour {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "hsl({}, {}%, {}%)",
+            self.hue, self.saturation, self.lightness
+        )
+    }
+}
+
+/// Palette colours the cells, live ones by how long they have been alive
+/// and the others by their state
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Palette {
+    pub dead: Colour,
+    /// colour of a cell just born
+    pub young: Colour,
+    /// colour of a cell alive for ages generations or longer
+    pub old: Colour,
+    /// generations a cell takes to look old
+    pub ages: usize,
+    /// colour of a cell of a Generations rule which just stopped being
+    /// alive, it fades to dead over the dying states
+    pub dying: Colour,
+    /// colours of the heads and tails of the electrons of Wireworld and
+    /// of its wires
+    pub head: Colour,
+    pub tail: Colour,
+    pub conductor: Colour,
+}
+
+/// the palette of the web UI, cells fade from black through blue to white,
+/// dying ones from red, and Wireworld has its usual blue, red and yellow
+impl Default for Palette {
+    fn default() -> Self {
+        Palette {
+            dead: Colour::hsl(0.0, 0.0, 100.0),
+            young: Colour::hsl(210.0, 100.0, 0.0),
+            old: Colour::hsl(210.0, 100.0, 100.0),
+            ages: 10,
+            dying: Colour::hsl(0.0, 100.0, 50.0),
+            head: Colour::hsl(210.0, 100.0, 50.0),
+            tail: Colour::hsl(0.0, 100.0, 50.0),
+            conductor: Colour::hsl(45.0, 100.0, 50.0),
+        }
+    }
+}
+
+impl Palette {
+    pub fn colour(&self, cell: &Cell) -> Colour {
+        match cell.state {
+            State::Dead => self.dead,
+            State::Alive => self.alive(cell.cycles_alive),
+            State::Dying { step, steps } => {
+                self.dying
+                    .towards(&self.dead, step as usize, steps as usize + 1)
+            }
+            State::Head => self.head,
+            State::Tail => self.tail,
+            State::Conductor => self.conductor,
+        }
+    }
+
+    /// colour of a cell alive for the given generations
+    pub fn alive(&self, age: usize) -> Colour {
+        if self.ages == 0 {
+            return self.old;
+        }
+        self.young.towards(&self.old, age.min(self.ages), self.ages)
+    }
+}
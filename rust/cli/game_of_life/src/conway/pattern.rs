@@ -1,51 +1,437 @@
-use lazy_static::lazy_static;
+use std::path::Path;
 
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::conway::game::State;
+use crate::conway::rule::Rule;
+
+/// live cells as (row, col) relative to the top left corner of the pattern
 pub type Pattern = Vec<(usize, usize)>;
 
-lazy_static! {
-    // Pattern formation:
-    //
-    //
-    // -----x-xx---
-    // ----x------x
-    // ---xx---x--x
-    // xx-x-----xx-
-    // xx-x-----xx-
-    // ---xx---x--x
-    // ----x------x
-    // -----x-xx---
-   pub static ref COPPERHEAD: Pattern = vec![
-        (0, 5),
-        (0, 7),
-        (0, 8),
-        (1, 4),
-        (1, 11),
-        (2, 3),
-        (2, 4),
-        (2, 8),
-        (2, 11),
-        (3, 0),
-        (3, 1),
-        (3, 3),
-        (3, 9),
-        (3, 10),
-        (4, 0),
-        (4, 1),
-        (4, 3),
-        (4, 9),
-        (4, 10),
-        (5, 3),
-        (5, 4),
-        (5, 8),
-        (5, 11),
-        (6, 4),
-        (6, 11),
-        (7, 5),
-        (7, 7),
-        (7, 8)
-    ];
-}
-
-pub fn copperhead() -> Pattern {
-    COPPERHEAD.to_vec()
+/// cells in other states than dead or alive, such as the wires of
+/// Wireworld, as (row, col, state) like a pattern
+pub type States = Vec<(usize, usize, State)>;
+
+/// patterns bundled with the binary, by name
+pub const LIBRARY: [(&str, &str); 12] = [
+    ("acorn", include_str!("../../patterns/acorn.rle")),
+    ("beacon", include_str!("../../patterns/beacon.rle")),
+    ("blinker", include_str!("../../patterns/blinker.rle")),
+    ("copperhead", include_str!("../../patterns/copperhead.rle")),
+    ("diehard", include_str!("../../patterns/diehard.rle")),
+    ("glider", include_str!("../../patterns/glider.rle")),
+    (
+        "gosper_glider_gun",
+        include_str!("../../patterns/gosper_glider_gun.rle"),
+    ),
+    ("lwss", include_str!("../../patterns/lwss.rle")),
+    ("pulsar", include_str!("../../patterns/pulsar.rle")),
+    (
+        "r_pentomino",
+        include_str!("../../patterns/r_pentomino.rle"),
+    ),
+    ("toad", include_str!("../../patterns/toad.rle")),
+    (
+        "wireworld_clock",
+        include_str!("../../patterns/wireworld_clock.rle"),
+    ),
+];
+
+/// RLE lines are kept below this length
+const RLE_LINE_LENGTH: usize = 70;
+
+/// rows and columns a parsed pattern may span at most
+pub const MAX_SIZE: usize = 4096;
+
+/// Loaded is a pattern read from a file along with what the file says about it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Loaded {
+    pub name: Option<String>,
+    /// the rule the pattern is meant to run under
+    pub rule: Option<Rule>,
+    pub cells: Pattern,
+    /// cells in the other states of the rule
+    pub states: States,
+}
+
+impl Loaded {
+    /// the offset which places the pattern in the middle of the board, its
+    /// cells in other states included
+    pub fn centred(&self, rows: usize, cols: usize) -> (usize, usize) {
+        let mut cells = self.cells.clone();
+        cells.extend(self.states.iter().map(|(row, col, _)| (*row, *col)));
+        centred(&cells, rows, cols)
+    }
+}
+
+/// returns the pattern of the bundled library
+pub fn library(name: &str) -> Option<Loaded> {
+    LIBRARY
+        .iter()
+        .find(|(entry, _)| *entry == name)
+        .map(|(_, rle)| parse_rle(rle).expect("bundled patterns are valid RLE"))
+}
+
+/// loads a pattern of the bundled library by name or else from a .rle or
+/// .cells file. Files of other extensions are told apart by their content.
+pub fn load(name_or_path: &str) -> Result<Loaded, String> {
+    if let Some(loaded) = library(name_or_path) {
+        return Ok(loaded);
+    }
+
+    let path = Path::new(name_or_path);
+    let text = std::fs::read_to_string(path).map_err(|err| {
+        format!(
+            "{} is neither a bundled pattern nor a readable file: {}",
+            name_or_path, err
+        )
+    })?;
+
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("rle") => parse_rle(&text),
+        Some("cells") => parse_cells(&text),
+        _ => parse(&text),
+    }
+}
+
+/// parses RLE or plaintext, whichever the text is written in
+pub fn parse(text: &str) -> Result<Loaded, String> {
+    parse_within(text, MAX_SIZE)
+}
+
+/// parses like parse but refuses patterns spanning more
+/// than max_size rows or columns
+pub fn parse_within(text: &str, max_size: usize) -> Result<Loaded, String> {
+    let is_cells = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('!'))
+        .all(|line| line.chars().all(|c| matches!(c, '.' | 'O' | '*')));
+
+    if is_cells {
+        cells_within(text, max_size)
+    } else {
+        rle_within(text, max_size)
+    }
+}
+
+/// parses the run length encoding of Golly and LifeWiki: #-lines with
+/// the name and comments, a header such as x = 3, y = 3, rule = B3/S23
+/// and runs of b (dead), o (alive) and $ (end of row) ending with !.
+/// Rules of more than two states write . for dead and A to X for the
+/// states 1 to 24, prefixed by p to y for the higher ones.
+pub fn parse_rle(text: &str) -> Result<Loaded, String> {
+    rle_within(text, MAX_SIZE)
+}
+
+fn rle_within(text: &str, max_size: usize) -> Result<Loaded, String> {
+    let too_large = || format!("pattern spans more than {} rows or columns", max_size);
+    let mut name = None;
+    let mut rule = None;
+    let mut cells = Vec::new();
+    let mut states = Vec::new();
+    let (mut row, mut col) = (0, 0);
+    let mut count = String::new();
+    let mut prefix = None;
+
+    'lines: for line in text.lines().map(str::trim) {
+        if let Some(comment) = line.strip_prefix("#N") {
+            name = Some(comment.trim().to_string());
+            continue;
+        }
+        if line.starts_with('#') || line.is_empty() {
+            continue;
+        }
+        if line.starts_with('x') {
+            rule = parse_rle_header(line)?;
+            continue;
+        }
+
+        for c in line.chars() {
+            if c.is_ascii_digit() {
+                count.push(c);
+                continue;
+            }
+            if ('p'..='y').contains(&c) && prefix.is_none() {
+                prefix = Some(c);
+                continue;
+            }
+
+            let run: usize = if count.is_empty() {
+                1
+            } else {
+                count
+                    .parse()
+                    .ok()
+                    .filter(|run| *run <= max_size)
+                    .ok_or_else(|| format!("run of {} cells is too long", count))?
+            };
+            count.clear();
+
+            // the end of the run, cells are only placed within max_size
+            let end = col + run;
+            let placed = || {
+                if row < max_size && end <= max_size {
+                    Ok(col..end)
+                } else {
+                    Err(too_large())
+                }
+            };
+
+            match (prefix.take(), c) {
+                (None, 'b' | '.') => col = end.min(max_size),
+                (None, 'o') => {
+                    cells.extend(placed()?.map(|col| (row, col)));
+                    col = end;
+                }
+                (prefix, 'A'..='X') => {
+                    let rule: Rule = rule.unwrap_or_default();
+                    let high = prefix.map_or(0, |p| 24 * (p as u32 - 'p' as u32 + 1));
+                    let number = high + c as u32 - 'A' as u32 + 1;
+                    let state = u8::try_from(number)
+                        .ok()
+                        .and_then(|number| rule.state(number))
+                        .ok_or_else(|| format!("rule {} has no state {}", rule, number))?;
+                    if state.is_alive() {
+                        cells.extend(placed()?.map(|col| (row, col)));
+                    } else {
+                        states.extend(placed()?.map(|col| (row, col, state)));
+                    }
+                    col = end;
+                }
+                (Some(p), c) => return Err(format!("unexpected \"{}{}\" in RLE", p, c)),
+                (None, '$') => {
+                    row = (row + run).min(max_size);
+                    col = 0;
+                }
+                (None, '!') => break 'lines,
+                (None, c) if c.is_whitespace() => {}
+                (None, c) => return Err(format!("unexpected \"{}\" in RLE", c)),
+            }
+        }
+    }
+
+    Ok(Loaded {
+        name,
+        rule,
+        cells,
+        states,
+    })
+}
+
+/// returns the rule of the header, the size is implied by the cells
+fn parse_rle_header(line: &str) -> Result<Option<Rule>, String> {
+    for field in line.split(',') {
+        let (key, value) = field
+            .split_once('=')
+            .ok_or_else(|| format!("invalid RLE header \"{}\"", line))?;
+        if key.trim() == "rule" {
+            return value.trim().parse().map(Some);
+        }
+    }
+    Ok(None)
+}
+
+/// parses plaintext: !-lines with the name and comments followed by a
+/// line per row of . (dead) and O (alive)
+pub fn parse_cells(text: &str) -> Result<Loaded, String> {
+    cells_within(text, MAX_SIZE)
+}
+
+fn cells_within(text: &str, max_size: usize) -> Result<Loaded, String> {
+    let mut name = None;
+    let mut cells = Vec::new();
+
+    let mut row = 0;
+    for line in text.lines() {
+        if let Some(comment) = line.strip_prefix('!') {
+            if let Some(value) = comment.strip_prefix("Name:") {
+                name = Some(value.trim().to_string());
+            }
+            continue;
+        }
+
+        for (col, c) in line.trim_end().chars().enumerate() {
+            match c {
+                '.' => {}
+                'O' | '*' if row >= max_size || col >= max_size => {
+                    return Err(format!(
+                        "pattern spans more than {} rows or columns",
+                        max_size
+                    ))
+                }
+                'O' | '*' => cells.push((row, col)),
+                c => return Err(format!("unexpected \"{}\" in plaintext", c)),
+            }
+        }
+        row += 1;
+    }
+
+    Ok(Loaded {
+        name,
+        rule: None,
+        cells,
+        states: Vec::new(),
+    })
+}
+
+/// rows and cols the pattern spans
+pub fn size(pattern: &Pattern) -> (usize, usize) {
+    pattern.iter().fold((0, 0), |(rows, cols), (row, col)| {
+        (
+            rows.max(row.saturating_add(1)),
+            cols.max(col.saturating_add(1)),
+        )
+    })
+}
+
+/// the offset which places the pattern in the middle of the board
+pub fn centred(pattern: &Pattern, rows: usize, cols: usize) -> (usize, usize) {
+    let (height, width) = size(pattern);
+    (
+        rows.saturating_sub(height) / 2,
+        cols.saturating_sub(width) / 2,
+    )
+}
+
+/// name standing for a random soup in place of a pattern
+pub const SOUP: &str = "soup";
+
+/// chance of a cell of a soup to be alive unless told otherwise
+pub const DEFAULT_DENSITY: f64 = 0.5;
+
+/// Soup describes a random pattern
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Soup {
+    /// chance of a cell to be alive
+    pub density: f64,
+    /// the same seed always gives the same soup, a random one if None
+    pub seed: Option<u64>,
+    /// rows and columns the soup covers, the whole board if None
+    pub region: Option<(usize, usize)>,
+}
+
+impl Soup {
+    /// the cells of the soup on a board of rows x cols, along with the
+    /// seed they were made with
+    pub fn generate(&self, rows: usize, cols: usize) -> (u64, Pattern) {
+        let seed = self.seed.unwrap_or_else(rand::random);
+        let (rows, cols) = match self.region {
+            Some((height, width)) => (height.min(rows), width.min(cols)),
+            None => (rows, cols),
+        };
+        (seed, soup(rows, cols, self.density, seed))
+    }
+}
+
+/// parses the region of a soup written as ROWSxCOLS
+pub fn parse_region(text: &str) -> Result<(usize, usize), String> {
+    let invalid = || format!("{} is not a region such as 16x16", text);
+    let (rows, cols) = text.split_once(['x', 'X']).ok_or_else(invalid)?;
+    let rows = rows.trim().parse().map_err(|_| invalid())?;
+    let cols = cols.trim().parse().map_err(|_| invalid())?;
+    Ok((rows, cols))
+}
+
+/// a random soup of rows x cols where each cell is alive with the given
+/// probability, the same seed always gives the same soup
+pub fn soup(rows: usize, cols: usize, density: f64, seed: u64) -> Pattern {
+    let mut rng = StdRng::seed_from_u64(seed);
+    // NaN is no chance at all
+    let density = if density.is_nan() {
+        0.0
+    } else {
+        density.clamp(0.0, 1.0)
+    };
+
+    let mut cells = Vec::new();
+    for row in 0..rows {
+        for col in 0..cols {
+            if rng.gen_bool(density) {
+                cells.push((row, col));
+            }
+        }
+    }
+    cells
+}
+
+/// writes the live cells as RLE, along with the cells in other states
+/// under rules of more than two, moved to the top left corner
+pub fn to_rle(pattern: &Pattern, states: &States, rule: Rule) -> String {
+    let mut numbered: Vec<(usize, usize, u8)> = pattern
+        .iter()
+        .map(|(row, col)| (*row, *col, 1))
+        .chain(
+            states
+                .iter()
+                .map(|(row, col, state)| (*row, *col, rule.number(*state))),
+        )
+        .filter(|(_, _, number)| *number != 0)
+        .collect();
+    let top = numbered.iter().map(|(row, _, _)| *row).min().unwrap_or(0);
+    let left = numbered.iter().map(|(_, col, _)| *col).min().unwrap_or(0);
+    for (row, col, _) in &mut numbered {
+        *row -= top;
+        *col -= left;
+    }
+    numbered.sort();
+    numbered.dedup_by_key(|(row, col, _)| (*row, *col));
+
+    let cells: Pattern = numbered.iter().map(|(row, col, _)| (*row, *col)).collect();
+    let (rows, cols) = size(&cells);
+    let mut out = format!("x = {}, y = {}, rule = {}\n", cols, rows, rule);
+
+    let multi = rule.states() > 2;
+    let dead = if multi { "." } else { "b" };
+    let tag = |number: u8| -> String {
+        let letter = |n: u8| char::from(b'A' + n);
+        match number {
+            _ if !multi => "o".to_string(),
+            1..=24 => letter(number - 1).to_string(),
+            _ => format!(
+                "{}{}",
+                char::from(b'p' + (number - 25) / 24),
+                letter((number - 25) % 24)
+            ),
+        }
+    };
+
+    // runs of the same tag, the dead cells ending a row are left out
+    let mut runs: Vec<(usize, String)> = Vec::new();
+    let mut push = |run: usize, tag: String| match runs.last_mut() {
+        Some((count, last)) if *last == tag => *count += run,
+        _ => runs.push((run, tag)),
+    };
+    let (mut row, mut col) = (0, 0);
+    for (r, c, number) in numbered {
+        if r > row {
+            push(r - row, "$".to_string());
+            row = r;
+            col = 0;
+        }
+        if c > col {
+            push(c - col, dead.to_string());
+        }
+        push(1, tag(number));
+        col = c + 1;
+    }
+    push(1, "!".to_string());
+
+    let mut line = String::new();
+    for (run, tag) in runs {
+        let item = if run == 1 {
+            tag
+        } else {
+            format!("{}{}", run, tag)
+        };
+        if line.len() + item.len() > RLE_LINE_LENGTH {
+            out.push_str(&line);
+            out.push('\n');
+            line.clear();
+        }
+        line.push_str(&item);
+    }
+    out.push_str(&line);
+    out.push('\n');
+    out
 }
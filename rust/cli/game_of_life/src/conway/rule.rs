@@ -0,0 +1,267 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::conway::game::State;
+
+/// Rule decides the fate of a cell by its state and the number of its
+/// live neighbours
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rule {
+    /// Life-like rules, written as B3/S23: a dead cell with 3 neighbours
+    /// is born, a live cell with 2 or 3 neighbours survives. With more than
+    /// two states, written as B2/S/C3, a live cell which does not survive
+    /// is dying for states - 2 generations, in which it cannot be born.
+    Generations {
+        /// bit n is set if a dead cell with n neighbours is born
+        birth: u16,
+        /// bit n is set if a live cell with n neighbours survives
+        survival: u16,
+        /// dead and alive included
+        states: u8,
+    },
+    /// electrons run along wires: a head turns into a tail, a tail back
+    /// into a conductor, and a conductor next to 1 or 2 heads into a head
+    Wireworld,
+}
+
+/// well known rules which may be given by name
+const NAMED: [(&str, &str); 9] = [
+    ("life", "B3/S23"),
+    ("conway", "B3/S23"),
+    ("highlife", "B36/S23"),
+    ("seeds", "B2/S"),
+    ("daynight", "B3678/S34678"),
+    ("day&night", "B3678/S34678"),
+    ("lifewithoutdeath", "B3/S012345678"),
+    ("briansbrain", "B2/S/C3"),
+    ("starwars", "B2/S345/C4"),
+];
+
+impl Rule {
+    pub const CONWAY: Rule = Rule::Generations {
+        birth: 1 << 3,
+        survival: 1 << 2 | 1 << 3,
+        states: 2,
+    };
+
+    /// the state of the cell in the next generation. Cells in a state the
+    /// rule does not know, left from another rule, pass into one it does.
+    pub fn next_state(&self, state: State, neighbours: usize) -> State {
+        match *self {
+            Rule::Generations {
+                birth,
+                survival,
+                states,
+            } => match state {
+                State::Alive | State::Head if survival & (1 << neighbours) != 0 => State::Alive,
+                State::Alive | State::Head => dying(0, states),
+                State::Dying { step, .. } => dying(step + 1, states),
+                State::Dead | State::Tail | State::Conductor if birth & (1 << neighbours) != 0 => {
+                    State::Alive
+                }
+                State::Dead | State::Tail | State::Conductor => State::Dead,
+            },
+            Rule::Wireworld => match state {
+                State::Dead => State::Dead,
+                State::Alive | State::Head => State::Tail,
+                State::Dying { .. } | State::Tail => State::Conductor,
+                State::Conductor if (1..=2).contains(&neighbours) => State::Head,
+                State::Conductor => State::Conductor,
+            },
+        }
+    }
+
+    /// whether the cell is alive in the next generation
+    pub fn next(&self, alive: bool, neighbours: usize) -> bool {
+        let state = if alive { self.alive() } else { State::Dead };
+        self.next_state(state, neighbours) == self.alive()
+    }
+
+    /// the state of a live cell, the head of an electron for Wireworld
+    pub fn alive(&self) -> State {
+        match self {
+            Rule::Generations { .. } => State::Alive,
+            Rule::Wireworld => State::Head,
+        }
+    }
+
+    /// number of states a cell may be in, dead and alive included
+    pub fn states(&self) -> u8 {
+        match self {
+            Rule::Generations { states, .. } => *states,
+            Rule::Wireworld => 4,
+        }
+    }
+
+    /// the state numbered as Golly does, 0 being dead and 1 alive, None if
+    /// the rule has no such state
+    pub fn state(&self, number: u8) -> Option<State> {
+        match (self, number) {
+            (_, 0) => Some(State::Dead),
+            (_, 1) => Some(self.alive()),
+            (Rule::Generations { states, .. }, number) if number < *states => Some(State::Dying {
+                step: number - 2,
+                steps: states - 2,
+            }),
+            (Rule::Wireworld, 2) => Some(State::Tail),
+            (Rule::Wireworld, 3) => Some(State::Conductor),
+            _ => None,
+        }
+    }
+
+    /// the number of the state as Golly numbers them, states the rule does
+    /// not know are dead
+    pub fn number(&self, state: State) -> u8 {
+        match (self, state) {
+            (_, State::Alive | State::Head) => 1,
+            (Rule::Generations { states, .. }, State::Dying { step, .. }) if step + 2 < *states => {
+                step + 2
+            }
+            (Rule::Wireworld, State::Tail) => 2,
+            (Rule::Wireworld, State::Conductor) => 3,
+            _ => 0,
+        }
+    }
+
+    /// the state a cell is flipped to by hand: dead cells are born and the
+    /// others die, Wireworld goes through conductor, head and tail
+    pub fn flipped(&self, state: State) -> State {
+        match (self, state) {
+            (Rule::Generations { .. }, State::Dead) => State::Alive,
+            (Rule::Generations { .. }, _) => State::Dead,
+            (Rule::Wireworld, State::Dead) => State::Conductor,
+            (Rule::Wireworld, State::Conductor) => State::Head,
+            (Rule::Wireworld, State::Head) => State::Tail,
+            (Rule::Wireworld, _) => State::Dead,
+        }
+    }
+
+    /// whether dead cells without any live neighbour are born, which
+    /// fills an unbounded plane in a single generation
+    pub fn births_from_nothing(&self) -> bool {
+        match self {
+            Rule::Generations { birth, .. } => birth & 1 != 0,
+            Rule::Wireworld => false,
+        }
+    }
+}
+
+/// the dying state step generations after a cell stopped being alive,
+/// dead once the states run out
+fn dying(step: u8, states: u8) -> State {
+    let steps = states.saturating_sub(2);
+    if step < steps {
+        State::Dying { step, steps }
+    } else {
+        State::Dead
+    }
+}
+
+impl Default for Rule {
+    fn default() -> Self {
+        Rule::CONWAY
+    }
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    /// accepts B3/S23, S23/B3, 23/3 (in any case), Generations rules such
+    /// as B2/S/C3 or /2/3, and the names of well known rules such as
+    /// highlife, seeds, brian's brain or wireworld
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let lower = s.to_lowercase().replace([' ', '-', '_', '\''], "");
+        if lower == "wireworld" {
+            return Ok(Rule::Wireworld);
+        }
+        if let Some((_, rule)) = NAMED.iter().find(|(name, _)| *name == lower) {
+            return rule.parse();
+        }
+
+        let invalid = || {
+            format!(
+                "invalid rule \"{}\", expected e.g. B3/S23, B2/S/C3 or wireworld",
+                s
+            )
+        };
+        let parts: Vec<&str> = lower.split('/').collect();
+        if !(2..=3).contains(&parts.len()) {
+            return Err(invalid());
+        }
+
+        // the older notation 23/3/3 lists the survivals first, without letters
+        let parts: Vec<String> = if parts.iter().all(|part| !part.starts_with(['b', 's', 'c'])) {
+            parts
+                .iter()
+                .zip(["s", "b", "c"])
+                .map(|(part, letter)| format!("{}{}", letter, part))
+                .collect()
+        } else {
+            parts.iter().map(|part| part.to_string()).collect()
+        };
+
+        let mut birth = None;
+        let mut survival = None;
+        let mut states = None;
+        for part in &parts {
+            let (target, digits) = match part.split_at(part.len().min(1)) {
+                ("b", digits) => (&mut birth, digits),
+                ("s", digits) => (&mut survival, digits),
+                ("c", digits) if states.is_none() => {
+                    states = Some(
+                        digits
+                            .parse::<u8>()
+                            .ok()
+                            .filter(|states| *states >= 2)
+                            .ok_or_else(invalid)?,
+                    );
+                    continue;
+                }
+                _ => return Err(invalid()),
+            };
+            if target.is_some() {
+                return Err(invalid());
+            }
+
+            let mut set = 0u16;
+            for digit in digits.chars() {
+                match digit.to_digit(10) {
+                    Some(n) if n <= 8 => set |= 1 << n,
+                    _ => return Err(invalid()),
+                }
+            }
+            *target = Some(set);
+        }
+
+        Ok(Rule::Generations {
+            birth: birth.ok_or_else(invalid)?,
+            survival: survival.ok_or_else(invalid)?,
+            states: states.unwrap_or(2),
+        })
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let digits = |set: u16| -> String {
+            (0..=8)
+                .filter(|n| set & (1 << n) != 0)
+                .map(|n: u16| n.to_string())
+                .collect()
+        };
+        match *self {
+            Rule::Generations {
+                birth,
+                survival,
+                states: 2,
+            } => write!(f, "B{}/S{}", digits(birth), digits(survival)),
+            Rule::Generations {
+                birth,
+                survival,
+                states,
+            } => write!(f, "B{}/S{}/C{}", digits(birth), digits(survival), states),
+            Rule::Wireworld => f.write_str("WireWorld"),
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::conway::engine::Engine;
+use crate::conway::rule::Rule;
+
+/// Sparse keeps only the live cells on an unbounded plane. A
+/// generation costs time in the number of live cells rather than
+/// the size of the board. Rules with B0 are not supported as
+/// they would fill the plane.
+#[derive(Debug, Default, Clone)]
+pub struct Sparse {
+    alive: HashSet<(i64, i64)>,
+    generation: u64,
+    rule: Rule,
+}
+
+impl Sparse {
+    pub fn with_rule(rule: Rule) -> Self {
+        Sparse {
+            rule,
+            ..Sparse::default()
+        }
+    }
+}
+
+impl Engine for Sparse {
+    fn step(&mut self) {
+        // only live cells and cells next to them can be alive in the
+        // next generation. Live cells are counted even without live
+        // neighbours, else rules surviving on S0 would lose them
+        let mut neighbours: HashMap<(i64, i64), usize> =
+            HashMap::with_capacity(self.alive.len() * 9);
+        for &cell in &self.alive {
+            neighbours.entry(cell).or_default();
+        }
+        for &(row, col) in &self.alive {
+            for dr in -1..=1 {
+                for dc in -1..=1 {
+                    if dr != 0 || dc != 0 {
+                        *neighbours.entry((row + dr, col + dc)).or_default() += 1;
+                    }
+                }
+            }
+        }
+
+        self.alive = neighbours
+            .into_iter()
+            .filter(|(cell, count)| self.rule.next(self.alive.contains(cell), *count))
+            .map(|(cell, _)| cell)
+            .collect();
+        self.generation += 1;
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn population(&self) -> u64 {
+        self.alive.len() as u64
+    }
+
+    fn is_alive(&self, row: i64, col: i64) -> bool {
+        self.alive.contains(&(row, col))
+    }
+
+    fn cells(&self) -> Vec<(i64, i64)> {
+        self.alive.iter().copied().collect()
+    }
+
+    fn set(&mut self, row: i64, col: i64, alive: bool) {
+        if alive {
+            self.alive.insert((row, col));
+        } else {
+            self.alive.remove(&(row, col));
+        }
+    }
+}
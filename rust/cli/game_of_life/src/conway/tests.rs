@@ -0,0 +1,1035 @@
+use clap::ValueEnum;
+
+use crate::conway::analysis::{self, Outcome};
+use crate::conway::boundary::Boundary;
+use crate::conway::engine::{self, Engine, Kind};
+use crate::conway::game::{Cell, Game, State};
+use crate::conway::hashlife::Hashlife;
+use crate::conway::history::History;
+use crate::conway::palette::{Colour, Palette};
+use crate::conway::pattern;
+use crate::conway::rule::Rule;
+use crate::export;
+
+/// side of the window the cells are compared in, also the size of the dense board
+const SIZE: usize = 64;
+/// where patterns are placed, away from the edges of the dense board
+const ORIGIN: i64 = 24;
+
+const BLINKER: &[(i64, i64)] = &[(0, 0), (0, 1), (0, 2)];
+const TOAD: &[(i64, i64)] = &[(0, 1), (0, 2), (0, 3), (1, 0), (1, 1), (1, 2)];
+const BEACON: &[(i64, i64)] = &[(0, 0), (0, 1), (1, 0), (2, 3), (3, 2), (3, 3)];
+const GLIDER: &[(i64, i64)] = &[(0, 1), (1, 2), (2, 0), (2, 1), (2, 2)];
+const LWSS: &[(i64, i64)] = &[
+    (0, 1),
+    (0, 4),
+    (1, 0),
+    (2, 0),
+    (2, 4),
+    (3, 0),
+    (3, 1),
+    (3, 2),
+    (3, 3),
+];
+
+fn pulsar() -> Vec<(i64, i64)> {
+    let mut cells = Vec::new();
+    for line in [0, 5, 7, 12] {
+        for other in [2, 3, 4, 8, 9, 10] {
+            cells.push((line, other));
+            cells.push((other, line));
+        }
+    }
+    cells
+}
+
+/// one universe of every engine holding the pattern
+fn universes(pattern: &[(i64, i64)], rule: Rule) -> Vec<(Kind, Box<dyn Engine>)> {
+    Kind::value_variants()
+        .iter()
+        .map(|kind| {
+            let mut universe = engine::new(*kind, SIZE, SIZE, rule, None).unwrap();
+            for (row, col) in pattern {
+                universe.set(ORIGIN + row, ORIGIN + col, true);
+            }
+            (*kind, universe)
+        })
+        .collect()
+}
+
+/// live cells of the window, sorted
+fn cells(universe: &dyn Engine) -> Vec<(i64, i64)> {
+    let mut cells = Vec::new();
+    for row in 0..SIZE as i64 {
+        for col in 0..SIZE as i64 {
+            if universe.is_alive(row, col) {
+                cells.push((row, col));
+            }
+        }
+    }
+    cells
+}
+
+fn shifted(pattern: &[(i64, i64)], rows: i64, cols: i64) -> Vec<(i64, i64)> {
+    let mut cells: Vec<_> = pattern
+        .iter()
+        .map(|(row, col)| (ORIGIN + row + rows, ORIGIN + col + cols))
+        .collect();
+    cells.sort();
+    cells
+}
+
+/// the pattern returns to itself moved by (rows, cols) after exactly period generations
+fn assert_moves(pattern: &[(i64, i64)], period: u64, rows: i64, cols: i64) {
+    let start = shifted(pattern, 0, 0);
+    let end = shifted(pattern, rows, cols);
+
+    for (kind, mut universe) in universes(pattern, Rule::CONWAY) {
+        for generation in 1..period {
+            universe.step();
+            assert_ne!(
+                cells(universe.as_ref()),
+                start,
+                "{:?} repeats after {} generations",
+                kind,
+                generation
+            );
+        }
+        universe.step();
+        assert_eq!(cells(universe.as_ref()), end, "{:?}", kind);
+        assert_eq!(universe.generation(), period, "{:?}", kind);
+        assert_eq!(universe.population(), pattern.len() as u64, "{:?}", kind);
+    }
+}
+
+#[test]
+fn blinker_has_period_two() {
+    assert_moves(BLINKER, 2, 0, 0);
+}
+
+#[test]
+fn toad_has_period_two() {
+    assert_moves(TOAD, 2, 0, 0);
+}
+
+#[test]
+fn beacon_has_period_two() {
+    assert_moves(BEACON, 2, 0, 0);
+}
+
+#[test]
+fn pulsar_has_period_three() {
+    assert_moves(&pulsar(), 3, 0, 0);
+}
+
+#[test]
+fn glider_moves_diagonally() {
+    assert_moves(GLIDER, 4, 1, 1);
+}
+
+#[test]
+fn lightweight_spaceship_moves_orthogonally() {
+    assert_moves(LWSS, 4, 0, -2);
+}
+
+#[test]
+fn engines_agree_over_many_generations() {
+    let mut universes = universes(&pulsar(), Rule::CONWAY);
+    for (_, universe) in &mut universes {
+        for (row, col) in GLIDER {
+            universe.set(row + 4, col + 4, true);
+        }
+        universe.step_many(16);
+    }
+
+    let (_, dense) = &universes[0];
+    for (kind, universe) in &universes[1..] {
+        assert_eq!(
+            cells(universe.as_ref()),
+            cells(dense.as_ref()),
+            "{:?}",
+            kind
+        );
+    }
+}
+
+#[test]
+fn hashlife_agrees_with_an_infinite_dense_board() {
+    let soup = pattern::soup(16, 16, 0.4, 7);
+    let mut dense = engine::new(
+        Kind::Dense,
+        SIZE,
+        SIZE,
+        Rule::CONWAY,
+        Some(Boundary::Infinite),
+    )
+    .unwrap();
+    let mut hashlife = engine::new(Kind::Hashlife, SIZE, SIZE, Rule::CONWAY, None).unwrap();
+    for (row, col) in &soup {
+        dense.set(ORIGIN + *row as i64, ORIGIN + *col as i64, true);
+        hashlife.set(ORIGIN + *row as i64, ORIGIN + *col as i64, true);
+    }
+
+    for generation in 1..=200 {
+        dense.step();
+        hashlife.step();
+        let (mut expected, mut actual) = (dense.cells(), hashlife.cells());
+        expected.sort_unstable();
+        actual.sort_unstable();
+        assert_eq!(actual, expected, "generation {}", generation);
+    }
+}
+
+#[test]
+fn hashlife_skips_ahead_to_where_single_steps_get() {
+    let (mut single, mut many) = (Hashlife::new(), Hashlife::new());
+    for (row, col) in GLIDER.iter().chain(&pulsar()) {
+        single.set(*row, *col - 20, true);
+        many.set(*row, *col - 20, true);
+    }
+
+    for _ in 0..100 {
+        single.step();
+    }
+    many.step_many(100);
+    let (mut expected, mut actual) = (single.cells(), many.cells());
+    expected.sort_unstable();
+    actual.sort_unstable();
+    assert_eq!(actual, expected);
+    assert_eq!(many.generation(), 100);
+
+    // a glider moves a cell diagonally every four generations
+    let mut glider = Hashlife::new();
+    for (row, col) in GLIDER {
+        glider.set(*row, *col, true);
+    }
+    glider.step_many(1 << 40);
+    let mut cells = glider.cells();
+    cells.sort_unstable();
+    let moved: Vec<_> = GLIDER
+        .iter()
+        .map(|(row, col)| (row + (1 << 38), col + (1 << 38)))
+        .collect();
+    assert_eq!(cells, moved);
+
+    // the edge of the plane is reached without overflowing
+    glider.step_many(u64::MAX);
+    assert_eq!(glider.generation(), u64::MAX);
+}
+
+#[test]
+fn engines_agree_on_survival_without_neighbours() {
+    let rule: Rule = "lifewithoutdeath".parse().unwrap();
+    let mut pattern = GLIDER.to_vec();
+    // a lone cell only survives through S0
+    pattern.push((10, 10));
+
+    let mut universes = universes(&pattern, rule);
+    for (_, universe) in &mut universes {
+        universe.step_many(8);
+    }
+
+    let (_, dense) = &universes[0];
+    assert!(dense.is_alive(ORIGIN + 10, ORIGIN + 10));
+    for (kind, universe) in &universes[1..] {
+        assert_eq!(
+            cells(universe.as_ref()),
+            cells(dense.as_ref()),
+            "{:?}",
+            kind
+        );
+    }
+}
+
+#[test]
+fn seeds_lets_every_live_cell_die() {
+    let rule: Rule = "seeds".parse().unwrap();
+    let domino = [(0, 0), (0, 1)];
+
+    for (kind, mut universe) in universes(&domino, rule) {
+        universe.step();
+        assert_eq!(
+            cells(universe.as_ref()),
+            shifted(&[(-1, 0), (-1, 1), (1, 0), (1, 1)], 0, 0),
+            "{:?}",
+            kind
+        );
+    }
+}
+
+#[test]
+fn rules_parse_and_display() {
+    for (text, expected) in [
+        ("B3/S23", "B3/S23"),
+        ("b36/s23", "B36/S23"),
+        ("S23/B3", "B3/S23"),
+        ("B2/S", "B2/S"),
+        ("HighLife", "B36/S23"),
+        ("Day & Night", "B3678/S34678"),
+        ("B2/S/C3", "B2/S/C3"),
+        ("/2/3", "B2/S/C3"),
+        ("345/2/4", "B2/S345/C4"),
+        ("Brian's Brain", "B2/S/C3"),
+        ("B3/S23/C2", "B3/S23"),
+        ("WireWorld", "WireWorld"),
+    ] {
+        let rule: Rule = text.parse().unwrap();
+        assert_eq!(rule.to_string(), expected, "{}", text);
+    }
+
+    for invalid in [
+        "",
+        "B3",
+        "B3/S9",
+        "B3/B3",
+        "X3/S23",
+        "B3/S23/S1",
+        "B2/S/C1",
+        "B2/S/C256",
+        "B2/S/C3/C4",
+        "B2/S/C3x",
+    ] {
+        assert!(invalid.parse::<Rule>().is_err(), "{}", invalid);
+    }
+}
+
+#[test]
+fn highlife_differs_from_life_on_six_neighbours() {
+    let highlife: Rule = "B36/S23".parse().unwrap();
+
+    assert!(highlife.next(false, 6));
+    assert!(!Rule::CONWAY.next(false, 6));
+    for neighbours in 0..=8 {
+        assert_eq!(
+            highlife.next(true, neighbours),
+            Rule::CONWAY.next(true, neighbours)
+        );
+    }
+}
+
+#[test]
+fn unbounded_engines_refuse_births_from_nothing() {
+    let rule: Rule = "B0/S8".parse().unwrap();
+
+    assert!(engine::new(Kind::Dense, SIZE, SIZE, rule, None).is_ok());
+    assert!(engine::new(Kind::Sparse, SIZE, SIZE, rule, None).is_err());
+    assert!(engine::new(Kind::Hashlife, SIZE, SIZE, rule, None).is_err());
+}
+
+#[test]
+fn unbounded_engines_refuse_more_than_two_states() {
+    for rule in ["B2/S/C3", "wireworld"] {
+        let rule: Rule = rule.parse().unwrap();
+
+        assert!(engine::new(Kind::Dense, SIZE, SIZE, rule, None).is_ok());
+        assert!(engine::new(Kind::Sparse, SIZE, SIZE, rule, None).is_err());
+        assert!(engine::new(Kind::Hashlife, SIZE, SIZE, rule, None).is_err());
+    }
+}
+
+#[test]
+fn empty_boards_are_refused() {
+    assert!(engine::new(Kind::Dense, 0, SIZE, Rule::CONWAY, None).is_err());
+    assert!(engine::new(Kind::Dense, SIZE, 0, Rule::CONWAY, None).is_err());
+    assert!(engine::new(Kind::Sparse, 0, 0, Rule::CONWAY, None).is_ok());
+
+    // nowhere to put the pattern, but no panic either
+    let mut game = Game::empty(0, 0);
+    game.load(&vec![(0, 1), (1, 2)], 0, 0);
+    assert_eq!(game.alive_cells, 0);
+}
+
+/// the bundled pattern placed in a universe of every engine
+fn bundled(name: &str) -> Vec<(Kind, Box<dyn Engine>)> {
+    let loaded = pattern::library(name).unwrap();
+    let cells: Vec<(i64, i64)> = loaded
+        .cells
+        .iter()
+        .map(|(row, col)| (*row as i64, *col as i64))
+        .collect();
+    universes(&cells, loaded.rule.unwrap())
+}
+
+#[test]
+fn rle_and_plaintext_read_the_same_pattern() {
+    let rle = pattern::parse("#N Glider\nx = 3, y = 3, rule = B3/S23\nbob$2bo$3o!").unwrap();
+    let cells = pattern::parse("!Name: Glider\n.O.\n..O\nOOO\n").unwrap();
+
+    assert_eq!(rle.name.as_deref(), Some("Glider"));
+    assert_eq!(rle.rule, Some(Rule::CONWAY));
+    assert_eq!(cells.name.as_deref(), Some("Glider"));
+    assert_eq!(rle.cells, cells.cells);
+    assert_eq!(rle.cells, vec![(0, 1), (1, 2), (2, 0), (2, 1), (2, 2)]);
+}
+
+#[test]
+fn rle_round_trips() {
+    for (name, _) in pattern::LIBRARY {
+        let loaded = pattern::library(name).unwrap();
+        let rle = pattern::to_rle(&loaded.cells, &loaded.states, loaded.rule.unwrap());
+
+        assert!(rle.lines().all(|line| line.len() <= 70), "{}", name);
+        let mut expected = loaded.clone();
+        expected.cells.sort();
+        expected.states.sort_by_key(|(row, col, _)| (*row, *col));
+        let parsed = pattern::parse_rle(&rle).unwrap();
+        assert_eq!(parsed.cells, expected.cells, "{}", name);
+        assert_eq!(parsed.states, expected.states, "{}", name);
+    }
+
+    assert_eq!(
+        pattern::to_rle(
+            &vec![(5, 7), (5, 8), (7, 7)],
+            &Vec::new(),
+            "B36/S23".parse().unwrap()
+        ),
+        "x = 2, y = 3, rule = B36/S23\n2o2$o!\n"
+    );
+}
+
+#[test]
+fn invalid_patterns_are_refused() {
+    assert!(pattern::parse("x = 3, y = 3\nbob$2bq!").is_err());
+    assert!(pattern::parse("x = 3, y = 3, rule = B9/S23\n3o!").is_err());
+    assert!(pattern::parse_cells(".O.\n.X.").is_err());
+    assert!(pattern::load("no such pattern").is_err());
+}
+
+#[test]
+fn oversized_patterns_are_refused() {
+    for rle in [
+        "18446744073709551615b2o!",
+        "18446744073709551615$o!",
+        "99999999999999999999999o!",
+        "2000000000o!",
+        "4000b97o!",
+        "4096$o!",
+    ] {
+        assert!(pattern::parse_rle(rle).is_err(), "{}", rle);
+    }
+    assert!(pattern::parse_within("129o!", 128).is_err());
+    assert!(pattern::parse_within("127$o!", 128).is_ok());
+    assert!(pattern::parse_within(&"O".repeat(129), 128).is_err());
+
+    // dead cells and rows past the end place nothing
+    let loaded = pattern::parse_rle("o4096b$4096$!").unwrap();
+    assert_eq!(loaded.cells, vec![(0, 0)]);
+    assert_eq!(pattern::size(&vec![(usize::MAX, 0)]), (usize::MAX, 1));
+}
+
+#[test]
+fn copperhead_moves_a_cell_every_ten_generations() {
+    for (kind, mut universe) in bundled("copperhead") {
+        let start = cells(universe.as_ref());
+        universe.step_many(10);
+
+        let end = cells(universe.as_ref());
+        let moved_right: Vec<_> = start.iter().map(|(row, col)| (*row, col + 1)).collect();
+        assert_eq!(end, moved_right, "{:?}", kind);
+    }
+}
+
+#[test]
+fn gosper_glider_gun_emits_a_glider_every_thirty_generations() {
+    for (kind, mut universe) in bundled("gosper_glider_gun") {
+        assert_eq!(universe.population(), 36, "{:?}", kind);
+        universe.step_many(30);
+        assert_eq!(universe.population(), 36 + 5, "{:?}", kind);
+    }
+}
+
+#[test]
+fn diehard_vanishes_after_130_generations() {
+    for (kind, mut universe) in bundled("diehard") {
+        universe.step_many(129);
+        assert!(universe.population() > 0, "{:?}", kind);
+        universe.step();
+        assert_eq!(universe.population(), 0, "{:?}", kind);
+    }
+}
+
+#[test]
+fn next_cycle_reports_the_cells_which_look_different() {
+    let mut game = Game::empty(8, 8);
+    game.load(&vec![(0, 0), (0, 1), (0, 2)], 3, 2);
+
+    // the ends die, the cells above and below are born, the middle ages
+    let mut changed = game.next_cycle();
+    changed.sort();
+    assert_eq!(changed, vec![(2, 3), (3, 2), (3, 3), (3, 4), (4, 3)]);
+
+    // after ten generations the middle is as dark as it gets
+    for _ in 0..9 {
+        game.next_cycle();
+    }
+    let mut changed = game.next_cycle();
+    changed.sort();
+    assert_eq!(changed, vec![(2, 3), (3, 2), (3, 4), (4, 3)]);
+}
+
+#[test]
+fn reset_restores_the_loaded_pattern() {
+    let mut game = Game::empty(16, 16);
+    game.load(&pattern::library("glider").unwrap().cells, 4, 4);
+    let start = game.live_cells();
+
+    game.step_many(10);
+    assert_ne!(game.live_cells(), start);
+
+    game.reset();
+    assert_eq!(game.live_cells(), start);
+    assert_eq!(game.cycles, 0);
+    assert_eq!(game.alive_cells, 5);
+}
+
+/// a dense board of size x size with the glider in the top row, its left
+/// column at col
+fn bounded_glider(boundary: Boundary, size: usize, col: i64) -> Game {
+    let mut game = Game::empty(size, size);
+    game.boundary = boundary;
+    for (row, other) in GLIDER {
+        game.set(*row, col + other, true);
+    }
+    game
+}
+
+/// the live cells of the board moved by (rows, cols), wrapping around, sorted
+fn moved(game: &Game, rows: i64, cols: i64) -> pattern::Pattern {
+    let size = game.state.len() as i64;
+    let mut cells: pattern::Pattern = game
+        .live_cells()
+        .iter()
+        .map(|(row, col)| {
+            (
+                (*row as i64 + rows).rem_euclid(size) as usize,
+                (*col as i64 + cols).rem_euclid(size) as usize,
+            )
+        })
+        .collect();
+    cells.sort();
+    cells
+}
+
+#[test]
+fn glider_comes_back_around_a_torus() {
+    let mut game = bounded_glider(Boundary::Torus, 8, 0);
+    let start = game.live_cells();
+
+    // a cell every four generations, eight cells across the board
+    game.step_many(32);
+    assert_eq!(game.live_cells(), start);
+}
+
+#[test]
+fn glider_crashes_into_a_block_at_dead_edges() {
+    let mut game = bounded_glider(Boundary::Dead, 10, 0);
+
+    game.step_many(40);
+    let block = vec![(8, 8), (8, 9), (9, 8), (9, 9)];
+    assert_eq!(game.live_cells(), block);
+    game.step_many(4);
+    assert_eq!(game.live_cells(), block);
+}
+
+#[test]
+fn glider_comes_back_upside_down_across_a_klein_bottle() {
+    let mut game = bounded_glider(Boundary::Klein, 16, 8);
+
+    // down and right before it crosses the right edge
+    game.step_many(8);
+    let before = moved(&game, 1, 1);
+    game.step_many(4);
+    assert_eq!(game.live_cells(), before);
+
+    // up and right once it came back in on the left
+    game.step_many(28);
+    let after = moved(&game, -1, 1);
+    game.step_many(4);
+    assert_eq!(game.live_cells(), after);
+    assert_eq!(game.population(), 5);
+}
+
+#[test]
+fn glider_grows_an_infinite_board() {
+    let mut game = bounded_glider(Boundary::Infinite, 8, 0);
+
+    game.step_many(100);
+    assert!(game.state.len() > 8 && game.state[0].len() > 8);
+    assert_eq!(game.population(), 5);
+    for (row, col) in GLIDER {
+        assert!(game.is_alive(row + 25, col + 25), "({}, {})", row, col);
+    }
+}
+
+#[test]
+fn infinite_board_stops_growing_at_its_largest_size() {
+    let mut game = bounded_glider(Boundary::Infinite, 8, 0);
+    game.max_size = 16;
+
+    game.step_many(100);
+    assert_eq!((game.state.len(), game.state[0].len()), (16, 16));
+    assert!(game.population() < 5);
+
+    game.reset();
+    assert_eq!(game.state.len(), 8);
+}
+
+#[test]
+fn engines_list_the_same_cells() {
+    for (kind, mut universe) in universes(&pulsar(), Rule::CONWAY) {
+        universe.step();
+        let mut listed = universe.cells();
+        listed.sort();
+        assert_eq!(listed, cells(universe.as_ref()), "{:?}", kind);
+    }
+}
+
+#[test]
+fn analysis_tells_what_a_pattern_settles_into() {
+    let block = [(0, 0), (0, 1), (1, 0), (1, 1)];
+    for (pattern, generations, outcome) in [
+        (&block[..], 4, Outcome::StillLife { generation: 0 }),
+        (
+            BLINKER,
+            4,
+            Outcome::Oscillator {
+                generation: 0,
+                period: 2,
+            },
+        ),
+        (
+            GLIDER,
+            8,
+            Outcome::Spaceship {
+                generation: 0,
+                period: 4,
+                rows: 1,
+                cols: 1,
+            },
+        ),
+        (
+            LWSS,
+            8,
+            Outcome::Spaceship {
+                generation: 0,
+                period: 4,
+                rows: 0,
+                cols: -2,
+            },
+        ),
+        (GLIDER, 3, Outcome::Unsettled),
+    ] {
+        for (kind, mut universe) in universes(pattern, Rule::CONWAY) {
+            let analysis = analysis::analyse(universe.as_mut(), generations);
+            assert_eq!(analysis.outcome, outcome, "{:?}", kind);
+            assert_eq!(analysis.samples.len() as u64, generations + 1);
+        }
+    }
+}
+
+#[test]
+fn analysis_only_compares_with_the_latest_generations() {
+    for (kind, mut universe) in universes(BLINKER, Rule::CONWAY) {
+        // too small to hold a generation, a period of two goes unnoticed
+        let analysis = analysis::analyse_within(universe.as_mut(), 8, 1);
+        assert_eq!(analysis.outcome, Outcome::Unsettled, "{:?}", kind);
+        assert_eq!(analysis.samples.len(), 9);
+    }
+}
+
+#[test]
+fn analysis_follows_population_and_bounds() {
+    for (kind, mut universe) in bundled("diehard") {
+        let analysis = analysis::analyse(universe.as_mut(), 140);
+
+        assert_eq!(
+            analysis.outcome,
+            Outcome::Extinct { generation: 130 },
+            "{:?}",
+            kind
+        );
+        let start = &analysis.samples[0];
+        assert_eq!(start.population, 7);
+        let bounds = start.bounds.unwrap();
+        assert_eq!((bounds.height, bounds.width), (3, 8));
+        assert!(analysis.samples[130].bounds.is_none());
+    }
+}
+
+#[test]
+fn soup_depends_only_on_its_seed() {
+    let soup = pattern::soup(16, 16, 0.5, 42);
+
+    assert_eq!(soup, pattern::soup(16, 16, 0.5, 42));
+    assert_ne!(soup, pattern::soup(16, 16, 0.5, 43));
+    assert!(soup.iter().all(|(row, col)| *row < 16 && *col < 16));
+    assert!(pattern::soup(16, 16, 0.0, 42).is_empty());
+    assert_eq!(pattern::soup(4, 4, 1.0, 42).len(), 16);
+}
+
+#[test]
+fn soup_stays_within_its_region() {
+    let soup = pattern::Soup {
+        density: 1.0,
+        seed: Some(7),
+        region: Some((4, 6)),
+    };
+    let (seed, cells) = soup.generate(16, 16);
+    assert_eq!(seed, 7);
+    assert_eq!(pattern::size(&cells), (4, 6));
+
+    // a region larger than the board is cut to it
+    let (_, cells) = pattern::Soup {
+        region: Some((40, 2)),
+        ..soup
+    }
+    .generate(16, 16);
+    assert_eq!(pattern::size(&cells), (16, 2));
+
+    assert_eq!(pattern::parse_region("16x8"), Ok((16, 8)));
+    assert_eq!(pattern::parse_region(" 3 X 4 "), Ok((3, 4)));
+    for invalid in ["", "16", "16x", "ax4", "-1x4"] {
+        assert!(pattern::parse_region(invalid).is_err(), "{}", invalid);
+    }
+}
+
+#[test]
+fn undo_and_redo_walk_through_generations() {
+    let mut game = Game::empty(16, 16);
+    game.load(&pattern::library("r_pentomino").unwrap().cells, 6, 6);
+
+    let mut boards = vec![game.state.clone()];
+    for _ in 0..20 {
+        game.next_cycle();
+        boards.push(game.state.clone());
+    }
+
+    // the cells come back as they were, ages included
+    for generation in (0..20).rev() {
+        assert!(game.undo().is_some());
+        assert_eq!(game.cycles, generation);
+        assert_eq!(game.state, boards[generation]);
+        assert_eq!(game.alive_cells as u64, game.population());
+    }
+    assert!(game.undo().is_none());
+
+    for board in &boards[1..] {
+        assert!(game.redo().is_some());
+        assert_eq!(&game.state, board);
+    }
+    assert!(game.redo().is_none());
+}
+
+#[test]
+fn undo_takes_back_a_flip() {
+    let mut game = Game::empty(8, 8);
+    game.load(&vec![(0, 0), (0, 1), (0, 2)], 3, 2);
+    game.next_cycle();
+    let before = game.state.clone();
+
+    game.flip(0, 0);
+    assert_eq!(game.alive_cells, 4);
+    assert_eq!(game.undo(), Some(vec![(0, 0)]));
+    assert_eq!(game.state, before);
+    assert_eq!(game.alive_cells, 3);
+    assert_eq!(game.cycles, 1);
+
+    // a new change drops what was undone
+    game.next_cycle();
+    assert!(game.redo().is_none());
+}
+
+#[test]
+fn rewind_goes_as_far_back_as_the_history() {
+    let mut game = Game::empty(16, 16);
+    game.load(&pattern::library("glider").unwrap().cells, 4, 4);
+    // a generation of a glider changes about ten cells
+    game.history = History::new(2048);
+    let start = game.state.clone();
+
+    game.step_many(4);
+    assert!(game.rewind(0));
+    assert_eq!(game.state, start);
+
+    game.step_many(40);
+    assert!(!game.rewind(0));
+    assert!(game.cycles > 0 && game.cycles < 40, "{}", game.cycles);
+
+    game.history = History::disabled();
+    game.step_many(4);
+    assert!(game.undo().is_none());
+}
+
+#[test]
+fn undo_shrinks_a_grown_board_back() {
+    let mut game = bounded_glider(Boundary::Infinite, 8, 0);
+    let start = game.cells();
+
+    game.step_many(40);
+    assert!(game.state.len() > 8);
+    assert!(game.rewind(0));
+    assert_eq!((game.state.len(), game.state[0].len()), (8, 8));
+    assert_eq!(game.cells(), start);
+}
+
+#[test]
+fn default_palette_is_the_gradient_of_the_web_ui() {
+    let mut game = Game::empty(8, 8);
+    game.load(&vec![(0, 0), (0, 1), (1, 0), (1, 1)], 2, 2);
+
+    assert_eq!(game.state[0][0].color(), "hsl(0, 0%, 100%)");
+    for (generations, lightness) in [(0, 0), (3, 30), (5, 50), (12, 100)] {
+        let mut game = Game::empty(8, 8);
+        game.load(&vec![(0, 0), (0, 1), (1, 0), (1, 1)], 2, 2);
+        game.step_many(generations);
+        assert_eq!(
+            game.state[2][2].color(),
+            format!("hsl(210, 100%, {}%)", lightness)
+        );
+    }
+}
+
+#[test]
+fn colours_parse_and_convert() {
+    for (text, rgb) in [
+        ("#1e90ff", [0x1e, 0x90, 0xff]),
+        ("#FFF", [255, 255, 255]),
+        ("hsl(210, 100%, 50%)", [0, 128, 255]),
+        ("hsl(0, 0%, 0%)", [0, 0, 0]),
+        (" HSL(120,100%,25%) ", [0, 128, 0]),
+    ] {
+        assert_eq!(text.parse::<Colour>().unwrap().rgb(), rgb, "{}", text);
+    }
+
+    for invalid in [
+        "",
+        "blue",
+        "#12345",
+        "#ggg",
+        "hsl(1, 2%)",
+        "hsl(0, 150%, 50%)",
+    ] {
+        assert!(invalid.parse::<Colour>().is_err(), "{}", invalid);
+    }
+
+    let palette = Palette {
+        dead: "#000".parse().unwrap(),
+        young: "#ff0000".parse().unwrap(),
+        old: "#0000ff".parse().unwrap(),
+        ages: 2,
+        ..Palette::default()
+    };
+    assert_eq!(palette.alive(0).rgb(), [255, 0, 0]);
+    assert_eq!(palette.alive(1).rgb(), [0, 255, 0]);
+    assert_eq!(palette.alive(5).rgb(), [0, 0, 255]);
+}
+
+#[test]
+fn export_writes_an_animated_gif_and_png_frames() {
+    let dir = std::env::temp_dir().join(format!("game_of_life_export_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let options = export::Options {
+        generations: 8,
+        step: 2,
+        scale: 3,
+        delay: 50,
+        palette: Palette::default(),
+    };
+    let glider = |game: &mut Game| game.load(&pattern::library("glider").unwrap().cells, 1, 1);
+
+    let mut game = Game::empty(10, 12);
+    glider(&mut game);
+    export::run(options, &mut game, &dir.join("glider.gif")).unwrap();
+
+    let file = std::fs::File::open(dir.join("glider.gif")).unwrap();
+    let mut options_ = gif::DecodeOptions::new();
+    options_.set_color_output(gif::ColorOutput::Indexed);
+    let mut decoder = options_.read_info(file).unwrap();
+    assert_eq!((decoder.width(), decoder.height()), (36, 30));
+    let mut frames = 0;
+    while let Some(frame) = decoder.read_next_frame().unwrap() {
+        assert_eq!(frame.delay, 5);
+        // five cells of nine pixels each
+        let live = frame.buffer.iter().filter(|index| **index != 0).count();
+        assert_eq!(live, 5 * 9);
+        frames += 1;
+    }
+    assert_eq!(frames, 5);
+
+    let mut game = Game::empty(10, 12);
+    glider(&mut game);
+    export::run(options, &mut game, &dir.join("glider.png")).unwrap();
+    for generation in [0, 2, 4, 6, 8] {
+        let file = std::fs::File::open(dir.join(format!("glider-{}.png", generation))).unwrap();
+        let mut reader = png::Decoder::new(file).read_info().unwrap();
+        let mut rgb = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut rgb).unwrap();
+        assert_eq!((info.width, info.height), (36, 30));
+        let white = rgb
+            .chunks(3)
+            .filter(|pixel| *pixel == [255, 255, 255])
+            .count();
+        assert_eq!(white, 36 * 30 - 5 * 9, "{}", generation);
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn multi_state_rle_reads_and_writes_every_state() {
+    let loaded = pattern::parse("x = 4, y = 2, rule = B2/S/C30\n2A.B$3.pA!").unwrap();
+    let rule = loaded.rule.unwrap();
+
+    assert_eq!(loaded.cells, vec![(0, 0), (0, 1)]);
+    assert_eq!(
+        loaded.states,
+        vec![
+            (0, 3, State::Dying { step: 0, steps: 28 }),
+            (
+                1,
+                3,
+                State::Dying {
+                    step: 23,
+                    steps: 28
+                }
+            ),
+        ]
+    );
+    assert_eq!(
+        pattern::to_rle(&loaded.cells, &loaded.states, rule),
+        "x = 4, y = 2, rule = B2/S/C30\n2A.B$3.pA!\n"
+    );
+
+    // a state beyond those of the rule, or of two states
+    assert!(pattern::parse("x = 1, y = 1, rule = B2/S/C3\nC!").is_err());
+    assert!(pattern::parse("x = 1, y = 1, rule = B3/S23\nB!").is_err());
+    assert!(pattern::parse("x = 1, y = 1, rule = WireWorld\npA!").is_err());
+}
+
+#[test]
+fn dying_cells_of_generations_rules_are_not_born() {
+    let brain: Rule = "B2/S/C3".parse().unwrap();
+    let star_wars: Rule = "B2/S345/C4".parse().unwrap();
+
+    assert_eq!(brain.next_state(State::Dead, 2), State::Alive);
+    assert_eq!(
+        brain.next_state(State::Alive, 2),
+        State::Dying { step: 0, steps: 1 }
+    );
+    assert_eq!(
+        brain.next_state(State::Dying { step: 0, steps: 1 }, 2),
+        State::Dead
+    );
+    assert_eq!(star_wars.next_state(State::Alive, 4), State::Alive);
+    assert_eq!(
+        star_wars.next_state(State::Dying { step: 0, steps: 2 }, 2),
+        State::Dying { step: 1, steps: 2 }
+    );
+    // two states die at once
+    assert_eq!(Rule::CONWAY.next_state(State::Alive, 1), State::Dead);
+}
+
+#[test]
+fn brians_brain_spaceship_moves_a_cell_every_generation() {
+    let loaded = pattern::parse("x = 2, y = 2, rule = B2/S/C3\nAB$AB!").unwrap();
+    let mut game = Game::with_rule(16, 16, loaded.rule.unwrap());
+    game.load_with_states(&loaded.cells, &loaded.states, 7, 10);
+    let start = (game.live_cells(), game.other_cells());
+
+    game.step_many(5);
+
+    let moved = |cells: &pattern::Pattern| -> pattern::Pattern {
+        cells.iter().map(|(row, col)| (*row, col - 5)).collect()
+    };
+    assert_eq!(game.live_cells(), moved(&start.0));
+    assert_eq!(
+        game.other_cells(),
+        start
+            .1
+            .iter()
+            .map(|(row, col, state)| (*row, col - 5, *state))
+            .collect::<pattern::States>()
+    );
+    assert_eq!(game.alive_cells, 2);
+}
+
+#[test]
+fn wireworld_clock_sends_an_electron_every_ten_generations() {
+    let loaded = pattern::library("wireworld_clock").unwrap();
+    let mut game = Game::with_rule(8, 24, loaded.rule.unwrap());
+    game.load_with_states(&loaded.cells, &loaded.states, 2, 2);
+    let wires = |game: &Game| {
+        game.state
+            .iter()
+            .flatten()
+            .filter(|cell| cell.state != State::Dead)
+            .count()
+    };
+    assert_eq!(game.alive_cells, 1);
+    assert_eq!(wires(&game), 21);
+
+    // the first electron runs down the wire and out of its end
+    game.step_many(20);
+    let board = game.state.clone();
+    game.step_many(5);
+    assert_ne!(game.state, board);
+    game.step_many(5);
+    assert_eq!(game.state, board);
+    assert_eq!(wires(&game), 21);
+
+    // the wires are part of what repeats, a lone electron is no spaceship
+    game.reset();
+    assert_eq!(
+        analysis::analyse(&mut game, 40).outcome,
+        Outcome::Oscillator {
+            generation: 7,
+            period: 10
+        }
+    );
+}
+
+#[test]
+fn flips_go_through_the_states_of_the_rule() {
+    let mut game = Game::with_rule(4, 4, "wireworld".parse().unwrap());
+    let mut states = Vec::new();
+    for _ in 0..4 {
+        game.flip(1, 1);
+        states.push(game.state[1][1].state);
+    }
+    assert_eq!(
+        states,
+        [State::Conductor, State::Head, State::Tail, State::Dead]
+    );
+
+    // a dying cell is killed rather than revived
+    game.rule = "B2/S/C3".parse().unwrap();
+    game.state[1][1].state = State::Dying { step: 0, steps: 1 };
+    game.flip(1, 1);
+    assert_eq!(game.state[1][1].state, State::Dead);
+    game.flip(1, 1);
+    assert_eq!(game.state[1][1].state, State::Alive);
+    assert_eq!(game.alive_cells, 1);
+}
+
+#[test]
+fn palette_colours_every_state() {
+    let colour = |state: State| {
+        Cell {
+            state,
+            ..Cell::default()
+        }
+        .color()
+    };
+
+    assert_eq!(
+        colour(State::Dying { step: 0, steps: 1 }),
+        "hsl(0, 100%, 50%)"
+    );
+    assert_eq!(
+        colour(State::Dying { step: 1, steps: 3 }),
+        "hsl(0, 75%, 62.5%)"
+    );
+    assert_eq!(colour(State::Head), "hsl(210, 100%, 50%)");
+    assert_eq!(colour(State::Tail), "hsl(0, 100%, 50%)");
+    assert_eq!(colour(State::Conductor), "hsl(45, 100%, 50%)");
+}
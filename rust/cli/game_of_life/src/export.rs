@@ -0,0 +1,211 @@
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use crate::conway::engine::Engine;
+use crate::conway::game::{Cell, Game, State};
+use crate::conway::palette::Palette;
+use crate::conway::rule::Rule;
+
+/// most ages a palette may have, a GIF has room for 256 colours
+pub const MAX_AGES: usize = 254;
+
+/// most generations an export may run for, the board steps through each
+pub const MAX_GENERATIONS: u64 = 100_000;
+
+/// largest side of a cell in pixels
+pub const MAX_SCALE: usize = 64;
+
+/// largest width and height of a frame in pixels
+pub const MAX_SIDE: usize = 16384;
+
+/// Options of an export, given on the command line
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+    pub generations: u64,
+    /// generations advanced between two frames
+    pub step: u64,
+    /// side of a cell in pixels
+    pub scale: usize,
+    /// milliseconds a frame of a GIF is shown
+    pub delay: u64,
+    pub palette: Palette,
+}
+
+/// Output is where the frames are written
+enum Output {
+    /// a single animated GIF
+    Gif(gif::Encoder<BufWriter<File>>),
+    /// a PNG per frame, numbered by generation
+    Png { stem: PathBuf, digits: usize },
+}
+
+/// renders the board every step generations, as it was when the export
+/// started, to an animated GIF or numbered PNG frames depending on the
+/// extension of the output. Returns what was written.
+pub fn run(options: Options, game: &mut Game, output: &Path) -> Result<String, String> {
+    if options.palette.ages > MAX_AGES {
+        return Err(format!("a palette has {} ages at most", MAX_AGES));
+    }
+    let step = options.step.max(1);
+    let scale = options.scale.max(1);
+
+    // an infinite board grows, the frames show where it started
+    let (rows, cols) = (game.state.len(), game.state[0].len());
+    let (width, height) = (cols * scale, rows * scale);
+    if width > MAX_SIDE || height > MAX_SIDE {
+        return Err(format!(
+            "a frame is {} pixels wide and high at most, {}x{} cells of {} pixels are {}x{}",
+            MAX_SIDE, rows, cols, scale, height, width
+        ));
+    }
+    let states = states(game.rule);
+    let colours = colours(&options.palette, &states);
+    if colours.len() > 256 {
+        return Err(format!(
+            "{} ages and the {} states of rule {} take {} colours, a GIF has room for 256",
+            options.palette.ages,
+            game.rule.states(),
+            game.rule,
+            colours.len()
+        ));
+    }
+
+    let mut out = match output.extension().and_then(|extension| extension.to_str()) {
+        Some("gif") => {
+            let (width, height) = match (u16::try_from(width), u16::try_from(height)) {
+                (Ok(width), Ok(height)) => (width, height),
+                _ => return Err("a GIF is 65535 pixels wide and high at most".to_string()),
+            };
+            let file = create(output)?;
+            let mut encoder =
+                gif::Encoder::new(BufWriter::new(file), width, height, &colours.concat())
+                    .map_err(|err| err.to_string())?;
+            encoder
+                .set_repeat(gif::Repeat::Infinite)
+                .map_err(|err| err.to_string())?;
+            Output::Gif(encoder)
+        }
+        Some("png") => Output::Png {
+            stem: output.with_extension(""),
+            digits: game
+                .cycles
+                .saturating_add(options.generations as usize)
+                .to_string()
+                .len(),
+        },
+        _ => return Err(format!("{} is neither a .gif nor a .png", output.display())),
+    };
+
+    let frames = options.generations / step + 1;
+    for frame in 0..frames {
+        if frame > 0 {
+            game.step_many(step);
+        }
+        let pixels = render(game, rows, cols, scale, options.palette.ages, &states);
+
+        match &mut out {
+            Output::Gif(encoder) => {
+                let frame = gif::Frame {
+                    width: width as u16,
+                    height: height as u16,
+                    // in hundredths of a second
+                    delay: (options.delay / 10).clamp(1, u16::MAX as u64) as u16,
+                    buffer: Cow::Owned(pixels),
+                    ..gif::Frame::default()
+                };
+                encoder.write_frame(&frame).map_err(|err| err.to_string())?;
+            }
+            Output::Png { stem, digits } => {
+                let path = PathBuf::from(format!(
+                    "{}-{:0digits$}.png",
+                    stem.display(),
+                    game.cycles,
+                    digits = *digits
+                ));
+                let rgb: Vec<u8> = pixels
+                    .iter()
+                    .flat_map(|index| colours[*index as usize])
+                    .collect();
+                png(&path, width as u32, height as u32, &rgb)?;
+            }
+        }
+    }
+
+    Ok(match out {
+        Output::Gif(_) => format!("wrote {} frames to {}", frames, output.display()),
+        Output::Png { stem, .. } => format!("wrote {} frames to {}-*.png", frames, stem.display()),
+    })
+}
+
+/// the states of the rule other than dead or alive, which are coloured
+/// after the ages
+fn states(rule: Rule) -> Vec<State> {
+    (1..rule.states())
+        .filter_map(|number| rule.state(number))
+        .filter(|state| *state != State::Alive)
+        .collect()
+}
+
+/// the colours of the palette by index, dead cells first, then live
+/// cells by age, then the other states
+fn colours(palette: &Palette, states: &[State]) -> Vec<[u8; 3]> {
+    let mut colours = vec![palette.dead.rgb()];
+    colours.extend((0..=palette.ages).map(|age| palette.alive(age).rgb()));
+    colours.extend(states.iter().map(|state| {
+        let cell = Cell {
+            state: *state,
+            ..Cell::default()
+        };
+        palette.colour(&cell).rgb()
+    }));
+    colours
+}
+
+/// the board of rows x cols from the top left corner of the universe, a
+/// colour index per pixel
+fn render(
+    game: &Game,
+    rows: usize,
+    cols: usize,
+    scale: usize,
+    ages: usize,
+    states: &[State],
+) -> Vec<u8> {
+    let width = cols * scale;
+    let mut pixels = vec![0; width * rows * scale];
+
+    for row in 0..rows {
+        for col in 0..cols {
+            let cell = game.cell(row as i64, col as i64);
+            let index = match cell.state {
+                State::Dead => continue,
+                State::Alive => 1 + cell.cycles_alive.min(ages),
+                // states of another rule, left from before it changed, are dead
+                state => match states.iter().position(|other| *other == state) {
+                    Some(position) => ages + 2 + position,
+                    None => continue,
+                },
+            } as u8;
+            for y in row * scale..(row + 1) * scale {
+                let start = y * width + col * scale;
+                pixels[start..start + scale].fill(index);
+            }
+        }
+    }
+    pixels
+}
+
+fn create(path: &Path) -> Result<File, String> {
+    File::create(path).map_err(|err| format!("unable to create {}: {}", path.display(), err))
+}
+
+fn png(path: &Path, width: u32, height: u32, rgb: &[u8]) -> Result<(), String> {
+    let mut encoder = png::Encoder::new(BufWriter::new(create(path)?), width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header().map_err(|err| err.to_string())?;
+    writer.write_image_data(rgb).map_err(|err| err.to_string())
+}
@@ -0,0 +1,155 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::{broadcast, Notify};
+
+use crate::conway::game::Game;
+use crate::http::handler;
+
+/// fastest and slowest speed the game may be played at, in milliseconds per generation
+pub const MIN_DELAY: u64 = 20;
+pub const MAX_DELAY: u64 = 2000;
+
+/// frames a browser may fall behind before it misses some and is sent
+/// the whole grid instead
+const FRAME_CAPACITY: usize = 16;
+
+/// Frame is rendered HTML streamed to every connected browser
+#[derive(Debug, Clone)]
+pub enum Frame {
+    /// the grid along with the stats
+    Grid(String),
+    /// the cells changed since the last frame along with the stats
+    Cells(String),
+    /// the play/pause/step/speed controls
+    Playback(String),
+}
+
+/// Autoplay advances the shared game on a server side tick while it
+/// is playing. Every change is published to all connected browsers,
+/// hence they all see the same simulation.
+pub struct Autoplay {
+    playing: AtomicBool,
+    /// milliseconds between two generations
+    delay: AtomicU64,
+    frames: broadcast::Sender<Frame>,
+    /// wakes the ticker when playback changes
+    changed: Notify,
+}
+
+impl Autoplay {
+    /// a paused autoplay
+    pub fn new(delay: u64) -> Self {
+        let (frames, _) = broadcast::channel(FRAME_CAPACITY);
+        Autoplay {
+            playing: AtomicBool::new(false),
+            delay: AtomicU64::new(delay.clamp(MIN_DELAY, MAX_DELAY)),
+            frames,
+            changed: Notify::new(),
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing.load(Ordering::Relaxed)
+    }
+
+    pub fn set_playing(&self, playing: bool) {
+        self.playing.store(playing, Ordering::Relaxed);
+        self.changed.notify_one();
+    }
+
+    pub fn delay(&self) -> u64 {
+        self.delay.load(Ordering::Relaxed)
+    }
+
+    /// sets the delay between two generations, kept within MIN_DELAY and MAX_DELAY
+    pub fn set_delay(&self, delay: u64) {
+        self.delay
+            .store(delay.clamp(MIN_DELAY, MAX_DELAY), Ordering::Relaxed);
+        self.changed.notify_one();
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Frame> {
+        self.frames.subscribe()
+    }
+
+    /// number of browsers streaming the game
+    pub fn watchers(&self) -> usize {
+        self.frames.receiver_count()
+    }
+
+    pub fn publish(&self, frame: Frame) {
+        // nobody watching is fine
+        let _ = self.frames.send(frame);
+    }
+}
+
+/// advances the game every delay while playing and publishes the changed cells,
+/// or the whole grid when an infinite board grew
+pub async fn run(game: Arc<Mutex<Game>>, autoplay: Arc<Autoplay>) {
+    loop {
+        if !autoplay.is_playing() {
+            autoplay.changed.notified().await;
+            continue;
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_millis(autoplay.delay())) => {}
+            // paused or sped up, start over with the new playback
+            _ = autoplay.changed.notified() => continue,
+        }
+
+        let frame = handler::step(&mut game.lock().unwrap());
+        autoplay.publish(frame);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delay_is_kept_within_bounds() {
+        let autoplay = Autoplay::new(0);
+        assert_eq!(autoplay.delay(), MIN_DELAY);
+        assert!(!autoplay.is_playing());
+
+        autoplay.set_delay(u64::MAX);
+        assert_eq!(autoplay.delay(), MAX_DELAY);
+        autoplay.set_delay(100);
+        assert_eq!(autoplay.delay(), 100);
+    }
+
+    #[tokio::test]
+    async fn ticker_publishes_generations_while_playing() {
+        let mut game = Game::empty(8, 8);
+        game.load(&vec![(0, 0), (0, 1), (0, 2)], 3, 3);
+        let game = Arc::new(Mutex::new(game));
+        let autoplay = Arc::new(Autoplay::new(MIN_DELAY));
+        let ticker = tokio::spawn(run(game.clone(), autoplay.clone()));
+        let mut frames = autoplay.subscribe();
+        assert_eq!(autoplay.watchers(), 1);
+
+        autoplay.set_playing(true);
+        for _ in 0..3 {
+            let frame = tokio::time::timeout(Duration::from_secs(1), frames.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(matches!(frame, Frame::Cells(_)));
+        }
+        assert!(game.lock().unwrap().cycles >= 3);
+
+        // a tick in flight may still land, none after it
+        autoplay.set_playing(false);
+        tokio::time::sleep(Duration::from_millis(5 * MIN_DELAY)).await;
+        while frames.try_recv().is_ok() {}
+        let cycles = game.lock().unwrap().cycles;
+        tokio::time::sleep(Duration::from_millis(5 * MIN_DELAY)).await;
+        assert!(frames.try_recv().is_err());
+        assert_eq!(game.lock().unwrap().cycles, cycles);
+
+        ticker.abort();
+    }
+}
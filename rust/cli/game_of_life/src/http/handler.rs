@@ -1,35 +1,52 @@
 use askama::Template;
-use serde::{de, Deserialize, Deserializer};
-use std::sync::{Arc, Mutex};
+use futures_util::stream::Stream;
+use serde::Deserialize;
+use std::convert::Infallible;
+use std::sync::Arc;
+use tokio_stream::{wrappers::BroadcastStream, StreamExt};
 
 use axum::{
-    extract::{Extension, Query, State},
-    http::StatusCode,
-    response::{Html, IntoResponse, Response},
+    extract::{Extension, Form, Path, Query},
+    http::{header, HeaderName, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        Html, IntoResponse, Redirect, Response,
+    },
 };
 
-use axum::response::sse::{Event, KeepAlive, Sse};
-use futures_util::stream::{self, Stream};
-use std::{convert::Infallible, time::Duration};
-use tokio_stream::StreamExt as _;
+use clap::ValueEnum;
 
 use crate::conway;
+use crate::conway::boundary::Boundary;
+use crate::http::autoplay::{self, Autoplay, Frame};
+use crate::http::session::{self, Owned, Session, Sessions};
 
 #[derive(Template)]
 #[template(path = "index.html")]
 struct IndexTemplate {
+    /// id of the game, in the link to watch it
+    id: String,
+    /// whether the visitor watches the game of someone else
+    spectating: bool,
     state: Vec<Vec<conway::game::Cell>>,
     with_sse: bool,
     cycles: usize,
     alive_cells: usize,
-}
-
-#[derive(Template)]
-#[template(path = "grid.html")]
-struct GridTemplate {
-    state: Vec<Vec<conway::game::Cell>>,
-    cycles: usize,
-    alive_cells: usize,
+    rule: String,
+    rule_error: Option<String>,
+    boundary: String,
+    boundaries: Vec<String>,
+    patterns: Vec<&'static str>,
+    pattern_error: Option<String>,
+    seed: String,
+    used_seed: Option<u64>,
+    density: f64,
+    soup_error: Option<String>,
+    oob: bool,
+    playing: bool,
+    delay: u64,
+    min_delay: u64,
+    max_delay: u64,
 }
 
 #[derive(Template)]
@@ -40,71 +57,660 @@ struct GridResponseTemplate {
     alive_cells: usize,
 }
 
-pub async fn index(state: Extension<Arc<Mutex<conway::game::Game>>>) -> impl IntoResponse {
-    let game = state.lock().unwrap();
+/// the game of the visitor, or the form to start one
+pub async fn index(session: Option<Owned>, sessions: Extension<Arc<Sessions>>) -> Response {
+    match session {
+        Some(Owned(session)) => render_index(&session, false),
+        None => new_game_form(sessions).await.into_response(),
+    }
+}
+
+/// the game of someone else, without the controls
+pub async fn watch(Path(id): Path<String>, sessions: Extension<Arc<Sessions>>) -> Response {
+    match sessions.get(&id) {
+        Some(session) => render_index(&session, true),
+        None => (StatusCode::NOT_FOUND, "there is no such game").into_response(),
+    }
+}
+
+fn render_index(session: &Session, spectating: bool) -> Response {
+    let game = session.game.lock().unwrap();
 
     TemplateResponse(IndexTemplate {
+        id: session.id.clone(),
+        spectating,
         state: game.state.clone(),
-        with_sse: false,
+        with_sse: true,
         cycles: game.cycles,
         alive_cells: game.alive_cells,
+        rule: game.rule.to_string(),
+        rule_error: None,
+        boundary: game.boundary.to_string(),
+        boundaries: boundaries(),
+        patterns: library(),
+        pattern_error: None,
+        seed: String::new(),
+        used_seed: None,
+        density: conway::pattern::DEFAULT_DENSITY,
+        soup_error: None,
+        oob: false,
+        playing: session.autoplay.is_playing(),
+        delay: session.autoplay.delay(),
+        min_delay: autoplay::MIN_DELAY,
+        max_delay: autoplay::MAX_DELAY,
     })
     .into_response()
 }
 
-pub async fn next_cycle(state: Extension<Arc<Mutex<conway::game::Game>>>) -> impl IntoResponse {
-    let mut game = state.lock().unwrap();
-    let tmp = game.next_cycle();
+#[derive(Template)]
+#[template(path = "new.html")]
+struct NewGameTemplate {
+    rows: usize,
+    cols: usize,
+    max_size: usize,
+}
+
+pub async fn new_game_form(sessions: Extension<Arc<Sessions>>) -> impl IntoResponse {
+    TemplateResponse(NewGameTemplate {
+        rows: sessions.defaults.rows,
+        cols: sessions.defaults.cols,
+        max_size: session::MAX_SIZE,
+    })
+}
 
-    game.state = tmp.state;
-    game.cycles = tmp.cycles;
-    game.alive_cells = tmp.alive_cells;
+#[derive(Debug, Deserialize)]
+pub struct NewGameOptions {
+    rows: usize,
+    cols: usize,
+}
+
+/// starts a game of the visitor, replacing the one they had
+pub async fn new_game(
+    sessions: Extension<Arc<Sessions>>,
+    previous: Option<Owned>,
+    Form(options): Form<NewGameOptions>,
+) -> Response {
+    if let Some(Owned(previous)) = previous {
+        sessions.remove(&previous.id);
+    }
+
+    match sessions.create(options.rows, options.cols) {
+        Some(session) => {
+            ([(header::SET_COOKIE, session.cookie())], Redirect::to("/")).into_response()
+        }
+        None => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "there are too many games, try again later",
+        )
+            .into_response(),
+    }
+}
 
-    TemplateResponse(GridResponseTemplate {
+/// renders the grid along with the stats, as swapped in after every generation
+pub(crate) fn render_grid(game: &conway::game::Game) -> String {
+    let template = GridResponseTemplate {
         state: game.state.clone(),
         cycles: game.cycles,
         alive_cells: game.alive_cells,
-    })
-    .into_response()
+    };
+    template
+        .render()
+        .unwrap_or_else(|err| format!("Unable to parse template. Error: {err}"))
+}
+
+#[derive(Template)]
+#[template(path = "cells.html")]
+struct CellsTemplate {
+    cells: Vec<(usize, usize, conway::game::Cell)>,
+    cycles: usize,
+    alive_cells: usize,
+}
+
+/// renders only the changed cells along with the stats, swapped in out of band
+pub(crate) fn render_cells(game: &conway::game::Game, changed: &[(usize, usize)]) -> String {
+    let template = CellsTemplate {
+        cells: changed
+            .iter()
+            .map(|(i, j)| (*i, *j, game.state[*i][*j]))
+            .collect(),
+        cycles: game.cycles,
+        alive_cells: game.alive_cells,
+    };
+    template
+        .render()
+        .unwrap_or_else(|err| format!("Unable to parse template. Error: {err}"))
+}
+
+/// renders the grid for the browser that asked and every other connected one
+fn publish_grid(game: &conway::game::Game, autoplay: &Autoplay) -> Html<String> {
+    let grid = render_grid(game);
+    autoplay.publish(Frame::Grid(grid.clone()));
+    Html(grid)
+}
+
+/// advances the game by a generation and renders the changed cells, or the
+/// whole grid when an infinite board grew
+pub(crate) fn step(game: &mut conway::game::Game) -> Frame {
+    let size = (game.state.len(), game.state[0].len());
+    let changed = game.next_cycle();
+    render_changes(game, size, &changed)
+}
+
+/// renders the changed cells, or the whole grid when the board is no longer
+/// of the size it was
+fn render_changes(
+    game: &conway::game::Game,
+    size: (usize, usize),
+    changed: &[(usize, usize)],
+) -> Frame {
+    if size == (game.state.len(), game.state[0].len()) {
+        Frame::Cells(render_cells(game, changed))
+    } else {
+        Frame::Grid(render_grid(game))
+    }
+}
+
+/// advances the game by a single generation, the step of the playback controls
+pub async fn next_cycle(Owned(session): Owned) -> Response {
+    let mut game = session.game.lock().unwrap();
+    let frame = step(&mut game);
+    publish_changes(&session.autoplay, frame)
+}
+
+/// undoes the latest generation or flip
+pub async fn prev(Owned(session): Owned) -> Response {
+    let mut game = session.game.lock().unwrap();
+    let size = (game.state.len(), game.state[0].len());
+    match game.undo() {
+        Some(changed) => {
+            let frame = render_changes(&game, size, &changed);
+            publish_changes(&session.autoplay, frame)
+        }
+        None => StatusCode::NO_CONTENT.into_response(),
+    }
+}
+
+/// redoes the latest generation or flip undone
+pub async fn redo(Owned(session): Owned) -> Response {
+    let mut game = session.game.lock().unwrap();
+    let size = (game.state.len(), game.state[0].len());
+    match game.redo() {
+        Some(changed) => {
+            let frame = render_changes(&game, size, &changed);
+            publish_changes(&session.autoplay, frame)
+        }
+        None => StatusCode::NO_CONTENT.into_response(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RewindOptions {
+    generation: usize,
+}
+
+/// undoes changes back to the generation, or as far as the history goes
+pub async fn rewind(
+    Query(options): Query<RewindOptions>,
+    Owned(session): Owned,
+) -> impl IntoResponse {
+    let mut game = session.game.lock().unwrap();
+
+    game.rewind(options.generation);
+    publish_grid(&game, &session.autoplay)
+}
+
+/// sends the changes to every connected browser and to the one that asked,
+/// which swaps in a whole grid in place of the one it has
+fn publish_changes(autoplay: &Autoplay, frame: Frame) -> Response {
+    autoplay.publish(frame.clone());
+
+    match frame {
+        Frame::Grid(grid) => (
+            [
+                (HeaderName::from_static("hx-retarget"), "#state"),
+                (HeaderName::from_static("hx-reswap"), "innerHTML"),
+            ],
+            Html(grid),
+        )
+            .into_response(),
+        Frame::Cells(cells) | Frame::Playback(cells) => Html(cells).into_response(),
+    }
+}
+
+#[derive(Template)]
+#[template(path = "soup.html")]
+struct SoupTemplate {
+    seed: String,
+    used_seed: Option<u64>,
+    density: f64,
+    soup_error: Option<String>,
+    oob: bool,
+}
+
+/// the grid along with the soup controls after a soup was made
+#[derive(Template)]
+#[template(path = "soup_response.html")]
+struct SoupResponseTemplate {
+    state: Vec<Vec<conway::game::Cell>>,
+    cycles: usize,
+    alive_cells: usize,
+    seed: String,
+    used_seed: Option<u64>,
+    density: f64,
+    soup_error: Option<String>,
+    oob: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResetOptions {
+    /// the seed of a random soup, a random one if empty
+    seed: Option<String>,
+    /// chance of a cell of the soup to be alive
+    density: Option<f64>,
+    /// rows and columns the soup covers as ROWSxCOLS, the whole board if empty
+    region: Option<String>,
 }
 
-pub async fn reset(state: Extension<Arc<Mutex<conway::game::Game>>>) -> impl IntoResponse {
-    let mut game = state.lock().unwrap();
+/// restores the board the game started with, or starts it over from a random
+/// soup when any of the soup options is given
+pub async fn reset(Query(options): Query<ResetOptions>, Owned(session): Owned) -> Response {
+    let mut game = session.game.lock().unwrap();
+
+    if options.seed.is_none() && options.density.is_none() && options.region.is_none() {
+        game.reset();
+        return publish_grid(&game, &session.autoplay).into_response();
+    }
+
+    let seed = options.seed.unwrap_or_default();
+    let density = options.density.unwrap_or(conway::pattern::DEFAULT_DENSITY);
+    let soup = soup(&seed, density, options.region.as_deref());
+    let soup = match soup {
+        Ok(soup) => soup,
+        // only the error is shown, the board stays as it is
+        Err(err) => {
+            return (
+                [("HX-Reswap", "none")],
+                TemplateResponse(SoupTemplate {
+                    seed,
+                    used_seed: None,
+                    density,
+                    soup_error: Some(err),
+                    oob: true,
+                }),
+            )
+                .into_response()
+        }
+    };
 
-    game.reset();
-    TemplateResponse(GridTemplate {
+    let (rows, cols) = (game.state.len(), game.state[0].len());
+    let (used_seed, cells) = soup.generate(rows, cols);
+    let (row, col) = conway::pattern::centred(&cells, rows, cols);
+    game.load(&cells, row, col);
+    session.autoplay.publish(Frame::Grid(render_grid(&game)));
+
+    TemplateResponse(SoupResponseTemplate {
         state: game.state.clone(),
         cycles: game.cycles,
         alive_cells: game.alive_cells,
+        seed,
+        used_seed: Some(used_seed),
+        density,
+        soup_error: None,
+        oob: true,
     })
     .into_response()
 }
 
+/// the soup of the options of the form, which leaves them empty rather than out
+fn soup(seed: &str, density: f64, region: Option<&str>) -> Result<conway::pattern::Soup, String> {
+    if !(0.0..=1.0).contains(&density) {
+        return Err("the density is a chance between 0 and 1".to_string());
+    }
+    let seed = match seed.trim() {
+        "" => None,
+        seed => Some(
+            seed.parse()
+                .map_err(|_| format!("{} is not a seed, use a whole number", seed))?,
+        ),
+    };
+    let region = match region.map(str::trim) {
+        None | Some("") => None,
+        Some(region) => Some(conway::pattern::parse_region(region)?),
+    };
+
+    Ok(conway::pattern::Soup {
+        density,
+        seed,
+        region,
+    })
+}
+
+#[derive(Template)]
+#[template(path = "cell_response.html")]
+struct CellTemplate {
+    i: usize,
+    j: usize,
+    cell: conway::game::Cell,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct SwitchOptions {
     i: usize,
     j: usize,
 }
 
-pub async fn flip(
-    Query(swaps): Query<SwitchOptions>,
-    state: Extension<Arc<Mutex<conway::game::Game>>>,
-) -> impl IntoResponse {
-    let mut game = state.lock().unwrap();
+pub async fn flip(Query(swaps): Query<SwitchOptions>, Owned(session): Owned) -> impl IntoResponse {
+    let mut game = session.game.lock().unwrap();
 
     let i = swaps.i;
     let j = swaps.j;
+    if i >= game.state.len() || j >= game.state[0].len() {
+        return (StatusCode::BAD_REQUEST, "the cell is off the board").into_response();
+    }
 
     game.flip(i, j);
+    session
+        .autoplay
+        .publish(Frame::Cells(render_cells(&game, &[(i, j)])));
+
+    TemplateResponse(CellTemplate {
+        i,
+        j,
+        cell: game.state[i][j],
+    })
+    .into_response()
+}
+
+#[derive(Template)]
+#[template(path = "rule.html")]
+struct RuleTemplate {
+    rule: String,
+    rule_error: Option<String>,
+    oob: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RuleOptions {
+    rule: String,
+}
+
+/// replaces the rule of the game, an invalid rule keeps the current one
+pub async fn rule(Query(options): Query<RuleOptions>, Owned(session): Owned) -> impl IntoResponse {
+    let mut game = session.game.lock().unwrap();
+
+    let rule_error = match options.rule.parse() {
+        Ok(rule) => {
+            game.rule = rule;
+            None
+        }
+        Err(err) => Some(err),
+    };
+
+    TemplateResponse(RuleTemplate {
+        rule: game.rule.to_string(),
+        rule_error,
+        oob: false,
+    })
+    .into_response()
+}
+
+#[derive(Template)]
+#[template(path = "boundary.html")]
+struct BoundaryTemplate {
+    boundary: String,
+    boundaries: Vec<String>,
+}
+
+fn boundaries() -> Vec<String> {
+    Boundary::value_variants()
+        .iter()
+        .map(Boundary::to_string)
+        .collect()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BoundaryOptions {
+    boundary: Boundary,
+}
+
+/// replaces the edges of the board, the cells stay where they are
+pub async fn boundary(
+    Query(options): Query<BoundaryOptions>,
+    Owned(session): Owned,
+) -> impl IntoResponse {
+    let mut game = session.game.lock().unwrap();
+    game.boundary = options.boundary;
+
+    TemplateResponse(BoundaryTemplate {
+        boundary: game.boundary.to_string(),
+        boundaries: boundaries(),
+    })
+}
+
+/// the grid, stats, rule and pattern controls after a pattern was loaded
+#[derive(Template)]
+#[template(path = "pattern_response.html")]
+struct PatternResponseTemplate {
+    state: Vec<Vec<conway::game::Cell>>,
+    cycles: usize,
+    alive_cells: usize,
+    rule: String,
+    rule_error: Option<String>,
+    patterns: Vec<&'static str>,
+    pattern_error: Option<String>,
+    oob: bool,
+}
+
+#[derive(Template)]
+#[template(path = "pattern.html")]
+struct PatternTemplate {
+    patterns: Vec<&'static str>,
+    pattern_error: Option<String>,
+    oob: bool,
+}
+
+fn library() -> Vec<&'static str> {
+    conway::pattern::LIBRARY
+        .iter()
+        .map(|(name, _)| *name)
+        .collect()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LibraryOptions {
+    name: String,
+    row: Option<usize>,
+    col: Option<usize>,
+}
 
-    // TODO: figure out why we cannot return a template even if the parent template is passing in
-    // the required variables. Error message is angry that i,j are not available...
-    match game.state[i][j].state {
-        Some(_) =>  format!("<div id=\"cell-{i}-{j}\" hx-get=\"/flip?i={i}&j={j}\" hx-target=\"#cell-{i}-{j}\" hx-swap=\"outerHTML\"
-                style=\"background-color: #000000; width: 20px; height: 20px; border: 1px solid #c4c4c4; cursor: pointer;\"></div>"),
-        None =>     format!("<div id=\"cell-{i}-{j}\" hx-get=\"/flip?i={i}&j={j}\" hx-target=\"#cell-{i}-{j}\" hx-swap=\"outerHTML\"style=\"background-color: #ffffff; width: 20px; height: 20px; border: 1px solid #c4c4c4; cursor: pointer;\"></div>")
+#[derive(Debug, Deserialize)]
+pub struct UploadOptions {
+    text: String,
+    row: Option<usize>,
+    col: Option<usize>,
+}
+
+/// loads a pattern of the bundled library, centred unless row and col are given
+pub async fn load_pattern(
+    Query(options): Query<LibraryOptions>,
+    Owned(session): Owned,
+) -> impl IntoResponse {
+    let loaded = conway::pattern::library(&options.name)
+        .ok_or_else(|| format!("there is no pattern named {}", options.name));
+    place_pattern(&session, loaded, options.row, options.col)
+}
+
+/// loads a pattern pasted as RLE or plaintext, at most as large as a board can get
+pub async fn upload_pattern(
+    Owned(session): Owned,
+    Form(options): Form<UploadOptions>,
+) -> impl IntoResponse {
+    let loaded = conway::pattern::parse_within(&options.text, session::MAX_SIZE);
+    place_pattern(&session, loaded, options.row, options.col)
+}
+
+fn place_pattern(
+    session: &Session,
+    loaded: Result<conway::pattern::Loaded, String>,
+    row: Option<usize>,
+    col: Option<usize>,
+) -> Response {
+    let loaded = match loaded {
+        Ok(loaded) => loaded,
+        // only the error is shown, the board stays as it is
+        Err(err) => {
+            return (
+                [("HX-Reswap", "none")],
+                TemplateResponse(PatternTemplate {
+                    patterns: library(),
+                    pattern_error: Some(err),
+                    oob: true,
+                }),
+            )
+                .into_response()
+        }
+    };
 
+    let mut game = session.game.lock().unwrap();
+    let (rows, cols) = (game.state.len(), game.state[0].len());
+    let (centre_row, centre_col) = loaded.centred(rows, cols);
+    // the rule first, the live cells of Wireworld are heads
+    if let Some(rule) = loaded.rule {
+        game.rule = rule;
     }
+    game.load_with_states(
+        &loaded.cells,
+        &loaded.states,
+        row.unwrap_or(centre_row),
+        col.unwrap_or(centre_col),
+    );
+    session.autoplay.publish(Frame::Grid(render_grid(&game)));
+
+    TemplateResponse(PatternResponseTemplate {
+        state: game.state.clone(),
+        cycles: game.cycles,
+        alive_cells: game.alive_cells,
+        rule: game.rule.to_string(),
+        rule_error: None,
+        patterns: library(),
+        pattern_error: None,
+        oob: true,
+    })
+    .into_response()
+}
+
+/// downloads the board as RLE
+pub async fn export(Owned(session): Owned) -> impl IntoResponse {
+    let game = session.game.lock().unwrap();
+
+    (
+        [
+            (header::CONTENT_TYPE, "text/plain; charset=utf-8"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"board.rle\"",
+            ),
+        ],
+        conway::pattern::to_rle(&game.live_cells(), &game.other_cells(), game.rule),
+    )
+}
+
+#[derive(Template)]
+#[template(path = "playback.html")]
+struct PlaybackTemplate {
+    playing: bool,
+    delay: u64,
+    min_delay: u64,
+    max_delay: u64,
+}
+
+fn render_playback(autoplay: &Autoplay) -> String {
+    let template = PlaybackTemplate {
+        playing: autoplay.is_playing(),
+        delay: autoplay.delay(),
+        min_delay: autoplay::MIN_DELAY,
+        max_delay: autoplay::MAX_DELAY,
+    };
+    template
+        .render()
+        .unwrap_or_else(|err| format!("Unable to parse template. Error: {err}"))
+}
+
+/// renders the controls for the browser that asked and every other connected one
+fn publish_playback(autoplay: &Autoplay) -> Html<String> {
+    let playback = render_playback(autoplay);
+    autoplay.publish(Frame::Playback(playback.clone()));
+    Html(playback)
+}
+
+pub async fn play(Owned(session): Owned) -> impl IntoResponse {
+    session.autoplay.set_playing(true);
+    publish_playback(&session.autoplay)
+}
+
+pub async fn pause(Owned(session): Owned) -> impl IntoResponse {
+    session.autoplay.set_playing(false);
+    publish_playback(&session.autoplay)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SpeedOptions {
+    /// milliseconds between two generations
+    delay: u64,
+}
+
+pub async fn speed(
+    Query(options): Query<SpeedOptions>,
+    Owned(session): Owned,
+) -> impl IntoResponse {
+    session.autoplay.set_delay(options.delay);
+    publish_playback(&session.autoplay)
+}
+
+/// streams the game of the visitor
+pub async fn sse(Owned(session): Owned) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    stream(&session)
+}
+
+/// streams the game of someone else
+pub async fn watch_sse(
+    Path(id): Path<String>,
+    sessions: Extension<Arc<Sessions>>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, StatusCode> {
+    let session = sessions.get(&id).ok_or(StatusCode::NOT_FOUND)?;
+    Ok(stream(&session))
+}
+
+/// streams the grid on every change of the game and the controls on
+/// every change of the playback, starting with the current ones
+fn stream(session: &Session) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    // subscribed first, a generation in between is sent twice rather than never
+    let frames = session.autoplay.subscribe();
+    let current = {
+        let game = session.game.lock().unwrap();
+        [
+            Frame::Grid(render_grid(&game)),
+            Frame::Playback(render_playback(&session.autoplay)),
+        ]
+    };
+
+    // a browser falling behind missed some changed cells, it catches up
+    // with the whole grid
+    let game = session.game.clone();
+    let frames = BroadcastStream::new(frames).map(move |frame| {
+        frame.unwrap_or_else(|_| Frame::Grid(render_grid(&game.lock().unwrap())))
+    });
+
+    let stream = tokio_stream::iter(current).chain(frames).map(|frame| {
+        let event = match frame {
+            Frame::Grid(html) => Event::default().event("message").data(html),
+            Frame::Cells(html) => Event::default().event("cells").data(html),
+            Frame::Playback(html) => Event::default().event("playback").data(html),
+        };
+        Ok(event)
+    });
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
 }
 
 struct TemplateResponse<T>(pub T);
@@ -1 +1,3 @@
+pub mod autoplay;
 pub mod handler;
+pub mod session;
@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use axum::{
+    async_trait,
+    extract::{Extension, FromRequestParts},
+    http::{request::Parts, StatusCode},
+};
+use axum_extra::TypedHeader;
+use headers::Cookie;
+use rand::{distributions::Alphanumeric, Rng};
+use tokio::task::AbortHandle;
+
+use crate::conway::{boundary::Boundary, game::Game, history::History, pattern, rule::Rule};
+use crate::http::autoplay::{self, Autoplay};
+
+/// cookie holding the id and the token of the game of the visitor
+pub const COOKIE: &str = "game_session";
+
+/// largest board a game may be created with or grow to, on either side
+pub const MAX_SIZE: usize = 128;
+
+/// games kept at once, new ones are refused beyond it
+pub const MAX_SESSIONS: usize = 1000;
+
+/// bytes the changes a game can be stepped back through may take
+/// up, kept low as there are many games
+const HISTORY_BYTES: usize = 512 << 10;
+
+/// how often idle sessions are looked for
+const REAP_INTERVAL: Duration = Duration::from_secs(60);
+
+const ID_LENGTH: usize = 12;
+const TOKEN_LENGTH: usize = 24;
+
+/// Start is what every new game starts with
+#[derive(Debug, Clone)]
+pub enum Start {
+    Pattern(pattern::Loaded),
+    /// a soup of its own for every game unless the seed is given
+    Soup(pattern::Soup),
+}
+
+/// Defaults of a new game, given on the command line
+#[derive(Debug, Clone)]
+pub struct Defaults {
+    pub rows: usize,
+    pub cols: usize,
+    /// rule of every new game, else the rule of the pattern
+    pub rule: Option<Rule>,
+    pub boundary: Boundary,
+    pub start: Start,
+    /// top left corner of the pattern, centred by default
+    pub row: Option<usize>,
+    pub col: Option<usize>,
+    /// milliseconds between two generations while playing
+    pub delay: u64,
+}
+
+/// Session is the game of a single visitor. Anyone knowing the id may
+/// watch it, only the visitor holding the token may play it.
+pub struct Session {
+    pub id: String,
+    token: String,
+    pub game: Arc<Mutex<Game>>,
+    pub autoplay: Arc<Autoplay>,
+    last_seen: Mutex<Instant>,
+    ticker: AbortHandle,
+}
+
+impl Session {
+    /// value of the cookie which identifies the visitor as the player
+    pub fn cookie(&self) -> String {
+        format!(
+            "{}={}.{}; Path=/; HttpOnly; SameSite=Lax",
+            COOKIE, self.id, self.token
+        )
+    }
+
+    pub fn touch(&self) {
+        *self.last_seen.lock().unwrap() = Instant::now();
+    }
+
+    /// whether nobody played the game for longer than idle nor is watching it
+    fn is_idle(&self, idle: Duration) -> bool {
+        self.last_seen.lock().unwrap().elapsed() > idle && self.autoplay.watchers() == 0
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.ticker.abort();
+    }
+}
+
+/// Sessions holds the game of every visitor by id
+pub struct Sessions {
+    sessions: RwLock<HashMap<String, Arc<Session>>>,
+    pub defaults: Defaults,
+    /// time after which a game nobody plays nor watches is dropped
+    idle: Duration,
+}
+
+impl Sessions {
+    pub fn new(defaults: Defaults, idle: Duration) -> Self {
+        Sessions {
+            sessions: RwLock::new(HashMap::new()),
+            defaults,
+            idle,
+        }
+    }
+
+    /// creates a game of rows x cols, at most MAX_SIZE on either side,
+    /// holding the default pattern. None is returned if there are
+    /// MAX_SESSIONS games which are not idle.
+    pub fn create(&self, rows: usize, cols: usize) -> Option<Arc<Session>> {
+        if self.sessions.read().unwrap().len() >= MAX_SESSIONS
+            && (self.remove_idle() == 0 || self.sessions.read().unwrap().len() >= MAX_SESSIONS)
+        {
+            return None;
+        }
+
+        let rows = rows.clamp(1, MAX_SIZE);
+        let cols = cols.clamp(1, MAX_SIZE);
+        let defaults = &self.defaults;
+
+        let loaded = match &defaults.start {
+            Start::Pattern(loaded) => loaded.clone(),
+            Start::Soup(soup) => pattern::Loaded {
+                name: None,
+                rule: None,
+                cells: soup.generate(rows, cols).1,
+                states: Vec::new(),
+            },
+        };
+        let mut game = Game::with_rule(
+            rows,
+            cols,
+            defaults.rule.or(loaded.rule).unwrap_or_default(),
+        );
+        game.boundary = defaults.boundary;
+        game.max_size = MAX_SIZE;
+        game.history = History::new(HISTORY_BYTES);
+        let (centre_row, centre_col) = loaded.centred(rows, cols);
+        game.load_with_states(
+            &loaded.cells,
+            &loaded.states,
+            defaults.row.unwrap_or(centre_row),
+            defaults.col.unwrap_or(centre_col),
+        );
+
+        let game = Arc::new(Mutex::new(game));
+        let autoplay = Arc::new(Autoplay::new(defaults.delay));
+        let ticker = tokio::spawn(autoplay::run(game.clone(), autoplay.clone()));
+
+        let session = Arc::new(Session {
+            id: random(ID_LENGTH),
+            token: random(TOKEN_LENGTH),
+            game,
+            autoplay,
+            last_seen: Mutex::new(Instant::now()),
+            ticker: ticker.abort_handle(),
+        });
+        self.sessions
+            .write()
+            .unwrap()
+            .insert(session.id.clone(), session.clone());
+        Some(session)
+    }
+
+    /// drops the game, e.g. as its player started a new one
+    pub fn remove(&self, id: &str) {
+        self.sessions.write().unwrap().remove(id);
+    }
+
+    pub fn get(&self, id: &str) -> Option<Arc<Session>> {
+        self.sessions.read().unwrap().get(id).cloned()
+    }
+
+    /// the session of the cookie, if its token matches
+    fn owned(&self, cookie: &str) -> Option<Arc<Session>> {
+        let (id, token) = cookie.split_once('.')?;
+        self.get(id).filter(|session| session.token == token)
+    }
+
+    /// drops the sessions idle for too long, returning how many were dropped
+    pub fn remove_idle(&self) -> usize {
+        let mut sessions = self.sessions.write().unwrap();
+        let before = sessions.len();
+        sessions.retain(|_, session| !session.is_idle(self.idle));
+        before - sessions.len()
+    }
+}
+
+/// drops idle sessions for as long as the server runs
+pub async fn reap(sessions: Arc<Sessions>) {
+    let mut interval = tokio::time::interval(REAP_INTERVAL);
+    loop {
+        interval.tick().await;
+        let removed = sessions.remove_idle();
+        if removed > 0 {
+            tracing::info!("dropped {} idle games", removed);
+        }
+    }
+}
+
+fn random(length: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(length)
+        .map(char::from)
+        .collect()
+}
+
+/// Owned extracts the session of the visitor from the cookie, requests
+/// without one, such as those of someone watching, are unauthorized
+pub struct Owned(pub Arc<Session>);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Owned
+where
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        const UNAUTHORIZED: (StatusCode, &str) =
+            (StatusCode::UNAUTHORIZED, "start a game of your own at /");
+
+        let Extension(sessions) = Extension::<Arc<Sessions>>::from_request_parts(parts, state)
+            .await
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "sessions are missing"))?;
+        let TypedHeader(cookie) = TypedHeader::<Cookie>::from_request_parts(parts, state)
+            .await
+            .map_err(|_| UNAUTHORIZED)?;
+
+        let session = cookie
+            .get(COOKIE)
+            .and_then(|value| sessions.owned(value))
+            .ok_or(UNAUTHORIZED)?;
+        session.touch();
+        Ok(Owned(session))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn sessions_are_capped() {
+        let sessions = Sessions::new(
+            Defaults {
+                rows: 8,
+                cols: 8,
+                rule: None,
+                boundary: Boundary::Torus,
+                start: Start::Pattern(pattern::library("glider").unwrap()),
+                row: None,
+                col: None,
+                delay: 200,
+            },
+            Duration::from_secs(60),
+        );
+
+        let first = sessions.create(8, 8).unwrap();
+        for _ in 1..MAX_SESSIONS {
+            assert!(sessions.create(8, 8).is_some());
+        }
+        assert!(sessions.create(8, 8).is_none());
+
+        // a player starting over frees their old game
+        sessions.remove(&first.id);
+        assert!(sessions.get(&first.id).is_none());
+        assert!(sessions.create(8, 8).is_some());
+    }
+}
@@ -1,19 +1,17 @@
-use askama::Template;
-use std::sync::{Arc, Mutex};
-
-use axum::{
-    extract::Extension,
-    http::StatusCode,
-    response::{Html, IntoResponse, Response},
-    routing::get,
-    Router,
-};
+use std::sync::Arc;
+
+use axum::{extract::Extension, routing::get, Router};
 use clap::{Parser, Subcommand};
+
+use crate::conway::pattern;
 use tracing::info;
 use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt};
 
+mod analyse;
 mod conway;
+mod export;
 mod http;
+mod tui;
 
 #[derive(Debug, Parser)]
 struct Args {
@@ -21,15 +19,227 @@ struct Args {
     command: Command,
 }
 
+/// SoupArgs describe the random soup standing in for the pattern soup
+#[derive(Debug, Clone, Copy, clap::Args)]
+struct SoupArgs {
+    /// seed of a random soup, a random one by default, reported to run it again
+    #[arg(long)]
+    seed: Option<u64>,
+    /// chance of a cell of a random soup to be alive
+    #[arg(long, default_value_t = pattern::DEFAULT_DENSITY)]
+    density: f64,
+    /// rows and columns a random soup covers, such as 16x16, the whole board by default
+    #[arg(long, value_parser = pattern::parse_region)]
+    region: Option<(usize, usize)>,
+}
+
+impl SoupArgs {
+    fn soup(&self) -> pattern::Soup {
+        pattern::Soup {
+            density: self.density,
+            seed: self.seed,
+            region: self.region,
+        }
+    }
+}
+
+/// PaletteArgs are the colours of an export, of each state of a cell and
+/// of live cells by age
+#[derive(Debug, Clone, Copy, clap::Args)]
+struct PaletteArgs {
+    /// colour of dead cells, such as #ffffff or hsl(0, 0%, 100%)
+    #[arg(long, default_value = "hsl(0, 0%, 100%)")]
+    dead: conway::palette::Colour,
+    /// colour of a cell just born
+    #[arg(long, default_value = "hsl(210, 100%, 0%)")]
+    young: conway::palette::Colour,
+    /// colour of a cell alive for ages generations or longer
+    #[arg(long, default_value = "hsl(210, 100%, 100%)")]
+    old: conway::palette::Colour,
+    /// generations a cell takes to fade from young to old
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u8).range(..=export::MAX_AGES as i64))]
+    ages: u8,
+    /// colour of a cell of a Generations rule just starting to die
+    #[arg(long, default_value = "hsl(0, 100%, 50%)")]
+    dying: conway::palette::Colour,
+    /// colour of the head of an electron of Wireworld
+    #[arg(long, default_value = "hsl(210, 100%, 50%)")]
+    head: conway::palette::Colour,
+    /// colour of the tail of an electron of Wireworld
+    #[arg(long, default_value = "hsl(0, 100%, 50%)")]
+    tail: conway::palette::Colour,
+    /// colour of the wires of Wireworld
+    #[arg(long, default_value = "hsl(45, 100%, 50%)")]
+    conductor: conway::palette::Colour,
+}
+
+impl PaletteArgs {
+    fn palette(&self) -> conway::palette::Palette {
+        conway::palette::Palette {
+            dead: self.dead,
+            young: self.young,
+            old: self.old,
+            ages: self.ages as usize,
+            dying: self.dying,
+            head: self.head,
+            tail: self.tail,
+            conductor: self.conductor,
+        }
+    }
+}
+
 #[derive(Subcommand, Debug)]
 enum Command {
+    /// plays the game in the terminal, where cells may be edited
     Run {
+        /// milliseconds between two frames while playing
         #[arg(short, long, default_value_t = 200)]
         delay: u64,
+        /// engine advancing the universe, dense is a board the size of the terminal
+        #[arg(short, long, value_enum, default_value_t = conway::engine::Kind::Dense)]
+        engine: conway::engine::Kind,
+        /// edges of the dense board, a torus by default. The other engines are infinite.
+        #[arg(short, long, value_enum)]
+        boundary: Option<conway::boundary::Boundary>,
+        /// generations advanced between two frames
+        #[arg(short, long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..=tui::MAX_STEP))]
+        step: u64,
+        /// rule such as B3/S23, B36/S23 or B2/S/C3, or a name such as highlife or
+        /// wireworld. Defaults to the rule of the pattern, else B3/S23.
+        #[arg(short, long)]
+        rule: Option<conway::rule::Rule>,
+        /// bundled pattern such as glider or gosper_glider_gun, a .rle or .cells file,
+        /// or soup for a random one
+        #[arg(long, default_value = "copperhead")]
+        pattern: String,
+        #[command(flatten)]
+        soup: SoupArgs,
+        /// row of the top left corner of the pattern, centred by default
+        #[arg(long)]
+        row: Option<usize>,
+        /// column of the top left corner of the pattern, centred by default
+        #[arg(long)]
+        col: Option<usize>,
     },
+    /// runs a pattern without drawing it and reports every generation,
+    /// what the pattern settled into and how far it spread
+    Analyse {
+        /// generations to run
+        #[arg(short, long, default_value_t = 1000, value_parser = clap::value_parser!(u64).range(..=conway::analysis::MAX_GENERATIONS))]
+        generations: u64,
+        /// engine advancing the universe, sparse lets spaceships fly off
+        #[arg(short, long, value_enum, default_value_t = conway::engine::Kind::Sparse)]
+        engine: conway::engine::Kind,
+        /// edges of the dense board, a torus by default. The other engines are infinite.
+        #[arg(short, long, value_enum)]
+        boundary: Option<conway::boundary::Boundary>,
+        /// rule such as B3/S23, B36/S23 or B2/S/C3, or a name such as highlife or
+        /// wireworld. Defaults to the rule of the pattern, else B3/S23.
+        #[arg(short, long)]
+        rule: Option<conway::rule::Rule>,
+        /// bundled pattern such as glider or gosper_glider_gun, a .rle or .cells file,
+        /// or soup for a random one
+        #[arg(long, default_value = pattern::SOUP)]
+        pattern: String,
+        #[command(flatten)]
+        soup: SoupArgs,
+        /// rows of the dense board
+        #[arg(long, default_value_t = 64, value_parser = board_size(pattern::MAX_SIZE))]
+        rows: usize,
+        /// columns of the dense board
+        #[arg(long, default_value_t = 64, value_parser = board_size(pattern::MAX_SIZE))]
+        cols: usize,
+        /// row of the top left corner of the pattern, centred by default
+        #[arg(long)]
+        row: Option<usize>,
+        /// column of the top left corner of the pattern, centred by default
+        #[arg(long)]
+        col: Option<usize>,
+        #[arg(short, long, value_enum, default_value_t = analyse::Format::Csv)]
+        format: analyse::Format,
+    },
+    /// renders generations of the dense board to an animated GIF or to
+    /// numbered PNG frames
+    Export {
+        /// a .gif for an animation, a .png for a file per frame such as run-042.png
+        #[arg(short, long)]
+        output: std::path::PathBuf,
+        /// generations to run
+        #[arg(short, long, default_value_t = 100, value_parser = clap::value_parser!(u64).range(..=export::MAX_GENERATIONS))]
+        generations: u64,
+        /// generations advanced between two frames
+        #[arg(short, long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..=export::MAX_GENERATIONS))]
+        step: u64,
+        /// side of a cell in pixels
+        #[arg(long, default_value_t = 8, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..=export::MAX_SCALE as u64))]
+        scale: usize,
+        /// milliseconds a frame of a GIF is shown
+        #[arg(short, long, default_value_t = 100)]
+        delay: u64,
+        #[command(flatten)]
+        palette: Box<PaletteArgs>,
+        /// edges of the board, a torus by default. An infinite board grows
+        /// beyond the frames, which show where it started.
+        #[arg(short, long, value_enum, default_value_t = conway::boundary::Boundary::Torus)]
+        boundary: conway::boundary::Boundary,
+        /// rule such as B3/S23, B36/S23 or B2/S/C3, or a name such as highlife or
+        /// wireworld. Defaults to the rule of the pattern, else B3/S23.
+        #[arg(short, long)]
+        rule: Option<conway::rule::Rule>,
+        /// bundled pattern such as glider or gosper_glider_gun, a .rle or .cells file,
+        /// or soup for a random one
+        #[arg(long, default_value = "copperhead")]
+        pattern: String,
+        #[command(flatten)]
+        soup: SoupArgs,
+        /// rows of the board
+        #[arg(long, default_value_t = 64, value_parser = board_size(pattern::MAX_SIZE))]
+        rows: usize,
+        /// columns of the board
+        #[arg(long, default_value_t = 64, value_parser = board_size(pattern::MAX_SIZE))]
+        cols: usize,
+        /// row of the top left corner of the pattern, centred by default
+        #[arg(long)]
+        row: Option<usize>,
+        /// column of the top left corner of the pattern, centred by default
+        #[arg(long)]
+        col: Option<usize>,
+    },
+    /// serves the game in the browser, every visitor plays a game of their own
     Web {
         #[arg(short, long, default_value_t = 3000)]
         port: u64,
+        /// milliseconds between two generations while playing
+        #[arg(short, long, default_value_t = 200)]
+        delay: u64,
+        /// rule such as B3/S23, B36/S23 or B2/S/C3, or a name such as highlife or
+        /// wireworld. Defaults to the rule of the pattern, else B3/S23.
+        #[arg(short, long)]
+        rule: Option<conway::rule::Rule>,
+        /// edges of every new game
+        #[arg(short, long, value_enum, default_value_t = conway::boundary::Boundary::Torus)]
+        boundary: conway::boundary::Boundary,
+        /// bundled pattern such as glider or gosper_glider_gun, a .rle or .cells file,
+        /// or soup for a random one
+        #[arg(long, default_value = "copperhead")]
+        pattern: String,
+        #[command(flatten)]
+        soup: SoupArgs,
+        /// row of the top left corner of the pattern, centred by default
+        #[arg(long)]
+        row: Option<usize>,
+        /// column of the top left corner of the pattern, centred by default
+        #[arg(long)]
+        col: Option<usize>,
+        /// rows of a new game unless the visitor picks another size
+        #[arg(long, default_value_t = 32, value_parser = board_size(http::session::MAX_SIZE))]
+        rows: usize,
+        /// columns of a new game unless the visitor picks another size
+        #[arg(long, default_value_t = 32, value_parser = board_size(http::session::MAX_SIZE))]
+        cols: usize,
+        /// seconds after which a game nobody plays nor watches is dropped
+        #[arg(long, default_value_t = 1800)]
+        idle: u64,
     },
 }
 
@@ -38,22 +248,114 @@ async fn main() {
     let args = Args::parse();
 
     match args.command {
-        Command::Run { delay } => {
-            let (width, height) = term_size::dimensions_stdout().unwrap_or((32, 64));
-
-            let mut game = conway::game::Game::empty(height, width);
-
-            game.repaint();
-            std::thread::sleep(std::time::Duration::from_millis(delay));
-
-            loop {
-                game = game.next_cycle();
+        Command::Run {
+            delay,
+            engine,
+            boundary,
+            step,
+            rule,
+            pattern,
+            soup,
+            row,
+            col,
+        } => {
+            let (rows, cols) = or_exit(tui::board_size());
+            let (pattern, seed) = start(&pattern, soup, rows, cols);
+            let options = tui::Options {
+                kind: engine,
+                boundary,
+                rule: rule.or(pattern.rule).unwrap_or_default(),
+                delay,
+                step,
+            };
+            or_exit(tui::run(options, &pattern, row, col));
+            if let Some(seed) = seed {
+                println!("the random soup had seed {}", seed);
+            }
+        }
+        Command::Analyse {
+            generations,
+            engine,
+            boundary,
+            rule,
+            pattern,
+            soup,
+            rows,
+            cols,
+            row,
+            col,
+            format,
+        } => {
+            let (loaded, seed) = start(&pattern, soup, rows, cols);
+            let options = analyse::Options {
+                kind: engine,
+                boundary,
+                rule: rule.or(loaded.rule).unwrap_or_default(),
+                rows,
+                cols,
+                generations,
+                format,
+            };
+            print!(
+                "{}",
+                or_exit(analyse::run(options, &pattern, seed, &loaded, row, col))
+            );
+        }
+        Command::Export {
+            output,
+            generations,
+            step,
+            scale,
+            delay,
+            palette,
+            boundary,
+            rule,
+            pattern,
+            soup,
+            rows,
+            cols,
+            row,
+            col,
+        } => {
+            let (loaded, seed) = start(&pattern, soup, rows, cols);
+            let mut game =
+                conway::game::Game::with_rule(rows, cols, rule.or(loaded.rule).unwrap_or_default());
+            game.boundary = boundary;
+            // nothing is undone, the history would only take up memory
+            game.history = conway::history::History::disabled();
+            let (centre_row, centre_col) = loaded.centred(rows, cols);
+            game.load_with_states(
+                &loaded.cells,
+                &loaded.states,
+                row.unwrap_or(centre_row),
+                col.unwrap_or(centre_col),
+            );
 
-                game.repaint();
-                std::thread::sleep(std::time::Duration::from_millis(delay));
+            let options = export::Options {
+                generations,
+                step,
+                scale,
+                delay,
+                palette: palette.palette(),
+            };
+            println!("{}", or_exit(export::run(options, &mut game, &output)));
+            if let Some(seed) = seed {
+                println!("the random soup had seed {}", seed);
             }
         }
-        Command::Web { port } => {
+        Command::Web {
+            port,
+            delay,
+            rule,
+            boundary,
+            pattern,
+            soup,
+            row,
+            col,
+            rows,
+            cols,
+            idle,
+        } => {
             tracing_subscriber::registry()
                 .with(
                     tracing_subscriber::EnvFilter::try_from_default_env()
@@ -62,16 +364,52 @@ async fn main() {
                 .with(fmt::layer())
                 .init();
 
-            let game = conway::game::Game::initialise_random(32, 32);
-
-            let state = Arc::new(Mutex::new(game));
+            let defaults = http::session::Defaults {
+                rows,
+                cols,
+                rule,
+                boundary,
+                start: if pattern == pattern::SOUP {
+                    http::session::Start::Soup(soup.soup())
+                } else {
+                    http::session::Start::Pattern(or_exit(pattern::load(&pattern)))
+                },
+                row,
+                col,
+                delay,
+            };
+            let sessions = Arc::new(http::session::Sessions::new(
+                defaults,
+                std::time::Duration::from_secs(idle),
+            ));
+            tokio::spawn(http::session::reap(sessions.clone()));
 
             let router = Router::new()
                 .route("/", get(http::handler::index))
+                .route(
+                    "/new",
+                    get(http::handler::new_game_form).post(http::handler::new_game),
+                )
+                .route("/watch/:id", get(http::handler::watch))
+                .route("/watch/:id/sse", get(http::handler::watch_sse))
                 .route("/next", get(http::handler::next_cycle))
+                .route("/prev", get(http::handler::prev))
+                .route("/redo", get(http::handler::redo))
+                .route("/rewind", get(http::handler::rewind))
                 .route("/reset", get(http::handler::reset))
                 .route("/flip", get(http::handler::flip))
-                .layer(Extension(state));
+                .route("/rule", get(http::handler::rule))
+                .route("/boundary", get(http::handler::boundary))
+                .route(
+                    "/pattern",
+                    get(http::handler::load_pattern).post(http::handler::upload_pattern),
+                )
+                .route("/export", get(http::handler::export))
+                .route("/play", get(http::handler::play))
+                .route("/pause", get(http::handler::pause))
+                .route("/speed", get(http::handler::speed))
+                .route("/sse", get(http::handler::sse))
+                .layer(Extension(sessions));
 
             let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{port}"))
                 .await
@@ -82,3 +420,33 @@ async fn main() {
         }
     }
 }
+
+/// the pattern named on the command line for a board of rows x cols, soup
+/// being a random one, along with the seed of the soup
+fn start(name: &str, soup: SoupArgs, rows: usize, cols: usize) -> (pattern::Loaded, Option<u64>) {
+    if name != pattern::SOUP {
+        return (or_exit(pattern::load(name)), None);
+    }
+
+    let (seed, cells) = soup.soup().generate(rows, cols);
+    let loaded = pattern::Loaded {
+        name: Some(pattern::SOUP.to_string()),
+        rule: None,
+        cells,
+        states: Vec::new(),
+    };
+    (loaded, Some(seed))
+}
+
+/// parses the rows or columns of a board, from one up to max
+fn board_size(max: usize) -> clap::builder::RangedU64ValueParser<usize> {
+    clap::builder::RangedU64ValueParser::new().range(1..=max as u64)
+}
+
+/// prints the error of invalid arguments and exits
+fn or_exit<T>(result: Result<T, String>) -> T {
+    result.unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(2);
+    })
+}
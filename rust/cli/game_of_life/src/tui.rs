@@ -0,0 +1,478 @@
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    queue,
+    style::{Color, Print, SetForegroundColor},
+    terminal::{self, ClearType},
+};
+
+use crate::conway::boundary::Boundary;
+use crate::conway::engine::{self, Engine, Kind};
+use crate::conway::game::{Cell, State};
+use crate::conway::palette::Palette;
+use crate::conway::pattern;
+use crate::conway::rule::Rule;
+
+/// fastest and slowest speed, in milliseconds per frame
+const MIN_DELAY: u64 = 10;
+const MAX_DELAY: u64 = 5000;
+
+const ALIVE: char = '█';
+const HELP: &str = "arrows/hjkl move, space flip, enter stamp, [ ] pattern, p play, s step, u undo, r redo, <n>g rewind, +/- speed, c clear, q quit";
+
+/// most generations advanced between two frames, Hashlife skips them in
+/// a few jumps while the other engines step through every one
+pub const MAX_STEP: u64 = 1 << 30;
+
+/// Options of the terminal UI, given on the command line
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+    pub kind: Kind,
+    /// edges of the dense board, the default of the engine if None
+    pub boundary: Option<Boundary>,
+    pub rule: Rule,
+    /// milliseconds between two frames while playing
+    pub delay: u64,
+    /// generations advanced per frame
+    pub step: u64,
+}
+
+/// App is the state of the terminal UI. The board is a window onto the
+/// universe which follows the cursor.
+struct App {
+    options: Options,
+    engine: Box<dyn Engine>,
+    /// size of the board, the last line of the terminal shows the status
+    rows: u16,
+    cols: u16,
+    /// the cell of the universe in the top left corner of the board
+    top: i64,
+    left: i64,
+    cursor: (i64, i64),
+    playing: bool,
+    /// index of the bundled pattern enter stamps
+    selected: usize,
+    /// what the board currently shows, None where it has to be drawn
+    screen: Vec<Option<State>>,
+    /// generation typed so far, g rewinds to it
+    count: Option<u64>,
+    quit: bool,
+}
+
+/// Terminal switches to raw mode on an alternate screen and back when
+/// dropped, also when the UI panics
+struct Terminal;
+
+impl Terminal {
+    fn enter() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        let mut out = io::stdout();
+        queue!(
+            out,
+            terminal::EnterAlternateScreen,
+            terminal::Clear(ClearType::All),
+            SetForegroundColor(Color::Cyan)
+        )?;
+        out.flush()?;
+        Ok(Terminal)
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let mut out = io::stdout();
+        let _ = queue!(
+            out,
+            SetForegroundColor(Color::Reset),
+            cursor::Show,
+            terminal::LeaveAlternateScreen
+        );
+        let _ = out.flush();
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// size of the board, the terminal but for its last line which shows the status
+pub fn board_size() -> Result<(usize, usize), String> {
+    let (cols, rows) = terminal::size().map_err(|err| err.to_string())?;
+    Ok((rows.saturating_sub(1).max(1) as usize, cols.max(1) as usize))
+}
+
+/// runs the terminal UI until quit, starting with the pattern placed with its
+/// top left corner at (row, col), centred on the board by default
+pub fn run(
+    options: Options,
+    start: &pattern::Loaded,
+    row: Option<usize>,
+    col: Option<usize>,
+) -> Result<(), String> {
+    let (rows, cols) = board_size()?;
+    let mut app = App::new(options, rows as u16, cols as u16)?;
+
+    let (centre_row, centre_col) = start.centred(app.rows as usize, app.cols as usize);
+    app.stamp(
+        start,
+        row.unwrap_or(centre_row) as i64,
+        col.unwrap_or(centre_col) as i64,
+    );
+
+    let _terminal = Terminal::enter().map_err(|err| err.to_string())?;
+    app.run().map_err(|err| err.to_string())
+}
+
+impl App {
+    fn new(options: Options, rows: u16, cols: u16) -> Result<Self, String> {
+        Ok(App {
+            options,
+            engine: engine::new(
+                options.kind,
+                rows as usize,
+                cols as usize,
+                options.rule,
+                options.boundary,
+            )?,
+            rows,
+            cols,
+            top: 0,
+            left: 0,
+            cursor: (rows as i64 / 2, cols as i64 / 2),
+            playing: true,
+            selected: 0,
+            screen: vec![None; rows as usize * cols as usize],
+            count: None,
+            quit: false,
+        })
+    }
+
+    fn run(&mut self) -> io::Result<()> {
+        let mut out = io::stdout();
+        let mut last_frame = Instant::now();
+
+        while !self.quit {
+            self.draw(&mut out)?;
+
+            let delay = Duration::from_millis(self.options.delay);
+            let timeout = if self.playing {
+                delay.saturating_sub(last_frame.elapsed())
+            } else {
+                Duration::from_secs(60)
+            };
+            if event::poll(timeout)? {
+                match event::read()? {
+                    Event::Key(key) if key.kind == KeyEventKind::Press => self.handle(key),
+                    Event::Resize(cols, rows) => self.resize(rows, cols),
+                    _ => {}
+                }
+            }
+
+            if self.playing && last_frame.elapsed() >= delay {
+                self.engine.step_many(self.options.step);
+                last_frame = Instant::now();
+            }
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, key: KeyEvent) {
+        let count = self.count.take();
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => self.quit = true,
+            KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+            KeyCode::Up | KeyCode::Char('k') => self.move_cursor(-1, 0),
+            KeyCode::Down | KeyCode::Char('j') => self.move_cursor(1, 0),
+            KeyCode::Left | KeyCode::Char('h') => self.move_cursor(0, -1),
+            KeyCode::Right | KeyCode::Char('l') => self.move_cursor(0, 1),
+            KeyCode::Char(' ') => {
+                let (row, col) = self.cursor;
+                let state = self.options.rule.flipped(self.engine.state(row, col));
+                self.engine.set_state(row, col, state);
+            }
+            KeyCode::Enter => {
+                let (name, _) = pattern::LIBRARY[self.selected];
+                if let Some(loaded) = pattern::library(name) {
+                    let (row, col) = self.cursor;
+                    self.stamp(&loaded, row, col);
+                }
+            }
+            KeyCode::Char('[') => {
+                self.selected =
+                    (self.selected + pattern::LIBRARY.len() - 1) % pattern::LIBRARY.len()
+            }
+            KeyCode::Char(']') => self.selected = (self.selected + 1) % pattern::LIBRARY.len(),
+            KeyCode::Char('p') => self.playing = !self.playing,
+            KeyCode::Char('s') | KeyCode::Char('.') if !self.playing => {
+                self.engine.step_many(self.options.step)
+            }
+            KeyCode::Char('u') | KeyCode::Char(',') => {
+                self.playing = false;
+                self.engine.undo();
+            }
+            KeyCode::Char('r') => {
+                self.playing = false;
+                self.engine.redo();
+            }
+            KeyCode::Char(digit @ '0'..='9') => {
+                let digit = digit.to_digit(10).unwrap_or(0) as u64;
+                self.count = Some(count.unwrap_or(0).saturating_mul(10).saturating_add(digit));
+            }
+            // as far back as the history goes unless a generation was typed
+            KeyCode::Char('g') => {
+                self.playing = false;
+                self.engine.rewind(count.unwrap_or(0));
+            }
+            KeyCode::Char('+') | KeyCode::Char('=') => {
+                self.options.delay = (self.options.delay / 2).max(MIN_DELAY)
+            }
+            KeyCode::Char('-') => self.options.delay = (self.options.delay * 2).min(MAX_DELAY),
+            KeyCode::Char('c') => {
+                if let Ok(engine) = engine::new(
+                    self.options.kind,
+                    self.rows as usize,
+                    self.cols as usize,
+                    self.options.rule,
+                    self.options.boundary,
+                ) {
+                    self.engine = engine;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// moves the cursor, scrolling the board when it leaves it
+    fn move_cursor(&mut self, rows: i64, cols: i64) {
+        self.cursor = (self.cursor.0 + rows, self.cursor.1 + cols);
+        let (row, col) = self.cursor;
+
+        if row < self.top {
+            self.top = row;
+        } else if row >= self.top + self.rows as i64 {
+            self.top = row - self.rows as i64 + 1;
+        }
+        if col < self.left {
+            self.left = col;
+        } else if col >= self.left + self.cols as i64 {
+            self.left = col - self.cols as i64 + 1;
+        }
+    }
+
+    fn stamp(&mut self, loaded: &pattern::Loaded, row: i64, col: i64) {
+        for (i, j, state) in &loaded.states {
+            self.engine
+                .set_state(row + *i as i64, col + *j as i64, *state);
+        }
+        for (i, j) in &loaded.cells {
+            self.engine.set(row + *i as i64, col + *j as i64, true);
+        }
+    }
+
+    fn resize(&mut self, rows: u16, cols: u16) {
+        self.rows = rows.saturating_sub(1).max(1);
+        self.cols = cols.max(1);
+        self.screen = vec![None; self.rows as usize * self.cols as usize];
+        self.move_cursor(0, 0);
+
+        let mut out = io::stdout();
+        let _ = queue!(out, terminal::Clear(ClearType::All));
+    }
+
+    /// draws the cells which differ from the screen and the status line
+    fn draw(&mut self, out: &mut impl Write) -> io::Result<()> {
+        queue!(out, cursor::Hide)?;
+
+        for y in 0..self.rows {
+            for x in 0..self.cols {
+                let state = self.engine.state(self.top + y as i64, self.left + x as i64);
+                let shown = &mut self.screen[y as usize * self.cols as usize + x as usize];
+                if *shown != Some(state) {
+                    queue!(
+                        out,
+                        cursor::MoveTo(x, y),
+                        SetForegroundColor(colour(state)),
+                        Print(if state == State::Dead { ' ' } else { ALIVE })
+                    )?;
+                    *shown = Some(state);
+                }
+            }
+        }
+
+        let (name, _) = pattern::LIBRARY[self.selected];
+        let state = match self.count {
+            Some(count) => format!("rewind to {}", count),
+            None if self.playing => "playing".to_string(),
+            None => "paused".to_string(),
+        };
+        let status = format!(
+            "gen {} | pop {} | {} | {}ms x{} | {} | {} | {}",
+            self.engine.generation(),
+            self.engine.population(),
+            state,
+            self.options.delay,
+            self.options.step,
+            self.options.rule,
+            name,
+            HELP,
+        );
+        let status: String = status.chars().take(self.cols as usize).collect();
+        queue!(
+            out,
+            cursor::MoveTo(0, self.rows),
+            SetForegroundColor(Color::Reset),
+            Print(status),
+            terminal::Clear(ClearType::UntilNewLine),
+            SetForegroundColor(Color::Cyan),
+            cursor::MoveTo(
+                (self.cursor.1 - self.left) as u16,
+                (self.cursor.0 - self.top) as u16
+            ),
+            cursor::Show
+        )?;
+        out.flush()
+    }
+}
+
+/// colour of a cell in the state, live cells keep the colour of the
+/// terminal UI and the others take that of the web UI
+fn colour(state: State) -> Color {
+    match state {
+        State::Alive => Color::Cyan,
+        state => {
+            let cell = Cell {
+                state,
+                ..Cell::default()
+            };
+            let [r, g, b] = Palette::default().colour(&cell).rgb();
+            Color::Rgb { r, g, b }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn app(kind: Kind) -> App {
+        let options = Options {
+            kind,
+            boundary: None,
+            rule: Rule::CONWAY,
+            delay: 200,
+            step: 1,
+        };
+        let mut app = App::new(options, 16, 32).unwrap();
+        app.playing = false;
+        app
+    }
+
+    fn press(app: &mut App, keys: &str) {
+        for key in keys.chars() {
+            app.handle(KeyEvent::from(KeyCode::Char(key)));
+        }
+    }
+
+    #[test]
+    fn keys_flip_step_undo_and_clear() {
+        let mut app = app(Kind::Dense);
+        let (row, col) = app.cursor;
+
+        // a blinker drawn with the cursor
+        press(&mut app, "h l l h");
+        assert_eq!(app.engine.population(), 3);
+        assert!(app.engine.is_alive(row, col - 1));
+        assert!(app.engine.is_alive(row, col + 1));
+
+        press(&mut app, "s");
+        assert_eq!(app.engine.generation(), 1);
+        assert!(app.engine.is_alive(row - 1, col));
+        assert!(!app.engine.is_alive(row, col - 1));
+
+        press(&mut app, "u");
+        assert_eq!(app.engine.generation(), 0);
+        assert!(app.engine.is_alive(row, col - 1));
+        press(&mut app, "r");
+        assert_eq!(app.engine.generation(), 1);
+
+        press(&mut app, "c");
+        assert_eq!(app.engine.population(), 0);
+        assert_eq!(app.engine.generation(), 0);
+    }
+
+    #[test]
+    fn typed_generation_rewinds() {
+        let mut app = app(Kind::Dense);
+        press(&mut app, "h l l h");
+        press(&mut app, "ssss");
+        assert_eq!(app.engine.generation(), 4);
+
+        press(&mut app, "2");
+        assert_eq!(app.count, Some(2));
+        press(&mut app, "g");
+        assert_eq!(app.engine.generation(), 2);
+        assert_eq!(app.count, None);
+    }
+
+    #[test]
+    fn play_speed_and_stamp() {
+        let mut app = app(Kind::Sparse);
+        press(&mut app, "p");
+        assert!(app.playing);
+        // stepping is left to the tick while playing
+        press(&mut app, "s");
+        assert_eq!(app.engine.generation(), 0);
+
+        press(&mut app, "++");
+        assert_eq!(app.options.delay, 50);
+        press(&mut app, "-------");
+        assert_eq!(app.options.delay, MAX_DELAY);
+
+        press(&mut app, "]");
+        assert_eq!(app.selected, 1);
+        press(&mut app, "[[");
+        assert_eq!(app.selected, pattern::LIBRARY.len() - 1);
+
+        press(&mut app, "]");
+        app.handle(KeyEvent::from(KeyCode::Enter));
+        let (name, _) = pattern::LIBRARY[0];
+        let stamped = pattern::library(name).unwrap();
+        assert_eq!(app.engine.population(), stamped.cells.len() as u64);
+    }
+
+    #[test]
+    fn cursor_scrolls_the_board() {
+        let mut app = app(Kind::Sparse);
+        let (rows, cols) = (app.rows as i64, app.cols as i64);
+
+        for _ in 0..rows {
+            app.handle(KeyEvent::from(KeyCode::Down));
+        }
+        assert_eq!(app.cursor.0, rows / 2 + rows);
+        assert_eq!(app.top, app.cursor.0 - rows + 1);
+
+        for _ in 0..cols {
+            app.handle(KeyEvent::from(KeyCode::Left));
+        }
+        assert_eq!(app.cursor.1, cols / 2 - cols);
+        assert_eq!(app.left, app.cursor.1);
+    }
+
+    #[test]
+    fn only_changed_cells_are_drawn_again() {
+        let mut app = app(Kind::Dense);
+        press(&mut app, " ");
+
+        let mut first = Vec::new();
+        app.draw(&mut first).unwrap();
+        let mut second = Vec::new();
+        app.draw(&mut second).unwrap();
+        assert!(second.len() < first.len());
+        let second = String::from_utf8(second).unwrap();
+        assert!(!second.contains(ALIVE));
+        assert!(second.contains("gen 0 | pop 1 | paused"));
+
+        press(&mut app, "q");
+        assert!(app.quit);
+    }
+}
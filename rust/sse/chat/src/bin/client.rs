@@ -0,0 +1,485 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Context};
+use clap::Parser;
+use futures::StreamExt;
+use reqwest::Url;
+use rustyline::{DefaultEditor, ExternalPrinter};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/*
+* Terminal client for the chat server.
+*
+* The client claims a user handle and talks to the server through the
+* JSON API. Once a hangout is joined, its SSE feed is streamed to the
+* terminal and every line typed at the prompt is sent as message.
+*/
+
+#[derive(Debug, Parser)]
+struct Args {
+    /// address of the chat server
+    #[arg(short, long, default_value = "http://127.0.0.1:3000")]
+    server: String,
+    /// user handle to claim on the server
+    #[arg(long)]
+    handle: String,
+}
+
+const HELP: &str = "commands:
+  /list             list hangouts
+  /create <name>    create a hangout
+  /join <name>      join a hangout and stream its messages
+  /members          list members of the joined hangout
+  /history [n]      show the last n messages of the joined hangout
+  /help             show this help
+  /quit             leave
+anything else is sent to the joined hangout, including the
+commands the server knows: /me, /nick, /kick and /topic";
+
+type Printer = Arc<Mutex<Box<dyn ExternalPrinter + Send>>>;
+
+fn say(printer: &Printer, msg: impl Into<String>) {
+    let _ = printer.lock().unwrap().print(msg.into());
+}
+
+/// Stdout is used in place of the readline printer
+/// if stdin is not a terminal
+struct Stdout;
+
+impl ExternalPrinter for Stdout {
+    fn print(&mut self, msg: String) -> rustyline::Result<()> {
+        println!("{}", msg);
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct ApiError {
+    error: ApiErrorDetail,
+}
+
+#[derive(Deserialize)]
+struct ApiErrorDetail {
+    message: String,
+}
+
+#[derive(Serialize)]
+struct ClaimReq<'a> {
+    handle: &'a str,
+}
+
+#[derive(Deserialize)]
+struct UserResp {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct HangoutsResp {
+    hangouts: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct CreateHangoutReq<'a> {
+    name: &'a str,
+}
+
+#[derive(Deserialize)]
+struct MembersResp {
+    members: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct SendMessageReq<'a> {
+    body: &'a str,
+}
+
+#[derive(Deserialize)]
+struct Entry {
+    author: String,
+    body: String,
+}
+
+#[derive(Deserialize)]
+struct HistoryResp {
+    messages: Vec<Entry>,
+}
+
+struct Client {
+    http: reqwest::Client,
+    server: Url,
+    user_id: String,
+}
+
+/// turns non 2xx responses into an error carrying
+/// the message of the API's error body
+async fn check(resp: reqwest::Response) -> anyhow::Result<reqwest::Response> {
+    if resp.status().is_success() {
+        return Ok(resp);
+    }
+
+    let status = resp.status();
+    match resp.json::<ApiError>().await {
+        Ok(err) => bail!("{}", err.error.message),
+        Err(_) => bail!("server responded with {}", status),
+    }
+}
+
+/// appends the segments to the server's path. Each segment is
+/// percent-encoded, hence hangout names may contain slashes,
+/// spaces or question marks.
+fn url(server: &Url, segments: &[&str]) -> Url {
+    let mut url = server.clone();
+    if let Ok(mut path) = url.path_segments_mut() {
+        path.pop_if_empty().extend(segments);
+    }
+    url
+}
+
+impl Client {
+    async fn claim(server: &str, handle: &str) -> anyhow::Result<Self> {
+        let http = reqwest::Client::new();
+        let server =
+            Url::parse(server).with_context(|| format!("invalid server address {}", server))?;
+        if server.cannot_be_a_base() {
+            bail!("invalid server address {}", server);
+        }
+
+        let resp = http
+            .post(url(&server, &["api", "v1", "users"]))
+            .json(&ClaimReq { handle })
+            .send()
+            .await
+            .with_context(|| format!("connecting to {}", server))?;
+        let user: UserResp = check(resp).await?.json().await?;
+
+        Ok(Client {
+            http,
+            server,
+            user_id: user.id,
+        })
+    }
+
+    async fn hangouts(&self) -> anyhow::Result<Vec<String>> {
+        let resp = self
+            .http
+            .get(url(&self.server, &["api", "v1", "hangouts"]))
+            .send()
+            .await?;
+
+        Ok(check(resp).await?.json::<HangoutsResp>().await?.hangouts)
+    }
+
+    async fn create(&self, name: &str) -> anyhow::Result<()> {
+        let resp = self
+            .http
+            .post(url(&self.server, &["api", "v1", "hangouts"]))
+            .bearer_auth(&self.user_id)
+            .json(&CreateHangoutReq { name })
+            .send()
+            .await?;
+
+        check(resp).await.map(|_| ())
+    }
+
+    async fn members(&self, name: &str) -> anyhow::Result<Vec<String>> {
+        let resp = self
+            .http
+            .get(url(
+                &self.server,
+                &["api", "v1", "hangouts", name, "members"],
+            ))
+            .bearer_auth(&self.user_id)
+            .send()
+            .await?;
+
+        Ok(check(resp).await?.json::<MembersResp>().await?.members)
+    }
+
+    async fn history(&self, name: &str, limit: usize) -> anyhow::Result<Vec<Entry>> {
+        let resp = self
+            .http
+            .get(url(
+                &self.server,
+                &["api", "v1", "hangouts", name, "messages"],
+            ))
+            .bearer_auth(&self.user_id)
+            .query(&[("limit", limit)])
+            .send()
+            .await?;
+
+        Ok(check(resp).await?.json::<HistoryResp>().await?.messages)
+    }
+
+    async fn send(&self, name: &str, body: &str) -> anyhow::Result<()> {
+        let resp = self
+            .http
+            .post(url(
+                &self.server,
+                &["api", "v1", "hangouts", name, "messages"],
+            ))
+            .bearer_auth(&self.user_id)
+            .json(&SendMessageReq { body })
+            .send()
+            .await?;
+
+        check(resp).await.map(|_| ())
+    }
+
+    /// connects to the SSE feed of the hangout, see print_events
+    async fn stream(&self, name: &str) -> anyhow::Result<reqwest::Response> {
+        let resp = self
+            .http
+            .get(url(&self.server, &["sse", name]))
+            .header("Cookie", format!("user_handle={}", self.user_id))
+            .send()
+            .await?;
+
+        check(resp).await
+    }
+}
+
+/// prints the events of the SSE feed until the
+/// connection closes or the task is aborted
+fn print_events(resp: reqwest::Response, name: String, printer: Printer) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut body = resp.bytes_stream();
+        let mut buffer = Vec::new();
+
+        while let Some(Ok(chunk)) = body.next().await {
+            buffer.extend_from_slice(&chunk);
+
+            while let Some(raw) = next_event(&mut buffer) {
+                if let Some(line) = format_event(&raw) {
+                    say(&printer, line);
+                }
+            }
+        }
+
+        say(&printer, format!("-- stream of {} closed", name));
+    })
+}
+
+/// takes the first complete event off the buffer. Events are
+/// separated by an empty line; they are only decoded once complete
+/// as a chunk may end in the middle of a multi-byte character.
+fn next_event(buffer: &mut Vec<u8>) -> Option<String> {
+    let end = buffer.windows(2).position(|w| w == b"\n\n")?;
+    let raw: Vec<u8> = buffer.drain(..end + 2).collect();
+    Some(String::from_utf8_lossy(&raw).into_owned())
+}
+
+/// renders an SSE event for the terminal. Events
+/// without anything to show are skipped.
+fn format_event(raw: &str) -> Option<String> {
+    let mut event = "message";
+    let mut data = Vec::new();
+
+    for line in raw.lines() {
+        if let Some(name) = line.strip_prefix("event:") {
+            event = name.trim();
+        } else if let Some(value) = line.strip_prefix("data:") {
+            data.push(value.strip_prefix(' ').unwrap_or(value));
+        }
+    }
+
+    let data = data.join("\n");
+    match event {
+        "message" if !data.is_empty() => Some(strip_tags(&data)),
+        "typing" if !data.is_empty() => Some(format!("-- {}", strip_tags(&data))),
+        "topic" => Some(format!("-- topic: {}", strip_tags(&data))),
+        "user_join" => Some(format!(
+            "-- in hangout: {}",
+            data.split("</div>")
+                .map(strip_tags)
+                .filter(|handle| !handle.is_empty())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+        _ => None,
+    }
+}
+
+/// turns an html fragment into the text it shows
+fn strip_tags(html: &str) -> String {
+    let mut out = String::new();
+    let mut in_tag = false;
+
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            c if !in_tag => out.push(c),
+            _ => {}
+        }
+    }
+
+    // & goes last, else an escaped entity would be unescaped twice
+    out.trim()
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+
+    let client = Client::claim(&args.server, &args.handle).await?;
+
+    let mut editor = DefaultEditor::new()?;
+    let printer: Printer = match editor.create_external_printer() {
+        Ok(printer) => Arc::new(Mutex::new(Box::new(printer))),
+        Err(_) => Arc::new(Mutex::new(Box::new(Stdout))),
+    };
+
+    // rustyline blocks, hence it reads on its own thread and hands
+    // every line over. Ctrl-C, Ctrl-D or /quit end the session.
+    let (tx, mut lines) = mpsc::unbounded_channel::<String>();
+    std::thread::spawn(move || {
+        while let Ok(line) = editor.readline("> ") {
+            if line.trim() == "/quit" {
+                break;
+            }
+
+            let _ = editor.add_history_entry(&line);
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    say(
+        &printer,
+        format!("claimed \"{}\", type /help for commands", args.handle),
+    );
+
+    let mut joined: Option<(String, JoinHandle<()>)> = None;
+
+    while let Some(line) = lines.recv().await {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let (command, arg) = line.split_once(' ').unwrap_or((line, ""));
+        let arg = arg.trim();
+        let hangout = joined.as_ref().map(|(name, _)| name.clone());
+
+        let result = match (command, hangout) {
+            ("/help", _) => {
+                say(&printer, HELP);
+                Ok(())
+            }
+            ("/list", _) => client
+                .hangouts()
+                .await
+                .map(|hangouts| say(&printer, format!("hangouts: {}", hangouts.join(", ")))),
+            ("/create", _) if !arg.is_empty() => client
+                .create(arg)
+                .await
+                .map(|_| say(&printer, format!("created {}", arg))),
+            ("/join", _) if !arg.is_empty() => match client.stream(arg).await {
+                Ok(resp) => {
+                    if let Some((_, previous)) = joined.take() {
+                        previous.abort();
+                    }
+
+                    say(&printer, format!("joined {}", arg));
+                    let task = print_events(resp, arg.to_string(), printer.clone());
+                    joined = Some((arg.to_string(), task));
+                    Ok(())
+                }
+                Err(err) => Err(err),
+            },
+            ("/members", Some(hangout)) => client
+                .members(&hangout)
+                .await
+                .map(|members| say(&printer, format!("members: {}", members.join(", ")))),
+            ("/history", Some(hangout)) => client
+                .history(&hangout, arg.parse().unwrap_or(20))
+                .await
+                .map(|entries| {
+                    entries.iter().for_each(|entry| {
+                        say(&printer, format!("{}: {}", entry.author, entry.body))
+                    })
+                }),
+            ("/create" | "/join", _) => Err(anyhow::anyhow!("missing argument, see /help")),
+            (_, Some(hangout)) => client.send(&hangout, line).await,
+            (_, None) => Err(anyhow::anyhow!("join a hangout first, see /help")),
+        };
+
+        if let Err(err) = result {
+            say(&printer, format!("error: {:#}", err));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn url_encodes_hangout_names() {
+        let server = Url::parse("http://127.0.0.1:3000/").unwrap();
+        assert_eq!(
+            url(&server, &["sse", "a/b c?"]).as_str(),
+            "http://127.0.0.1:3000/sse/a%2Fb%20c%3F"
+        );
+    }
+
+    #[test]
+    fn events_split_inside_a_character() {
+        let event = "event: message\ndata: <b>ann</b>: grüß dich\n\n".as_bytes();
+        let split = event.iter().position(|b| *b == 0xc3).unwrap() + 1;
+
+        let mut buffer = event[..split].to_vec();
+        assert_eq!(next_event(&mut buffer), None);
+
+        buffer.extend_from_slice(&event[split..]);
+        let raw = next_event(&mut buffer).unwrap();
+        assert_eq!(format_event(&raw).as_deref(), Some("ann: grüß dich"));
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn format_events() {
+        let cases = [
+            (
+                "event: message\ndata: <div><b>ann</b>: hi</div>\n\n",
+                Some("ann: hi"),
+            ),
+            (
+                "event: typing\ndata: <i>ann is typing</i>\n\n",
+                Some("-- ann is typing"),
+            ),
+            ("event: typing\ndata: \n\n", None),
+            ("event: topic\ndata: rust\n\n", Some("-- topic: rust")),
+            (
+                "event: user_join\ndata: <div>ann</div><div>bob</div>\n\n",
+                Some("-- in hangout: ann, bob"),
+            ),
+            ("event: read\ndata: <div>ann</div>\n\n", None),
+            (": keep-alive\n\n", None),
+        ];
+
+        for (raw, want) in cases {
+            assert_eq!(format_event(raw).as_deref(), want, "{:?}", raw);
+        }
+    }
+
+    #[test]
+    fn strip_tags_keeps_text() {
+        assert_eq!(
+            strip_tags("<div> <b>ann</b>: a &lt; b &amp;lt; c </div>"),
+            "ann: a < b &lt; c"
+        );
+        assert_eq!(strip_tags("no tags"), "no tags");
+        assert_eq!(strip_tags("<img src=\"x\">"), "");
+    }
+}
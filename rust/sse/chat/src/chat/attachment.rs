@@ -0,0 +1,217 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// default maximum size of an uploaded file in bytes
+pub(crate) const MAX_SIZE: usize = 5 * 1024 * 1024;
+/// characters of a text file shown as preview
+const PREVIEW_LEN: usize = 500;
+
+/// Kind is a file type which may be uploaded. The type is
+/// taken from the content rather than what the client claims,
+/// hence files are always served with a matching content type.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Kind {
+    Png,
+    Jpeg,
+    Gif,
+    Webp,
+    Pdf,
+    Text,
+}
+
+impl Kind {
+    const ALL: [Kind; 6] = [
+        Kind::Png,
+        Kind::Jpeg,
+        Kind::Gif,
+        Kind::Webp,
+        Kind::Pdf,
+        Kind::Text,
+    ];
+
+    fn sniff(data: &[u8]) -> Option<Kind> {
+        if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(Kind::Png)
+        } else if data.starts_with(b"\xff\xd8\xff") {
+            Some(Kind::Jpeg)
+        } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+            Some(Kind::Gif)
+        } else if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+            Some(Kind::Webp)
+        } else if data.starts_with(b"%PDF-") {
+            Some(Kind::Pdf)
+        } else if std::str::from_utf8(data).is_ok() && !data.contains(&0) {
+            Some(Kind::Text)
+        } else {
+            None
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Kind::Png => "png",
+            Kind::Jpeg => "jpg",
+            Kind::Gif => "gif",
+            Kind::Webp => "webp",
+            Kind::Pdf => "pdf",
+            Kind::Text => "txt",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Kind::Png => "image/png",
+            Kind::Jpeg => "image/jpeg",
+            Kind::Gif => "image/gif",
+            Kind::Webp => "image/webp",
+            Kind::Pdf => "application/pdf",
+            Kind::Text => "text/plain; charset=utf-8",
+        }
+    }
+}
+
+/// Attachment is a stored file shared in a hangout
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Attachment {
+    /// file name given by the uploader
+    pub(crate) name: String,
+    /// sha256 of the content plus extension, the file is served under it
+    pub(crate) key: String,
+    pub(crate) content_type: String,
+    pub(crate) size: usize,
+    /// beginning of text files
+    pub(crate) preview: Option<String>,
+}
+
+impl Attachment {
+    pub fn url(&self) -> String {
+        format!("/attachments/{}", self.key)
+    }
+
+    pub fn is_image(&self) -> bool {
+        self.content_type.starts_with("image/")
+    }
+}
+
+/// Refused is why an upload has not been stored
+#[derive(Debug)]
+pub(crate) enum Refused {
+    /// the file exceeds the maximum size
+    TooLarge(usize),
+    /// the content is none of the supported kinds
+    UnsupportedType,
+    Io(std::io::Error),
+}
+
+/// Store keeps uploaded files on disk, named after the
+/// sha256 of their content. Uploading the same file twice
+/// stores it only once.
+pub(crate) struct Store {
+    dir: PathBuf,
+    pub(crate) max_size: usize,
+}
+
+impl Store {
+    pub fn new(dir: impl Into<PathBuf>, max_size: usize) -> Self {
+        Store {
+            dir: dir.into(),
+            max_size,
+        }
+    }
+
+    pub async fn save(&self, name: &str, data: &[u8]) -> Result<Attachment, Refused> {
+        if data.len() > self.max_size {
+            return Err(Refused::TooLarge(self.max_size));
+        }
+        let kind = Kind::sniff(data).ok_or(Refused::UnsupportedType)?;
+
+        let hash: String = Sha256::digest(data)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        let key = format!("{}.{}", hash, kind.extension());
+
+        let path = self.dir.join(&key);
+        if !tokio::fs::try_exists(&path).await.unwrap_or(false) {
+            tokio::fs::create_dir_all(&self.dir)
+                .await
+                .map_err(Refused::Io)?;
+
+            // written under a temporary name of its own first, so a
+            // file is never served before it is complete, even while
+            // the same file is uploaded twice at once
+            let partial = self
+                .dir
+                .join(format!("{}.{}.partial", key, uuid::Uuid::new_v4()));
+            let written = match tokio::fs::write(&partial, data).await {
+                Ok(()) => tokio::fs::rename(&partial, &path).await,
+                Err(err) => Err(err),
+            };
+            if let Err(err) = written {
+                let _ = tokio::fs::remove_file(&partial).await;
+                return Err(Refused::Io(err));
+            }
+        }
+
+        let preview = (kind == Kind::Text).then(|| {
+            let text = String::from_utf8_lossy(data);
+            match text.char_indices().nth(PREVIEW_LEN) {
+                Some((end, _)) => format!("{}…", &text[..end]),
+                None => text.into_owned(),
+            }
+        });
+
+        Ok(Attachment {
+            name: name.to_string(),
+            key,
+            content_type: kind.content_type().to_string(),
+            size: data.len(),
+            preview,
+        })
+    }
+
+    /// reads a stored file and its kind. Keys which were not
+    /// handed out by save are never looked up on disk.
+    pub async fn open(&self, key: &str) -> Option<(Kind, Vec<u8>)> {
+        let (hash, extension) = key.split_once('.')?;
+        if hash.len() != 64 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        let kind = Kind::ALL
+            .into_iter()
+            .find(|kind| kind.extension() == extension)?;
+
+        let data = tokio::fs::read(self.dir.join(key)).await.ok()?;
+        Some((kind, data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn same_file_saved_at_once_is_stored_once() {
+        let dir = std::env::temp_dir().join(format!("attachments-{}", uuid::Uuid::new_v4()));
+        let store = Store::new(&dir, MAX_SIZE);
+
+        let (first, second) =
+            tokio::join!(store.save("a.txt", b"hello"), store.save("b.txt", b"hello"));
+        let (first, second) = (first.unwrap(), second.unwrap());
+        assert_eq!(first.key, second.key);
+        assert_eq!(first.preview.as_deref(), Some("hello"));
+
+        let mut names = Vec::new();
+        let mut entries = tokio::fs::read_dir(&dir).await.unwrap();
+        while let Some(entry) = entries.next_entry().await.unwrap() {
+            names.push(entry.file_name().into_string().unwrap());
+        }
+        assert_eq!(names, vec![first.key.clone()]);
+        let (kind, data) = store.open(&first.key).await.unwrap();
+        assert_eq!((kind, data.as_slice()), (Kind::Text, &b"hello"[..]));
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// number of buckets after which full (hence idle) buckets are dropped
+const PRUNE_THRESHOLD: usize = 10_000;
+/// Retry-After sent to clients while no more handles can be claimed
+pub(crate) const FULL_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+/// Limits protect the server from clients flooding it
+/// with messages or handle claims.
+#[derive(Debug, Clone)]
+pub(crate) struct Limits {
+    /// messages per second a session may send
+    pub(crate) session_messages: f64,
+    /// messages per second all sessions of an ip may send
+    pub(crate) ip_messages: f64,
+    /// handle claims per minute an ip may make
+    pub(crate) ip_claims: f64,
+    /// maximum length of a message in bytes
+    pub(crate) max_message_len: usize,
+    /// maximum number of claimed user handles
+    pub(crate) max_online: usize,
+    /// time after which a user without open streams
+    /// or requests gives up their handle
+    pub(crate) idle_timeout: Duration,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            session_messages: 2.0,
+            ip_messages: 10.0,
+            ip_claims: 5.0,
+            max_message_len: 2000,
+            max_online: 1000,
+            idle_timeout: Duration::from_secs(600),
+        }
+    }
+}
+
+/// Limited is why a request has been refused
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Limited {
+    /// too many requests, retry after the duration
+    RetryAfter(Duration),
+    /// the message exceeds the maximum length
+    TooLong(usize),
+    /// no more user handles can be claimed
+    Full,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// RateLimiter keeps a token bucket per key. Buckets refill with
+/// `rate` tokens per second up to `burst` tokens and each request
+/// takes one token.
+pub(crate) struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(rate: f64, burst: f64) -> Self {
+        RateLimiter {
+            rate,
+            burst: burst.max(1.0),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// takes a token from the key's bucket. If the bucket is
+    /// empty the time until the next token is available is returned.
+    pub fn check(&self, key: &str) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if buckets.len() > PRUNE_THRESHOLD {
+            let (rate, burst) = (self.rate, self.burst);
+            buckets.retain(|_, bucket| {
+                bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * rate < burst
+            });
+        }
+
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+
+        bucket.tokens = (bucket.tokens
+            + now.duration_since(bucket.updated).as_secs_f64() * self.rate)
+            .min(self.burst);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+
+        if self.rate <= 0.0 {
+            return Err(Duration::MAX);
+        }
+        Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate))
+    }
+}
@@ -0,0 +1,188 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// upper bounds in seconds of the handler latency histogram
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+];
+
+/// Request identifies a handler latency series
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Request {
+    method: String,
+    route: String,
+    status: u16,
+}
+
+#[derive(Default)]
+struct Histogram {
+    /// observations per bucket, not cumulative
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+/// Metrics counts what happens inside the server. Gauges such
+/// as the number of online users are read from the State when
+/// the metrics are rendered, see State::render_metrics.
+#[derive(Default)]
+pub(crate) struct Metrics {
+    messages_sent: AtomicU64,
+    lagged: AtomicU64,
+    latency: Mutex<BTreeMap<Request, Histogram>>,
+}
+
+/// Gauges is a snapshot of the state taken while rendering
+pub(crate) struct Gauges {
+    pub(crate) online_users: usize,
+    pub(crate) hangouts: usize,
+    /// name of the hangout with its number of open streams
+    pub(crate) subscribers: Vec<(String, usize)>,
+    /// messages the PubSub could not pass on to other processes
+    pub(crate) relay_dropped: u64,
+}
+
+impl Metrics {
+    pub fn message_sent(&self) {
+        self.messages_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// a stream fell behind its hangout and skipped messages
+    pub fn lagged(&self) {
+        self.lagged.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn observe(&self, method: &str, route: &str, status: u16, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        let mut latency = self.latency.lock().unwrap();
+
+        let histogram = latency
+            .entry(Request {
+                method: method.to_string(),
+                route: route.to_string(),
+                status,
+            })
+            .or_default();
+
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|bound| secs <= *bound) {
+            histogram.buckets[bucket] += 1;
+        }
+        histogram.sum += secs;
+        histogram.count += 1;
+    }
+
+    /// renders all metrics in the Prometheus text format
+    pub fn render(&self, gauges: &Gauges) -> String {
+        let mut out = String::new();
+
+        gauge(
+            &mut out,
+            "chat_online_users",
+            "claimed user handles",
+            gauges.online_users,
+        );
+        gauge(
+            &mut out,
+            "chat_hangouts",
+            "existing hangouts",
+            gauges.hangouts,
+        );
+
+        out.push_str(
+            "# HELP chat_hangout_subscribers open SSE and WebSocket streams of a hangout\n",
+        );
+        out.push_str("# TYPE chat_hangout_subscribers gauge\n");
+        for (hangout, count) in &gauges.subscribers {
+            let _ = writeln!(
+                out,
+                "chat_hangout_subscribers{{hangout=\"{}\"}} {}",
+                label(hangout),
+                count
+            );
+        }
+
+        counter(
+            &mut out,
+            "chat_messages_sent_total",
+            "messages recorded in hangouts",
+            self.messages_sent.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "chat_lagged_receivers_total",
+            "times a stream fell behind and skipped messages",
+            self.lagged.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "chat_relay_dropped_total",
+            "messages dropped because the relay could not keep up",
+            gauges.relay_dropped,
+        );
+
+        out.push_str("# HELP chat_request_duration_seconds latency of the http handlers\n");
+        out.push_str("# TYPE chat_request_duration_seconds histogram\n");
+        for (request, histogram) in self.latency.lock().unwrap().iter() {
+            let labels = format!(
+                "method=\"{}\",route=\"{}\",status=\"{}\"",
+                label(&request.method),
+                label(&request.route),
+                request.status
+            );
+
+            let mut cumulative = 0;
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets) {
+                cumulative += count;
+                let _ = writeln!(
+                    out,
+                    "chat_request_duration_seconds_bucket{{{},le=\"{}\"}} {}",
+                    labels, bound, cumulative
+                );
+            }
+            let _ = writeln!(
+                out,
+                "chat_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}",
+                labels, histogram.count
+            );
+            let _ = writeln!(
+                out,
+                "chat_request_duration_seconds_sum{{{}}} {}",
+                labels, histogram.sum
+            );
+            let _ = writeln!(
+                out,
+                "chat_request_duration_seconds_count{{{}}} {}",
+                labels, histogram.count
+            );
+        }
+
+        out
+    }
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: usize) {
+    let _ = writeln!(
+        out,
+        "# HELP {} {}\n# TYPE {} gauge\n{} {}",
+        name, help, name, name, value
+    );
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    let _ = writeln!(
+        out,
+        "# HELP {} {}\n# TYPE {} counter\n{} {}",
+        name, help, name, name, value
+    );
+}
+
+/// escapes a label value of the text format
+fn label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -1,8 +1,24 @@
-use std::collections::HashMap;
-use std::sync::Mutex;
+pub(crate) mod attachment;
+pub(crate) mod limit;
+pub(crate) mod metrics;
+pub(crate) mod moderation;
+pub(crate) mod pubsub;
+pub(crate) mod search;
 
-use tokio::sync::broadcast::{Receiver, Sender};
-use tracing::{error, info};
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast::Receiver, watch};
+
+use attachment::{Attachment, Store};
+use limit::{Limited, Limits, RateLimiter};
+use metrics::{Gauges, Metrics};
+use moderation::{Context, Pipeline, Verdict};
+use pubsub::PubSub;
+use search::{Hit, Index, Query};
 
 /*
 * What should the chat app do?
@@ -17,6 +33,18 @@ use tracing::{error, info};
 *
 */
 
+/// minimum time between two typing events of the same
+/// user in a hangout. Key strokes in between are only
+/// used to keep the indicator alive.
+pub(crate) const TYPING_THROTTLE: Duration = Duration::from_secs(2);
+/// time after the last key stroke until a user is no
+/// longer considered to be typing
+pub(crate) const TYPING_EXPIRY: Duration = Duration::from_secs(5);
+/// default capacity of a hangout's broadcast channel
+pub(crate) const CHANNEL_CAPACITY: usize = 16;
+/// default interval of keep-alive messages on open streams
+pub(crate) const KEEP_ALIVE: Duration = Duration::from_secs(1);
+
 /// User represents an open connection
 /// made through a browser window. Each
 /// user must be uniquely identified by a user-handle
@@ -29,67 +57,351 @@ pub(crate) struct User {
 
 pub(crate) type UserInfo = (String, String);
 
+/// Presence keeps track of whether a user is still around. Users
+/// without open streams are removed once they have been idle for
+/// Limits::idle_timeout, see State::prune_online.
+struct Presence {
+    user: User,
+    /// open SSE and WebSocket streams of the user
+    streams: usize,
+    last_seen: Instant,
+}
+
+/// Entry is a message which has been sent
+/// to a hangout. The id is the position of the
+/// entry in the hangout's history.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Entry {
+    pub(crate) id: usize,
+    pub(crate) author: String,
+    pub(crate) body: String,
+    /// unix timestamp in seconds
+    pub(crate) sent_at: i64,
+}
+
+/// Typing tracks when a user last pressed a key
+/// and when the last typing event went out for them.
+struct Typing {
+    handle: String,
+    /// when the user started typing, which tells this indicator
+    /// apart from earlier ones of the same user
+    started: Instant,
+    last_seen: Instant,
+    last_sent: Instant,
+}
+
 pub(crate) struct Hangout {
     pub(crate) users: Vec<User>,
-    pub(crate) history: Vec<(i64, String)>,
-    rx: Option<Receiver<Message>>,
-    tx: Option<Sender<Message>>,
+    pub(crate) history: Vec<Entry>,
+    /// words of the history, see search
+    index: Index,
+    /// user id of whoever created the hangout
+    owner: Option<String>,
+    topic: String,
+    kicked: HashSet<String /* user id */>,
+    typing: HashMap<String /* user id */, Typing>,
+    read_markers: HashMap<String /* user id */, (String /* handle */, usize)>,
 }
 
-#[derive(Clone)]
+impl Hangout {
+    fn has_member(&self, user_id: &str) -> bool {
+        self.users.iter().any(|user| user.id == user_id)
+    }
+
+    fn typing_handles(&self) -> Vec<String> {
+        let mut handles: Vec<String> = self
+            .typing
+            .values()
+            .map(|typing| typing.handle.clone())
+            .collect();
+        handles.sort();
+        handles
+    }
+
+    fn read_markers(&self) -> Vec<(String, usize)> {
+        let mut markers: Vec<(String, usize)> = self.read_markers.values().cloned().collect();
+        markers.sort();
+        markers
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[allow(clippy::enum_variant_names)]
 pub(crate) enum Message {
     ChatMessage(String),
     UserJoin(String),
+    /// handles of all users currently typing in the hangout
+    Typing(Vec<String>),
+    /// handles of users with the id of the last
+    /// message they have read
+    Read(Vec<(String, usize)>),
+    /// new topic of the hangout
+    Topic(String),
+    /// announcement of the server, e.g. a user changed their handle
+    Notice(String),
+    /// the user has been removed from the hangout. Their
+    /// streams end once they receive this message
+    Kicked {
+        user_id: String,
+        handle: String,
+    },
+    /// a file has been shared in the hangout
+    Attachment {
+        author: String,
+        attachment: Attachment,
+    },
 }
 
 // State is the entire state of all online
 // users and ongoing chat rooms.
-#[derive(Default)]
+//
+// Messages of a hangout are fanned out through the PubSub
+// using the hangout's name as topic. Users and hangouts are
+// local to the process; hangouts with the same name in two
+// processes sharing a network PubSub share their messages.
 pub(crate) struct State {
-    online: Mutex<HashMap<String /*uuid::Uuid string */, User>>,
+    online: Mutex<HashMap<String /*uuid::Uuid string */, Presence>>,
     rooms: Mutex<HashMap<String, Hangout>>,
+    pubsub: Arc<dyn PubSub>,
+    /// interval in which open SSE streams send a keep-alive
+    pub(crate) keep_alive: Duration,
+    shutdown: watch::Sender<bool>,
+    limits: Limits,
+    session_messages: RateLimiter,
+    ip_messages: RateLimiter,
+    ip_claims: RateLimiter,
+    moderation: Pipeline,
+    /// files shared in hangouts
+    pub(crate) attachments: Store,
+    pub(crate) metrics: Metrics,
 }
 
 impl State {
-    pub fn new() -> Self {
-        State::default()
+    pub fn with_pubsub(pubsub: Arc<dyn PubSub>, keep_alive: Duration, limits: Limits) -> Self {
+        State {
+            online: Mutex::default(),
+            rooms: Mutex::default(),
+            pubsub,
+            keep_alive,
+            shutdown: watch::Sender::new(false),
+            // message limits allow bursts of twice the rate, claims
+            // are limited per minute and allow to use all at once
+            session_messages: RateLimiter::new(
+                limits.session_messages,
+                limits.session_messages * 2.0,
+            ),
+            ip_messages: RateLimiter::new(limits.ip_messages, limits.ip_messages * 2.0),
+            ip_claims: RateLimiter::new(limits.ip_claims / 60.0, limits.ip_claims),
+            limits,
+            moderation: Pipeline::new().filter(moderation::SlashCommands),
+            attachments: Store::new("attachments", attachment::MAX_SIZE),
+            metrics: Metrics::default(),
+        }
     }
 
-    pub fn claim_user_handle(&self, user_handle: &str) -> Option<String> {
-        if self
-            .online
+    /// replaces where uploaded files are stored, by
+    /// default the attachments directory is used
+    pub fn with_attachments(mut self, attachments: Store) -> Self {
+        self.attachments = attachments;
+        self
+    }
+
+    /// replaces the filters every message goes through before it is
+    /// broadcast. By default only slash commands are handled.
+    pub fn with_moderation(mut self, moderation: Pipeline) -> Self {
+        self.moderation = moderation;
+        self
+    }
+
+    /// signals every open stream to close
+    pub fn shutdown(&self) {
+        self.shutdown.send_replace(true);
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        *self.shutdown.borrow()
+    }
+
+    /// renders the metrics in the Prometheus text format
+    pub fn render_metrics(&self) -> String {
+        let online_users = self.online.lock().unwrap().len();
+
+        let mut subscribers: Vec<(String, usize)> = self
+            .rooms
             .lock()
             .unwrap()
+            .keys()
+            .map(|name| (name.clone(), self.pubsub.subscribers(name)))
+            .collect();
+        subscribers.sort();
+
+        self.metrics.render(&Gauges {
+            online_users,
+            hangouts: subscribers.len(),
+            subscribers,
+            relay_dropped: self.pubsub.dropped(),
+        })
+    }
+
+    /// resolves once shutdown has been called
+    pub fn on_shutdown(&self) -> impl std::future::Future<Output = ()> + Send + 'static {
+        let mut rx = self.shutdown.subscribe();
+        async move {
+            let _ = rx.wait_for(|shutdown| *shutdown).await;
+        }
+    }
+
+    /// checks whether a handle may be claimed from the ip
+    /// before claim_user_handle is called
+    pub fn check_claim(&self, ip: Option<IpAddr>) -> Result<(), Limited> {
+        let mut online = self.online.lock().unwrap();
+        self.prune_online(&mut online);
+        if online.len() >= self.limits.max_online {
+            return Err(Limited::Full);
+        }
+        drop(online);
+
+        match ip {
+            Some(ip) => self
+                .ip_claims
+                .check(&ip.to_string())
+                .map_err(Limited::RetryAfter),
+            None => Ok(()),
+        }
+    }
+
+    /// checks whether the user may send the message
+    /// before it is recorded and broadcast
+    pub fn check_message(
+        &self,
+        user_id: &str,
+        ip: Option<IpAddr>,
+        body: &str,
+    ) -> Result<(), Limited> {
+        self.check_length(body)?;
+        self.check_rate(user_id, ip)
+    }
+
+    pub fn check_length(&self, body: &str) -> Result<(), Limited> {
+        if body.len() > self.limits.max_message_len {
+            return Err(Limited::TooLong(self.limits.max_message_len));
+        }
+        Ok(())
+    }
+
+    /// takes a token of the user and of the ip, uploads take
+    /// one before their body is read
+    pub fn check_rate(&self, user_id: &str, ip: Option<IpAddr>) -> Result<(), Limited> {
+        self.session_messages
+            .check(user_id)
+            .map_err(Limited::RetryAfter)?;
+
+        match ip {
+            Some(ip) => self
+                .ip_messages
+                .check(&ip.to_string())
+                .map_err(Limited::RetryAfter),
+            None => Ok(()),
+        }
+    }
+
+    pub fn claim_user_handle(&self, user_handle: &str) -> Option<String> {
+        let mut online = self.online.lock().unwrap();
+        self.prune_online(&mut online);
+
+        if online
             .values()
-            .any(|user| user.handle == user_handle)
+            .any(|presence| presence.user.handle == user_handle)
         {
             return None;
         }
 
-        let mut online = self.online.lock().unwrap();
-
         let id = uuid::Uuid::new_v4().to_string();
         let _ = online.insert(
             id.clone(),
-            User {
-                id: id.clone(),
-                handle: user_handle.to_string(),
+            Presence {
+                user: User {
+                    id: id.clone(),
+                    handle: user_handle.to_string(),
+                },
+                streams: 0,
+                last_seen: Instant::now(),
             },
         );
 
         Some(id.clone())
     }
 
-    pub fn create_hangout(&self, name: &str) -> Option<Hangout> {
-        self.rooms.lock().unwrap().insert(
+    /// removes users who have no open streams and have not been
+    /// seen for Limits::idle_timeout, which frees their handles
+    fn prune_online(&self, online: &mut HashMap<String, Presence>) {
+        let now = Instant::now();
+        let idle: HashSet<String> = online
+            .values()
+            .filter(|presence| {
+                presence.streams == 0
+                    && now.duration_since(presence.last_seen) >= self.limits.idle_timeout
+            })
+            .map(|presence| presence.user.id.clone())
+            .collect();
+
+        if idle.is_empty() {
+            return;
+        }
+
+        online.retain(|id, _| !idle.contains(id));
+        for hangout in self.rooms.lock().unwrap().values_mut() {
+            hangout.users.retain(|user| !idle.contains(&user.id));
+            hangout.typing.retain(|id, _| !idle.contains(id));
+        }
+    }
+
+    /// returns the user and marks them as seen
+    pub fn get_user(&self, user_id: &str) -> Option<User> {
+        let mut online = self.online.lock().unwrap();
+        let presence = online.get_mut(user_id)?;
+        presence.last_seen = Instant::now();
+        Some(presence.user.clone())
+    }
+
+    /// ends one of the streams connect_to_hangout opened for the user
+    pub fn disconnect(&self, user_id: &str) {
+        if let Some(presence) = self.online.lock().unwrap().get_mut(user_id) {
+            presence.streams = presence.streams.saturating_sub(1);
+            presence.last_seen = Instant::now();
+        }
+    }
+
+    /// creates an empty hangout owned by the user. None is
+    /// returned if a hangout with the name already exists.
+    pub fn create_hangout(&self, name: &str, owner: Option<&str>) -> Option<()> {
+        let mut rooms = self.rooms.lock().unwrap();
+        if rooms.contains_key(name) {
+            return None;
+        }
+
+        rooms.insert(
             name.to_string(),
             Hangout {
                 users: Vec::new(),
                 history: Vec::new(),
-                rx: None,
-                tx: None,
+                index: Index::default(),
+                owner: owner.map(|owner| owner.to_string()),
+                topic: String::new(),
+                kicked: HashSet::new(),
+                typing: HashMap::new(),
+                read_markers: HashMap::new(),
             },
-        )
+        );
+        Some(())
+    }
+
+    pub fn get_topic(&self, name: &str) -> Option<String> {
+        self.rooms
+            .lock()
+            .unwrap()
+            .get(name)
+            .map(|hangout| hangout.topic.clone())
     }
 
     pub fn get_online_users(&self) -> Vec<UserInfo> {
@@ -99,7 +411,7 @@ impl State {
 
         online
             .values()
-            .map(|user| (user.id.clone(), user.handle.clone()))
+            .map(|presence| (presence.user.id.clone(), presence.user.handle.clone()))
             .collect()
     }
 
@@ -108,56 +420,121 @@ impl State {
             return Vec::new();
         };
 
-        rooms.keys().into_iter().map(|key| key.to_owned()).collect()
+        rooms.keys().map(|key| key.to_owned()).collect()
     }
 
-    pub fn init_hangout(&self, name: &str) -> Option<()> {
-        let mut hangout = self.rooms.lock().unwrap();
-        let Some(hangout) = hangout.get_mut(name) else {
-            return None;
+    /// whether the user joined the hangout, false if there is no such hangout
+    pub fn is_member(&self, name: &str, user_id: &str) -> bool {
+        let rooms = self.rooms.lock().unwrap();
+        rooms
+            .get(name)
+            .is_some_and(|hangout| hangout.has_member(user_id))
+    }
+
+    /// returns the handles of everyone who joined the hangout
+    pub fn get_hangout_members(&self, name: &str) -> Option<Vec<String>> {
+        let rooms = self.rooms.lock().unwrap();
+        let hangout = rooms.get(name)?;
+
+        let mut handles: Vec<String> = hangout
+            .users
+            .iter()
+            .map(|user| user.handle.clone())
+            .collect();
+        handles.sort();
+        handles.dedup();
+        Some(handles)
+    }
+
+    /// returns up to limit messages of the hangout which were sent
+    /// before the message with the id `before`, oldest first. Without
+    /// `before` the latest messages are returned.
+    pub fn get_history(
+        &self,
+        name: &str,
+        before: Option<usize>,
+        limit: usize,
+    ) -> Option<Vec<Entry>> {
+        let rooms = self.rooms.lock().unwrap();
+        let hangout = rooms.get(name)?;
+
+        let end = before
+            .unwrap_or(hangout.history.len())
+            .min(hangout.history.len());
+        let start = end.saturating_sub(limit);
+        Some(hangout.history[start..end].to_vec())
+    }
+
+    /// full-text search over the history of the queried hangout, which
+    /// the user has to be in, or of all hangouts the user is in. Hits are
+    /// returned newest first, at most search::MAX_HITS of them.
+    pub fn search(&self, user_id: &str, query: &Query) -> Option<Vec<Hit>> {
+        self.get_user(user_id)?;
+
+        let rooms = self.rooms.lock().unwrap();
+        let hangouts: Vec<(&String, &Hangout)> = match &query.hangout {
+            // hangouts the user is not in are treated as unknown
+            Some(name) => vec![rooms
+                .get_key_value(name)
+                .filter(|(_, hangout)| hangout.has_member(user_id))?],
+            None => rooms
+                .iter()
+                .filter(|(_, hangout)| hangout.has_member(user_id))
+                .collect(),
         };
 
-        if hangout.rx.is_some() && hangout.tx.is_some() {
-            return Some(());
-        }
+        let mut hits: Vec<Hit> = hangouts
+            .into_iter()
+            .flat_map(|(name, hangout)| {
+                hangout
+                    .index
+                    .lookup(&query.text)
+                    .into_iter()
+                    .filter_map(|id| hangout.history.get(id))
+                    .filter(|entry| query.matches(entry))
+                    .map(|entry| Hit {
+                        hangout: name.clone(),
+                        entry: entry.clone(),
+                    })
+            })
+            .collect();
 
-        let (tx, rx) = tokio::sync::broadcast::channel::<Message>(16);
-        hangout.tx = Some(tx);
-        hangout.rx = Some(rx);
+        hits.sort_by_key(|hit| std::cmp::Reverse((hit.entry.sent_at, hit.entry.id)));
+        hits.truncate(search::MAX_HITS);
+        Some(hits)
+    }
 
-        Some(())
+    pub fn init_hangout(&self, name: &str) -> Option<()> {
+        self.rooms.lock().unwrap().get(name).map(|_| ())
     }
 
+    /// subscribes the user to the hangout. Every successful call
+    /// has to be followed by a call to disconnect once the stream
+    /// closes, else the user never goes offline.
     pub fn connect_to_hangout(
         &self,
         name: &str,
         user_id: &str,
     ) -> Option<(Receiver<Message>, Vec<UserInfo>)> {
-        let users = self.online.lock().unwrap();
-        let Some(user) = users.get(user_id) else {
-            return None;
-        };
+        let mut users = self.online.lock().unwrap();
+        let presence = users.get_mut(user_id)?;
 
         let mut hangout = self.rooms.lock().unwrap();
-        let Some(hangout) = hangout.get_mut(name) else {
-            return None;
-        };
-
-        hangout.users.push(user.clone());
+        let hangout = hangout.get_mut(name)?;
 
-        let Some(ref rx) = hangout.rx else {
+        if hangout.kicked.contains(user_id) {
             return None;
-        };
+        }
+
+        hangout.users.push(presence.user.clone());
+        presence.streams += 1;
+        presence.last_seen = Instant::now();
 
-        // TODO:
-        // whoever is already in the hangout will not see that someone joined.
-        // Hence, we would need to streame this particular event to all connected
-        // connections
-        //
-        // used using broadcast_to_hangout in order to include new subscriber.
-        // Else send happens before new subscriber live
+        // whoever is already in the hangout learns about the new user through
+        // a Message::UserJoin the caller sends using broadcast_to_hangout once
+        // the receiver is returned. Else send happens before new subscriber live
         Some((
-            rx.resubscribe(),
+            self.pubsub.subscribe(name),
             hangout
                 .users
                 .iter()
@@ -166,20 +543,232 @@ impl State {
         ))
     }
 
-    pub fn broadcast_to_hangout(&self, name: &str, msg: Message) -> Option<()> {
-        let hangout = self.rooms.lock().unwrap();
-        let hangout = hangout.get(name).unwrap();
+    /// runs the message through the moderation pipeline
+    pub fn moderate(&self, name: &str, user_id: &str, body: &str) -> Option<Verdict> {
+        let user = self.get_user(user_id)?;
 
-        let Some(ref tx) = hangout.tx else {
-            return None;
+        let rooms = self.rooms.lock().unwrap();
+        let hangout = rooms.get(name)?;
+
+        if hangout.kicked.contains(user_id) {
+            return Some(Verdict::Reject(format!(
+                "you have been kicked from {}",
+                name
+            )));
+        }
+
+        let ctx = Context {
+            hangout: name,
+            handle: &user.handle,
+            is_owner: hangout.owner.as_deref() == Some(user_id),
+        };
+        Some(self.moderation.run(&ctx, body))
+    }
+
+    /// changes the handle of the user and returns the previous one.
+    /// Fails with a reason if the handle is taken.
+    pub fn rename_user(&self, user_id: &str, handle: &str) -> Result<String, String> {
+        let mut online = self.online.lock().unwrap();
+        if online
+            .values()
+            .any(|presence| presence.user.handle == handle)
+        {
+            return Err(format!("user handle \"{}\" already exists", handle));
+        }
+
+        let presence = online
+            .get_mut(user_id)
+            .ok_or_else(|| "unknown user".to_string())?;
+        let previous = std::mem::replace(&mut presence.user.handle, handle.to_string());
+
+        // typing indicators and read markers show the new handle as well
+        for hangout in self.rooms.lock().unwrap().values_mut() {
+            hangout
+                .users
+                .iter_mut()
+                .filter(|user| user.id == user_id)
+                .for_each(|user| user.handle = handle.to_string());
+            if let Some(typing) = hangout.typing.get_mut(user_id) {
+                typing.handle = handle.to_string();
+            }
+            if let Some((marker, _)) = hangout.read_markers.get_mut(user_id) {
+                *marker = handle.to_string();
+            }
+        }
+
+        Ok(previous)
+    }
+
+    /// removes the user with the handle from the hangout and keeps them
+    /// from joining again. Returns the id of the kicked user.
+    pub fn kick(&self, name: &str, handle: &str) -> Result<String, String> {
+        let mut rooms = self.rooms.lock().unwrap();
+        let hangout = rooms
+            .get_mut(name)
+            .ok_or_else(|| format!("hangout \"{}\" does not exist", name))?;
+
+        let user_id = hangout
+            .users
+            .iter()
+            .find(|user| user.handle == handle)
+            .map(|user| user.id.clone())
+            .ok_or_else(|| format!("{} is not in {}", handle, name))?;
+
+        hangout.users.retain(|user| user.id != user_id);
+        hangout.typing.remove(&user_id);
+        hangout.kicked.insert(user_id.clone());
+
+        Ok(user_id)
+    }
+
+    pub fn set_topic(&self, name: &str, topic: &str) -> Option<()> {
+        let mut rooms = self.rooms.lock().unwrap();
+        rooms.get_mut(name)?.topic = topic.to_string();
+        Some(())
+    }
+
+    /// appends a message of the user to the hangout's history.
+    /// Sending a message ends the user's typing indicator; the
+    /// returned list is the updated set of typing users if it changed.
+    pub fn record_message(
+        &self,
+        name: &str,
+        user_id: &str,
+        body: &str,
+    ) -> Option<(Entry, Option<Vec<String>>)> {
+        let user = self.get_user(user_id)?;
+
+        let mut rooms = self.rooms.lock().unwrap();
+        let hangout = rooms.get_mut(name)?;
+
+        let entry = Entry {
+            id: hangout.history.len(),
+            author: user.handle,
+            body: body.to_string(),
+            sent_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs() as i64)
+                .unwrap_or_default(),
         };
+        hangout.index.add(&entry);
+        hangout.history.push(entry.clone());
+        self.metrics.message_sent();
+
+        let typing = hangout
+            .typing
+            .remove(user_id)
+            .map(|_| hangout.typing_handles());
+
+        Some((entry, typing))
+    }
 
-        match tx.send(msg) {
-            Err(err) => {
-                error!("Sending message to hangout \"{}\": {}", name, err);
+    /// registers a key stroke of the user in the hangout. Returns the
+    /// handles of all typing users if a typing event should be sent out,
+    /// which is the case if the user was not typing before or the last
+    /// event is older than TYPING_THROTTLE. If the user just started
+    /// typing, the returned instant is the one to expire the indicator with.
+    pub fn user_typing(&self, name: &str, user_id: &str) -> Option<(Vec<String>, Option<Instant>)> {
+        let user = self.get_user(user_id)?;
+
+        let mut rooms = self.rooms.lock().unwrap();
+        let hangout = rooms.get_mut(name)?;
+
+        let now = Instant::now();
+        match hangout.typing.get_mut(user_id) {
+            Some(typing) if now.duration_since(typing.last_sent) < TYPING_THROTTLE => {
+                typing.last_seen = now;
                 None
             }
-            _ => Some(()),
+            Some(typing) => {
+                typing.last_seen = now;
+                typing.last_sent = now;
+                Some((hangout.typing_handles(), None))
+            }
+            None => {
+                hangout.typing.insert(
+                    user_id.to_string(),
+                    Typing {
+                        handle: user.handle,
+                        started: now,
+                        last_seen: now,
+                        last_sent: now,
+                    },
+                );
+                Some((hangout.typing_handles(), Some(now)))
+            }
+        }
+    }
+
+    /// checks whether the user's typing indicator which started at the
+    /// instant has expired. Returns Ok with the remaining typing users if
+    /// the indicator was removed, or Err with the instant at which the
+    /// indicator expires next if the user kept on typing. Ok(None) means
+    /// there was nothing to expire, also when the user stopped typing and
+    /// started again since.
+    pub fn expire_typing(
+        &self,
+        name: &str,
+        user_id: &str,
+        started: Instant,
+    ) -> Result<Option<Vec<String>>, Instant> {
+        let mut rooms = self.rooms.lock().unwrap();
+        let Some(hangout) = rooms.get_mut(name) else {
+            return Ok(None);
+        };
+
+        let Some(typing) = hangout
+            .typing
+            .get(user_id)
+            .filter(|typing| typing.started == started)
+        else {
+            return Ok(None);
+        };
+
+        let expires_at = typing.last_seen + TYPING_EXPIRY;
+        if expires_at > Instant::now() {
+            return Err(expires_at);
+        }
+
+        hangout.typing.remove(user_id);
+        Ok(Some(hangout.typing_handles()))
+    }
+
+    /// moves the user's read marker forward to message_id and returns all
+    /// read markers of the hangout. Markers never move backwards; None is
+    /// returned if the marker did not change or the user is not in the hangout.
+    pub fn mark_read(
+        &self,
+        name: &str,
+        user_id: &str,
+        message_id: usize,
+    ) -> Option<Vec<(String, usize)>> {
+        let user = self.get_user(user_id)?;
+
+        let mut rooms = self.rooms.lock().unwrap();
+        let hangout = rooms
+            .get_mut(name)
+            .filter(|hangout| hangout.has_member(user_id))?;
+
+        if message_id >= hangout.history.len() {
+            return None;
+        }
+
+        match hangout.read_markers.get(user_id) {
+            Some((_, current)) if *current >= message_id => None,
+            _ => {
+                hangout
+                    .read_markers
+                    .insert(user_id.to_string(), (user.handle, message_id));
+                Some(hangout.read_markers())
+            }
         }
     }
+
+    pub fn broadcast_to_hangout(&self, name: &str, msg: Message) -> Option<()> {
+        if !self.rooms.lock().unwrap().contains_key(name) {
+            return None;
+        }
+
+        self.pubsub.publish(name, msg)
+    }
 }
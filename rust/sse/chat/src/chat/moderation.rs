@@ -0,0 +1,268 @@
+/// Context is what a filter knows about the
+/// sender of a message
+pub(crate) struct Context<'a> {
+    pub(crate) hangout: &'a str,
+    pub(crate) handle: &'a str,
+    /// whether the sender created the hangout
+    pub(crate) is_owner: bool,
+}
+
+/// Command is a slash command found in a message
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Command {
+    /// /me <action>, sent as action of the user
+    Me(String),
+    /// /nick <handle>, changes the user's handle
+    Nick(String),
+    /// /kick <handle>, removes a user from the hangout (owners only)
+    Kick(String),
+    /// /topic <topic>, sets the topic of the hangout
+    Topic(String),
+}
+
+/// Verdict is the outcome of a filter
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Verdict {
+    /// hand the (possibly rewritten) message to the next filter
+    Pass(String),
+    /// drop the message, the reason is returned to the sender
+    Reject(String),
+    /// the message is a command, no further filter is applied
+    Command(Command),
+}
+
+/// Filter is a step of the moderation pipeline every message
+/// goes through before it is broadcast. Implement it to add your
+/// own filters and hand them to Pipeline::filter.
+pub(crate) trait Filter: Send + Sync {
+    fn apply(&self, ctx: &Context, body: String) -> Verdict;
+}
+
+/// Pipeline applies its filters in the order they were added
+/// until one of them does not pass the message on.
+#[derive(Default)]
+pub(crate) struct Pipeline {
+    filters: Vec<Box<dyn Filter>>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Pipeline::default()
+    }
+
+    pub fn filter(mut self, filter: impl Filter + 'static) -> Self {
+        self.filters.push(Box::new(filter));
+        self
+    }
+
+    pub fn run(&self, ctx: &Context, body: &str) -> Verdict {
+        let mut body = body.to_string();
+
+        for filter in &self.filters {
+            match filter.apply(ctx, body) {
+                Verdict::Pass(next) => body = next,
+                verdict => return verdict,
+            }
+        }
+
+        Verdict::Pass(body)
+    }
+}
+
+/// Blocklist masks every blocked word (case insensitive) with asterisks
+pub(crate) struct Blocklist {
+    words: Vec<String>,
+}
+
+impl Blocklist {
+    pub fn new(words: impl IntoIterator<Item = String>) -> Self {
+        Blocklist {
+            words: words
+                .into_iter()
+                .map(|word| word.trim().to_lowercase())
+                .filter(|word| !word.is_empty())
+                .collect(),
+        }
+    }
+}
+
+impl Filter for Blocklist {
+    fn apply(&self, _: &Context, body: String) -> Verdict {
+        let masked = map_words(&body, |word| {
+            let bare = word
+                .trim_matches(|c: char| !c.is_alphanumeric())
+                .to_lowercase();
+
+            if self.words.contains(&bare) {
+                "*".repeat(word.chars().count())
+            } else {
+                word.to_string()
+            }
+        });
+
+        Verdict::Pass(masked)
+    }
+}
+
+/// StripLinks replaces links with a placeholder
+pub(crate) struct StripLinks;
+
+impl Filter for StripLinks {
+    fn apply(&self, _: &Context, body: String) -> Verdict {
+        let stripped = map_words(&body, |word| {
+            let lower = word.to_lowercase();
+            if lower.starts_with("http://")
+                || lower.starts_with("https://")
+                || lower.starts_with("www.")
+            {
+                "[link removed]".to_string()
+            } else {
+                word.to_string()
+            }
+        });
+
+        Verdict::Pass(stripped)
+    }
+}
+
+/// replaces every word of the body, words are separated by any
+/// whitespace. The whitespace itself is kept as it is.
+fn map_words(body: &str, f: impl Fn(&str) -> String) -> String {
+    body.split_inclusive(char::is_whitespace)
+        .map(|part| {
+            let word = part.trim_end_matches(char::is_whitespace);
+            f(word) + &part[word.len()..]
+        })
+        .collect()
+}
+
+/// SlashCommands turns messages starting with a known
+/// command into a Command. Unknown commands are rejected.
+pub(crate) struct SlashCommands;
+
+impl Filter for SlashCommands {
+    fn apply(&self, ctx: &Context, body: String) -> Verdict {
+        let Some(command) = body.strip_prefix('/') else {
+            return Verdict::Pass(body);
+        };
+
+        let (name, arg) = command.split_once(' ').unwrap_or((command, ""));
+        let arg = arg.trim().to_string();
+
+        match name {
+            "me" if !arg.is_empty() => Verdict::Command(Command::Me(arg)),
+            "nick" if !arg.is_empty() && !arg.contains(' ') => Verdict::Command(Command::Nick(arg)),
+            "kick" if !ctx.is_owner => {
+                Verdict::Reject(format!("only the owner of {} can kick", ctx.hangout))
+            }
+            "kick" if !arg.is_empty() && arg != ctx.handle => Verdict::Command(Command::Kick(arg)),
+            "topic" => Verdict::Command(Command::Topic(arg)),
+            "me" | "nick" | "kick" => Verdict::Reject(format!("usage: /{} <argument>", name)),
+            _ => Verdict::Reject(format!("unknown command /{}", name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context(is_owner: bool) -> Context<'static> {
+        Context {
+            hangout: "lobby",
+            handle: "ann",
+            is_owner,
+        }
+    }
+
+    fn command(body: &str) -> Verdict {
+        SlashCommands.apply(&context(true), body.to_string())
+    }
+
+    #[test]
+    fn filters_split_on_any_whitespace() {
+        let ctx = context(false);
+        let pipeline = Pipeline::new()
+            .filter(Blocklist::new(["darn".to_string()]))
+            .filter(StripLinks);
+
+        assert_eq!(
+            pipeline.run(&ctx, "darn\tsee\nhttps://example.com  Darn!"),
+            Verdict::Pass("****\tsee\n[link removed]  *****".to_string())
+        );
+    }
+
+    #[test]
+    fn slash_commands_are_parsed() {
+        assert_eq!(
+            command("/me waves  "),
+            Verdict::Command(Command::Me("waves".to_string()))
+        );
+        assert_eq!(
+            command("/nick anna"),
+            Verdict::Command(Command::Nick("anna".to_string()))
+        );
+        assert_eq!(
+            command("/kick bob"),
+            Verdict::Command(Command::Kick("bob".to_string()))
+        );
+        assert_eq!(
+            command("/topic rust and more"),
+            Verdict::Command(Command::Topic("rust and more".to_string()))
+        );
+        assert_eq!(
+            command("/topic"),
+            Verdict::Command(Command::Topic(String::new()))
+        );
+        assert_eq!(command("not /me"), Verdict::Pass("not /me".to_string()));
+    }
+
+    #[test]
+    fn slash_commands_are_checked() {
+        let usage = |name: &str| Verdict::Reject(format!("usage: /{} <argument>", name));
+
+        assert_eq!(command("/me"), usage("me"));
+        assert_eq!(command("/nick"), usage("nick"));
+        assert_eq!(command("/nick an na"), usage("nick"));
+        assert_eq!(command("/kick"), usage("kick"));
+        // owners cannot kick themselves
+        assert_eq!(command("/kick ann"), usage("kick"));
+        assert_eq!(
+            SlashCommands.apply(&context(false), "/kick bob".to_string()),
+            Verdict::Reject("only the owner of lobby can kick".to_string())
+        );
+    }
+
+    #[test]
+    fn unknown_commands_are_rejected() {
+        assert_eq!(
+            command("/shrug"),
+            Verdict::Reject("unknown command /shrug".to_string())
+        );
+        assert_eq!(
+            command("/ me"),
+            Verdict::Reject("unknown command /".to_string())
+        );
+        assert_eq!(
+            command("/Me waves"),
+            Verdict::Reject("unknown command /Me".to_string())
+        );
+    }
+
+    #[test]
+    fn commands_are_not_rewritten_by_later_filters() {
+        let pipeline = Pipeline::new()
+            .filter(SlashCommands)
+            .filter(Blocklist::new(["darn".to_string()]))
+            .filter(StripLinks);
+
+        assert_eq!(
+            pipeline.run(&context(false), "/nick darn"),
+            Verdict::Command(Command::Nick("darn".to_string()))
+        );
+        assert_eq!(
+            pipeline.run(&context(false), "darn www.example.com"),
+            Verdict::Pass("**** [link removed]".to_string())
+        );
+    }
+}
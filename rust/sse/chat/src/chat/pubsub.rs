@@ -0,0 +1,554 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::sync::broadcast::{self, Receiver, Sender};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::sync::Notify;
+use tokio::task::AbortHandle;
+use tracing::{error, info, warn};
+
+use super::Message;
+
+/// number of messages queued for the relay before the
+/// oldest ones are dropped
+pub(crate) const OUTBOX_CAPACITY: usize = 1024;
+
+/// longest line accepted on a relay connection, a longer
+/// one breaks the connection
+const MAX_LINE_LEN: usize = 64 * 1024;
+
+/// PubSub fans out messages of a topic (the name of a hangout)
+/// to everyone who subscribed to the topic.
+pub(crate) trait PubSub: Send + Sync {
+    /// sends the message to every subscriber of the topic
+    fn publish(&self, topic: &str, msg: Message) -> Option<()>;
+    /// returns a receiver for all messages published to
+    /// the topic from now on
+    fn subscribe(&self, topic: &str) -> Receiver<Message>;
+    /// number of local receivers of the topic
+    fn subscribers(&self, topic: &str) -> usize;
+    /// number of messages which never left the process because
+    /// the connection to other processes could not keep up
+    fn dropped(&self) -> u64 {
+        0
+    }
+}
+
+/// InProcess keeps one broadcast channel per topic. Subscribers
+/// only ever see messages published within the same process.
+pub(crate) struct InProcess {
+    capacity: usize,
+    topics: Mutex<HashMap<String, Sender<Message>>>,
+}
+
+impl InProcess {
+    pub fn new(capacity: usize) -> Self {
+        InProcess {
+            capacity,
+            topics: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl PubSub for InProcess {
+    fn publish(&self, topic: &str, msg: Message) -> Option<()> {
+        let topics = self.topics.lock().unwrap();
+
+        // no one subscribed yet, hence there is no one
+        // who could have missed the message
+        let Some(tx) = topics.get(topic) else {
+            return Some(());
+        };
+
+        // send fails only if every receiver has been dropped
+        let _ = tx.send(msg);
+        Some(())
+    }
+
+    fn subscribe(&self, topic: &str) -> Receiver<Message> {
+        let mut topics = self.topics.lock().unwrap();
+
+        topics
+            .entry(topic.to_string())
+            .or_insert_with(|| broadcast::channel::<Message>(self.capacity).0)
+            .subscribe()
+    }
+
+    fn subscribers(&self, topic: &str) -> usize {
+        self.topics
+            .lock()
+            .unwrap()
+            .get(topic)
+            .map_or(0, |tx| tx.receiver_count())
+    }
+}
+
+/// Envelope is a message on the wire between a chat
+/// process and the relay. Each envelope is one line of JSON.
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    topic: String,
+    msg: Message,
+}
+
+/// Outbox queues the lines going to the relay. Once it is full
+/// the oldest line is dropped for every new one, hence a slow or
+/// unreachable relay cannot make the process run out of memory.
+struct Outbox {
+    capacity: usize,
+    lines: Mutex<VecDeque<String>>,
+    ready: Notify,
+    dropped: AtomicU64,
+    closed: AtomicBool,
+}
+
+impl Outbox {
+    fn new(capacity: usize) -> Self {
+        Outbox {
+            capacity: capacity.max(1),
+            lines: Mutex::new(VecDeque::new()),
+            ready: Notify::new(),
+            dropped: AtomicU64::new(0),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    fn push(&self, line: String) {
+        let mut lines = self.lines.lock().unwrap();
+        if lines.len() >= self.capacity {
+            lines.pop_front();
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        lines.push_back(line);
+        drop(lines);
+
+        self.ready.notify_one();
+    }
+
+    /// waits for the next line. None is returned once the
+    /// outbox has been closed and every line has been taken.
+    async fn pop(&self) -> Option<String> {
+        loop {
+            if let Some(line) = self.lines.lock().unwrap().pop_front() {
+                return Some(line);
+            }
+            if self.closed.load(Ordering::Relaxed) {
+                return None;
+            }
+            self.ready.notified().await;
+        }
+    }
+
+    fn close(&self) {
+        self.closed.store(true, Ordering::Relaxed);
+        self.ready.notify_one();
+    }
+}
+
+/// Lines reads the lines of a relay connection, refusing the
+/// ones longer than MAX_LINE_LEN. next_line can be cancelled
+/// and called again without losing what has been read so far.
+struct Lines<R> {
+    reader: BufReader<R>,
+    line: Vec<u8>,
+}
+
+impl<R: AsyncRead + Unpin> Lines<R> {
+    fn new(reader: R) -> Self {
+        Lines {
+            reader: BufReader::new(reader),
+            line: Vec::new(),
+        }
+    }
+
+    /// returns the next line without its newline, None once
+    /// the connection has been closed
+    async fn next_line(&mut self) -> std::io::Result<Option<String>> {
+        // one byte more than allowed tells a line which is too long
+        // apart from one which is just long enough
+        let limit = MAX_LINE_LEN + 1 - self.line.len();
+        (&mut self.reader)
+            .take(limit as u64)
+            .read_until(b'\n', &mut self.line)
+            .await?;
+
+        let line = std::mem::take(&mut self.line);
+        match line.strip_suffix(b"\n") {
+            Some(line) => String::from_utf8(line.to_vec())
+                .map(Some)
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err)),
+            None if line.len() > MAX_LINE_LEN => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("line longer than {} bytes", MAX_LINE_LEN),
+            )),
+            // closed in the middle of a line
+            None => Ok(None),
+        }
+    }
+}
+
+/// TcpRelay connects the process to a relay (see serve_relay) which
+/// forwards every published message to all other connected chat
+/// processes. Messages are delivered to local subscribers directly
+/// and to remote ones through the relay.
+///
+/// Only messages are shared, hangouts are not: a hangout has to be
+/// created in every process whose users should take part in it,
+/// else its messages are dropped by the processes missing it.
+pub(crate) struct TcpRelay {
+    local: Arc<InProcess>,
+    outbox: Arc<Outbox>,
+}
+
+impl TcpRelay {
+    pub async fn connect(addr: String, capacity: usize) -> std::io::Result<Self> {
+        let stream = TcpStream::connect(&addr).await?;
+        info!("connected to relay on {}", &addr);
+
+        let local = Arc::new(InProcess::new(capacity));
+        let outbox = Arc::new(Outbox::new(OUTBOX_CAPACITY));
+
+        tokio::spawn(run_relay_client(
+            addr,
+            stream,
+            local.clone(),
+            outbox.clone(),
+        ));
+
+        Ok(TcpRelay { local, outbox })
+    }
+}
+
+impl Drop for TcpRelay {
+    fn drop(&mut self) {
+        self.outbox.close();
+    }
+}
+
+impl PubSub for TcpRelay {
+    fn publish(&self, topic: &str, msg: Message) -> Option<()> {
+        let envelope = Envelope {
+            topic: topic.to_string(),
+            msg: msg.clone(),
+        };
+
+        match serde_json::to_string(&envelope) {
+            Ok(line) => self.outbox.push(line),
+            Err(err) => error!("encoding message for topic \"{}\": {}", topic, err),
+        }
+
+        self.local.publish(topic, msg)
+    }
+
+    fn subscribe(&self, topic: &str) -> Receiver<Message> {
+        self.local.subscribe(topic)
+    }
+
+    fn subscribers(&self, topic: &str) -> usize {
+        self.local.subscribers(topic)
+    }
+
+    fn dropped(&self) -> u64 {
+        self.outbox.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// keeps the connection to the relay alive, reconnecting
+/// after a second if it drops. Outgoing messages queue up
+/// in the outbox while the relay is unreachable.
+async fn run_relay_client(
+    addr: String,
+    stream: TcpStream,
+    local: Arc<InProcess>,
+    outbox: Arc<Outbox>,
+) {
+    let mut stream = Some(stream);
+    loop {
+        if let Some(stream) = stream.take() {
+            if pump(stream, &local, &outbox).await.is_none() {
+                // PubSub has been dropped, nothing left to do
+                return;
+            }
+            warn!("lost connection to relay on {}", &addr);
+        }
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        match TcpStream::connect(&addr).await {
+            Ok(s) => {
+                info!("reconnected to relay on {}", &addr);
+                stream = Some(s);
+            }
+            Err(err) => error!("connecting to relay on {}: {}", &addr, err),
+        }
+    }
+}
+
+/// moves messages between the relay connection and the local
+/// subscribers. Returns None if there will be no more outgoing
+/// messages and Some if the connection broke.
+async fn pump(stream: TcpStream, local: &InProcess, outbox: &Outbox) -> Option<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = Lines::new(reader);
+
+    loop {
+        tokio::select! {
+            line = lines.next_line() => {
+                let line = match line {
+                    Ok(Some(line)) => line,
+                    Ok(None) => return Some(()),
+                    Err(err) => {
+                        warn!("reading from relay: {}", err);
+                        return Some(());
+                    }
+                };
+
+                match serde_json::from_str::<Envelope>(&line) {
+                    Ok(envelope) => {
+                        local.publish(&envelope.topic, envelope.msg);
+                    }
+                    Err(err) => warn!("invalid message from relay: {}", err),
+                }
+            }
+            line = outbox.pop() => {
+                let mut line = line?;
+                line.push('\n');
+
+                if writer.write_all(line.as_bytes()).await.is_err() {
+                    return Some(());
+                }
+            }
+        }
+    }
+}
+
+/// serve_relay accepts chat processes on the address and forwards
+/// each line one process sends to every other connected process.
+pub(crate) async fn serve_relay(addr: impl ToSocketAddrs) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    info!("starting relay on {}", listener.local_addr()?);
+
+    relay(listener, Peers::default(), OUTBOX_CAPACITY).await
+}
+
+/// Peer is a chat process connected to the relay
+struct Peer {
+    /// lines waiting to be written to the process
+    tx: mpsc::Sender<String>,
+    /// the task serving the connection
+    task: AbortHandle,
+}
+
+/// connected chat processes by id
+type Peers = Arc<Mutex<HashMap<usize, Peer>>>;
+
+/// accepts chat processes on the listener. Up to capacity lines are
+/// queued for each of them, a process which falls further behind is
+/// disconnected so it cannot make the relay run out of memory.
+async fn relay(listener: TcpListener, peers: Peers, capacity: usize) -> std::io::Result<()> {
+    let next_id = AtomicUsize::new(0);
+
+    loop {
+        let (stream, remote) = listener.accept().await?;
+        let id = next_id.fetch_add(1, Ordering::Relaxed);
+        info!("relay peer {} connected from {}", id, remote);
+
+        let (tx, mut rx) = mpsc::channel::<String>(capacity.max(1));
+
+        // the peer is added while the lock is held, hence
+        // the task cannot remove it before it has been added
+        let mut connected = peers.lock().unwrap();
+        let task = tokio::spawn({
+            let peers = peers.clone();
+            async move {
+                let (reader, mut writer) = stream.into_split();
+                let mut lines = Lines::new(reader);
+
+                loop {
+                    tokio::select! {
+                        line = lines.next_line() => {
+                            let line = match line {
+                                Ok(Some(line)) => line,
+                                Ok(None) => break,
+                                Err(err) => {
+                                    warn!("reading from relay peer {}: {}", id, err);
+                                    break;
+                                }
+                            };
+
+                            forward(&peers, id, line);
+                        }
+                        Some(mut line) = rx.recv() => {
+                            line.push('\n');
+                            if writer.write_all(line.as_bytes()).await.is_err() {
+                                break;
+                            }
+                        }
+                    }
+                }
+
+                peers.lock().unwrap().remove(&id);
+                info!("relay peer {} disconnected", id);
+            }
+        });
+        connected.insert(
+            id,
+            Peer {
+                tx,
+                task: task.abort_handle(),
+            },
+        );
+    }
+}
+
+/// queues the line for every peer but the one it came from,
+/// disconnecting the peers whose queue is full
+fn forward(peers: &Peers, from: usize, line: String) {
+    peers.lock().unwrap().retain(|id, peer| {
+        if *id == from {
+            return true;
+        }
+
+        match peer.tx.try_send(line.clone()) {
+            Err(TrySendError::Full(_)) => {
+                warn!("relay peer {} fell behind, disconnecting", id);
+                peer.task.abort();
+                false
+            }
+            // a closed peer removes itself
+            Ok(()) | Err(TrySendError::Closed(_)) => true,
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::{limit::Limits, State, KEEP_ALIVE};
+
+    #[tokio::test]
+    async fn relay_delivers_to_other_process() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let peers = Peers::default();
+        tokio::spawn(relay(listener, peers.clone(), OUTBOX_CAPACITY));
+
+        let state =
+            |relay: TcpRelay| State::with_pubsub(Arc::new(relay), KEEP_ALIVE, Limits::default());
+        let a = state(TcpRelay::connect(addr.clone(), 16).await.unwrap());
+        let b = state(TcpRelay::connect(addr, 16).await.unwrap());
+
+        // hangouts are not shared, each process needs its own
+        a.create_hangout("hangout", None).unwrap();
+        b.create_hangout("hangout", None).unwrap();
+
+        let alice = a.claim_user_handle("alice").unwrap();
+        let bob = b.claim_user_handle("bob").unwrap();
+        let (mut local, _) = a.connect_to_hangout("hangout", &alice).unwrap();
+        let (mut remote, _) = b.connect_to_hangout("hangout", &bob).unwrap();
+
+        // the relay accepts peers in the background, messages
+        // published before it knows about b would not reach b
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while peers.lock().unwrap().len() < 2 {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .expect("relay did not accept both processes");
+
+        a.broadcast_to_hangout("hangout", Message::ChatMessage("hello".to_string()))
+            .unwrap();
+
+        let received = tokio::time::timeout(Duration::from_secs(5), remote.recv())
+            .await
+            .expect("message did not reach the other process");
+        assert!(matches!(received, Ok(Message::ChatMessage(msg)) if msg == "hello"));
+        assert!(matches!(local.recv().await, Ok(Message::ChatMessage(msg)) if msg == "hello"));
+    }
+
+    async fn wait_for_peers(peers: &Peers, count: usize) {
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while peers.lock().unwrap().len() != count {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap_or_else(|_| panic!("relay did not end up with {} peers", count));
+    }
+
+    #[tokio::test]
+    async fn relay_disconnects_peers_which_fall_behind() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let peers = Peers::default();
+        tokio::spawn(relay(listener, peers.clone(), 1));
+
+        let mut sender = TcpStream::connect(addr).await.unwrap();
+        let _stalled = TcpStream::connect(addr).await.unwrap();
+        wait_for_peers(&peers, 2).await;
+
+        // the stalled peer never reads, once the socket buffers
+        // are full the lines queue up in the relay
+        let mut line = "x".repeat(MAX_LINE_LEN - 1);
+        line.push('\n');
+        tokio::spawn(async move { while sender.write_all(line.as_bytes()).await.is_ok() {} });
+
+        wait_for_peers(&peers, 1).await;
+    }
+
+    #[tokio::test]
+    async fn relay_disconnects_peers_sending_overlong_lines() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let peers = Peers::default();
+        tokio::spawn(relay(listener, peers.clone(), OUTBOX_CAPACITY));
+
+        let mut sender = TcpStream::connect(addr).await.unwrap();
+        wait_for_peers(&peers, 1).await;
+
+        sender
+            .write_all("x".repeat(MAX_LINE_LEN + 1).as_bytes())
+            .await
+            .unwrap();
+
+        wait_for_peers(&peers, 0).await;
+        let mut buf = Vec::new();
+        let read = tokio::time::timeout(Duration::from_secs(5), sender.read_to_end(&mut buf))
+            .await
+            .expect("relay kept the connection open");
+        assert!(matches!(read, Ok(0)));
+    }
+
+    #[tokio::test]
+    async fn lines_are_limited_in_length() {
+        let longest = "x".repeat(MAX_LINE_LEN);
+        let input = format!("a\n{}\n{}x\n", longest, longest);
+        let mut lines = Lines::new(input.as_bytes());
+
+        assert_eq!(lines.next_line().await.unwrap().as_deref(), Some("a"));
+        assert_eq!(lines.next_line().await.unwrap(), Some(longest));
+        assert!(lines.next_line().await.is_err());
+
+        let mut lines = Lines::new("closed in the middle".as_bytes());
+        assert_eq!(lines.next_line().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn outbox_drops_oldest() {
+        let outbox = Outbox::new(2);
+        for line in ["a", "b", "c"] {
+            outbox.push(line.to_string());
+        }
+        outbox.close();
+
+        assert_eq!(outbox.dropped.load(Ordering::Relaxed), 1);
+        assert_eq!(outbox.pop().await.as_deref(), Some("b"));
+        assert_eq!(outbox.pop().await.as_deref(), Some("c"));
+        assert_eq!(outbox.pop().await, None);
+    }
+}
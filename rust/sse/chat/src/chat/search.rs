@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use super::Entry;
+
+/// maximum number of hits a search returns
+pub(crate) const MAX_HITS: usize = 100;
+
+/// Index is an inverted index over the messages of a hangout.
+/// It maps every word to the ids of the messages containing it,
+/// in the order they were sent.
+#[derive(Default)]
+pub(crate) struct Index {
+    words: HashMap<String, Vec<usize>>,
+}
+
+impl Index {
+    pub fn add(&mut self, entry: &Entry) {
+        let mut words = tokenize(&entry.body);
+        words.sort();
+        words.dedup();
+
+        for word in words {
+            self.words.entry(word).or_default().push(entry.id);
+        }
+    }
+
+    /// ids of the messages containing every word of the text,
+    /// oldest first. Text without any word matches nothing.
+    pub fn lookup(&self, text: &str) -> Vec<usize> {
+        let words = tokenize(text);
+
+        let mut postings = Vec::with_capacity(words.len());
+        for word in &words {
+            match self.words.get(word) {
+                Some(ids) => postings.push(ids),
+                None => return Vec::new(),
+            }
+        }
+
+        // intersecting from the rarest word keeps the candidates small
+        postings.sort_by_key(|ids| ids.len());
+        let Some((first, rest)) = postings.split_first() else {
+            return Vec::new();
+        };
+
+        first
+            .iter()
+            .filter(|id| rest.iter().all(|ids| ids.binary_search(id).is_ok()))
+            .copied()
+            .collect()
+    }
+}
+
+/// splits text into lowercase words, punctuation is dropped
+fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect()
+}
+
+/// Query narrows down a search, every set field has to match
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Query {
+    pub(crate) text: String,
+    /// search this hangout only, else all hangouts the user is in
+    pub(crate) hangout: Option<String>,
+    /// handle of the author, case insensitive
+    pub(crate) author: Option<String>,
+    /// unix timestamp in seconds, inclusive
+    pub(crate) since: Option<i64>,
+    /// unix timestamp in seconds, inclusive
+    pub(crate) until: Option<i64>,
+}
+
+impl Query {
+    pub fn matches(&self, entry: &Entry) -> bool {
+        self.author
+            .as_ref()
+            .is_none_or(|author| entry.author.eq_ignore_ascii_case(author))
+            && self.since.is_none_or(|since| entry.sent_at >= since)
+            && self.until.is_none_or(|until| entry.sent_at <= until)
+    }
+}
+
+/// Hit is a message found by a search
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Hit {
+    pub(crate) hangout: String,
+    #[serde(flatten)]
+    pub(crate) entry: Entry,
+}
+
+/// parses a YYYY-MM-DD date into the unix timestamp
+/// of its start, in UTC
+pub(crate) fn parse_day(date: &str) -> Option<i64> {
+    let mut parts = date.trim().splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: i64 = parts.next()?.parse().ok()?;
+    let day: i64 = parts.next()?.parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    // days since 1970-01-01 of the proleptic gregorian calendar, see
+    // http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    Some((era * 146_097 + day_of_era - 719_468) * 86_400)
+}
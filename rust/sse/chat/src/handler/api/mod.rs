@@ -0,0 +1,479 @@
+use axum::{
+    async_trait,
+    extract::{
+        rejection::{JsonRejection, PathRejection, QueryRejection},
+        ConnectInfo, DefaultBodyLimit, FromRequestParts, Multipart, Path, Query, State,
+    },
+    http::{
+        header::{AUTHORIZATION, RETRY_AFTER},
+        request::Parts,
+        StatusCode,
+    },
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use axum_extra::extract::CookieJar;
+
+use serde::{Deserialize, Serialize};
+use std::{net::SocketAddr, sync::Arc};
+
+use tracing::error;
+
+use super::attachment::{PublishError, UploadError};
+use crate::chat;
+use crate::chat::attachment::Attachment;
+
+/// maximum number of messages returned by a single history page
+const MAX_PAGE_SIZE: usize = 100;
+
+/// router of the JSON API, nested under /api/v1. It is backed
+/// by the same chat::State as the html handlers, hence messages
+/// sent through the API show up on the SSE and WebSocket streams.
+pub fn router() -> Router<Arc<chat::State>> {
+    Router::new()
+        .route("/users", post(claim_user_handle))
+        .route("/hangouts", get(list_hangouts).post(create_hangout))
+        .route("/hangouts/:name/members", get(list_members))
+        .route(
+            "/hangouts/:name/messages",
+            get(list_messages).post(send_message),
+        )
+        .route(
+            "/hangouts/:name/attachments",
+            // uploads are limited by the attachment store while reading
+            post(upload_attachment).layer(DefaultBodyLimit::disable()),
+        )
+        .route("/search", get(search))
+}
+
+/// ApiError is returned as {"error": {"code": ..., "message": ...}}
+/// with the matching status code.
+#[derive(Debug)]
+pub enum ApiError {
+    InvalidBody(String),
+    /// the path or query string could not be parsed
+    InvalidParameters(String),
+    Unauthorized,
+    HandleTaken(String),
+    HangoutExists(String),
+    UnknownHangout(String),
+    Limited(chat::limit::Limited),
+    /// moderation or a slash command refused the message
+    Rejected(String),
+    FileTooLarge(usize),
+    UnsupportedType,
+    Internal,
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: ErrorDetail,
+}
+
+#[derive(Serialize)]
+struct ErrorDetail {
+    code: &'static str,
+    message: String,
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let retry_after = match &self {
+            ApiError::Limited(chat::limit::Limited::RetryAfter(retry_after)) => Some(*retry_after),
+            ApiError::Limited(chat::limit::Limited::Full) => Some(chat::limit::FULL_RETRY_AFTER),
+            _ => None,
+        };
+
+        let (status, code, message) = match self {
+            ApiError::InvalidBody(reason) => (StatusCode::BAD_REQUEST, "invalid_body", reason),
+            ApiError::InvalidParameters(reason) => {
+                (StatusCode::BAD_REQUEST, "invalid_parameters", reason)
+            }
+            ApiError::Unauthorized => (
+                StatusCode::UNAUTHORIZED,
+                "unauthorized",
+                "claim a user handle and pass its id as bearer token".to_string(),
+            ),
+            ApiError::HandleTaken(handle) => (
+                StatusCode::CONFLICT,
+                "handle_taken",
+                format!("user handle \"{}\" already exists", handle),
+            ),
+            ApiError::HangoutExists(name) => (
+                StatusCode::CONFLICT,
+                "hangout_exists",
+                format!("hangout \"{}\" already exists", name),
+            ),
+            ApiError::UnknownHangout(name) => (
+                StatusCode::NOT_FOUND,
+                "unknown_hangout",
+                format!("hangout \"{}\" does not exist", name),
+            ),
+            ApiError::Limited(chat::limit::Limited::RetryAfter(_)) => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "rate_limited",
+                "too many requests, slow down".to_string(),
+            ),
+            ApiError::Limited(chat::limit::Limited::TooLong(max)) => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "message_too_long",
+                format!("message exceeds {} bytes", max),
+            ),
+            ApiError::Limited(chat::limit::Limited::Full) => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "server_full",
+                "too many users online".to_string(),
+            ),
+            ApiError::Rejected(reason) => (StatusCode::UNPROCESSABLE_ENTITY, "rejected", reason),
+            ApiError::FileTooLarge(max) => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "file_too_large",
+                format!("file exceeds {} bytes", max),
+            ),
+            ApiError::UnsupportedType => (
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "unsupported_type",
+                "only images, pdfs and text files can be shared".to_string(),
+            ),
+            ApiError::Internal => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "internal",
+                "something went wrong, try again later".to_string(),
+            ),
+        };
+
+        let mut response = (
+            status,
+            Json(ErrorBody {
+                error: ErrorDetail { code, message },
+            }),
+        )
+            .into_response();
+
+        if let Some(retry_after) = retry_after {
+            if let Ok(value) = super::retry_after_secs(retry_after).parse() {
+                response.headers_mut().insert(RETRY_AFTER, value);
+            }
+        }
+        response
+    }
+}
+
+impl From<chat::limit::Limited> for ApiError {
+    fn from(limited: chat::limit::Limited) -> Self {
+        ApiError::Limited(limited)
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        ApiError::InvalidBody(rejection.body_text())
+    }
+}
+
+impl From<PathRejection> for ApiError {
+    fn from(rejection: PathRejection) -> Self {
+        ApiError::InvalidParameters(rejection.body_text())
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(rejection: QueryRejection) -> Self {
+        ApiError::InvalidParameters(rejection.body_text())
+    }
+}
+
+/// ApiUser is the id of a claimed user handle, taken from the
+/// `Authorization: Bearer <id>` header or the user_handle cookie
+/// the html handlers set.
+pub struct ApiUser(pub String);
+
+#[async_trait]
+impl FromRequestParts<Arc<chat::State>> for ApiUser {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<chat::State>,
+    ) -> Result<Self, Self::Rejection> {
+        let bearer = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(|id| id.trim().to_string());
+
+        let user_id = match bearer {
+            Some(id) => id,
+            None => CookieJar::from_headers(&parts.headers)
+                .get("user_handle")
+                .map(|cookie| cookie.value().to_string())
+                .ok_or(ApiError::Unauthorized)?,
+        };
+
+        state
+            .get_user(&user_id)
+            .map(|user| ApiUser(user.id))
+            .ok_or(ApiError::Unauthorized)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ClaimUserHandleReq {
+    handle: String,
+}
+
+#[derive(Serialize)]
+pub struct UserResp {
+    id: String,
+    handle: String,
+}
+
+async fn claim_user_handle(
+    State(state): State<Arc<chat::State>>,
+    addr: Option<ConnectInfo<SocketAddr>>,
+    payload: Result<Json<ClaimUserHandleReq>, JsonRejection>,
+) -> Result<(StatusCode, Json<UserResp>), ApiError> {
+    let Json(req) = payload?;
+
+    state.check_claim(addr.map(|ConnectInfo(addr)| addr.ip()))?;
+
+    let id = state
+        .claim_user_handle(&req.handle)
+        .ok_or_else(|| ApiError::HandleTaken(req.handle.clone()))?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(UserResp {
+            id,
+            handle: req.handle,
+        }),
+    ))
+}
+
+#[derive(Serialize)]
+pub struct HangoutsResp {
+    hangouts: Vec<String>,
+}
+
+async fn list_hangouts(State(state): State<Arc<chat::State>>) -> Json<HangoutsResp> {
+    let mut hangouts = state.get_hangout_short();
+    hangouts.sort();
+
+    Json(HangoutsResp { hangouts })
+}
+
+#[derive(Deserialize)]
+pub struct CreateHangoutReq {
+    name: String,
+}
+
+#[derive(Serialize)]
+pub struct HangoutResp {
+    name: String,
+}
+
+async fn create_hangout(
+    State(state): State<Arc<chat::State>>,
+    ApiUser(user_id): ApiUser,
+    payload: Result<Json<CreateHangoutReq>, JsonRejection>,
+) -> Result<(StatusCode, Json<HangoutResp>), ApiError> {
+    let Json(req) = payload?;
+
+    state
+        .create_hangout(&req.name, Some(&user_id))
+        .ok_or_else(|| ApiError::HangoutExists(req.name.clone()))?;
+
+    Ok((StatusCode::CREATED, Json(HangoutResp { name: req.name })))
+}
+
+#[derive(Serialize)]
+pub struct MembersResp {
+    members: Vec<String>,
+}
+
+/// hangouts the user is not in are treated as unknown, as by search
+async fn list_members(
+    State(state): State<Arc<chat::State>>,
+    ApiUser(user_id): ApiUser,
+    path: Result<Path<String>, PathRejection>,
+) -> Result<Json<MembersResp>, ApiError> {
+    let Path(name) = path?;
+    if !state.is_member(&name, &user_id) {
+        return Err(ApiError::UnknownHangout(name));
+    }
+    let members = state
+        .get_hangout_members(&name)
+        .ok_or(ApiError::UnknownHangout(name))?;
+
+    Ok(Json(MembersResp { members }))
+}
+
+#[derive(Deserialize)]
+pub struct SendMessageReq {
+    body: String,
+}
+
+/// responds with the recorded message, or with 204 if the
+/// message was a slash command which did not record one
+async fn send_message(
+    State(state): State<Arc<chat::State>>,
+    path: Result<Path<String>, PathRejection>,
+    addr: Option<ConnectInfo<SocketAddr>>,
+    ApiUser(user_id): ApiUser,
+    payload: Result<Json<SendMessageReq>, JsonRejection>,
+) -> Result<Response, ApiError> {
+    let Path(name) = path?;
+    let Json(req) = payload?;
+
+    state.check_message(&user_id, addr.map(|ConnectInfo(addr)| addr.ip()), &req.body)?;
+
+    match super::publish_message(&state, &name, &user_id, &req.body) {
+        Ok(Some(entry)) => Ok((StatusCode::CREATED, Json(entry)).into_response()),
+        Ok(None) => Ok(StatusCode::NO_CONTENT.into_response()),
+        Err(super::Refused::NotFound) => Err(ApiError::UnknownHangout(name)),
+        Err(super::Refused::Rejected(reason)) => Err(ApiError::Rejected(reason)),
+    }
+}
+
+#[derive(Serialize)]
+pub struct AttachmentResp {
+    message: chat::Entry,
+    attachment: Attachment,
+    url: String,
+}
+
+/// expects a multipart form with the file in the `file` field
+async fn upload_attachment(
+    State(state): State<Arc<chat::State>>,
+    path: Result<Path<String>, PathRejection>,
+    addr: Option<ConnectInfo<SocketAddr>>,
+    ApiUser(user_id): ApiUser,
+    multipart: Multipart,
+) -> Result<(StatusCode, Json<AttachmentResp>), ApiError> {
+    let Path(name) = path?;
+    // before the multipart body is buffered
+    state.check_rate(&user_id, addr.map(|ConnectInfo(addr)| addr.ip()))?;
+
+    let upload = super::attachment::read_upload(multipart, state.attachments.max_size)
+        .await
+        .map_err(|err| match err {
+            UploadError::Malformed(reason) => ApiError::InvalidBody(reason),
+            UploadError::MissingFile => ApiError::InvalidBody("no file in field \"file\"".into()),
+            UploadError::TooLarge(max) => ApiError::FileTooLarge(max),
+        })?;
+
+    state.check_length(&upload.name)?;
+
+    let (message, attachment) =
+        super::attachment::publish_attachment(&state, &name, &user_id, upload)
+            .await
+            .map_err(|err| match err {
+                PublishError::NotFound => ApiError::UnknownHangout(name),
+                PublishError::Store(chat::attachment::Refused::TooLarge(max)) => {
+                    ApiError::FileTooLarge(max)
+                }
+                PublishError::Store(chat::attachment::Refused::UnsupportedType) => {
+                    ApiError::UnsupportedType
+                }
+                PublishError::Store(chat::attachment::Refused::Io(err)) => {
+                    error!("storing attachment: {}", err);
+                    ApiError::Internal
+                }
+            })?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(AttachmentResp {
+            message,
+            url: attachment.url(),
+            attachment,
+        }),
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct HistoryQuery {
+    /// only return messages with an id lower than this one
+    before: Option<usize>,
+    limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct HistoryResp {
+    messages: Vec<chat::Entry>,
+    /// value for `before` to fetch the previous page,
+    /// None if there are no older messages
+    next_before: Option<usize>,
+}
+
+/// hangouts the user is not in are treated as unknown, as by search
+async fn list_messages(
+    State(state): State<Arc<chat::State>>,
+    ApiUser(user_id): ApiUser,
+    path: Result<Path<String>, PathRejection>,
+    query: Result<Query<HistoryQuery>, QueryRejection>,
+) -> Result<Json<HistoryResp>, ApiError> {
+    let Path(name) = path?;
+    let Query(query) = query?;
+    if !state.is_member(&name, &user_id) {
+        return Err(ApiError::UnknownHangout(name));
+    }
+    let limit = query.limit.unwrap_or(50).clamp(1, MAX_PAGE_SIZE);
+
+    let messages = state
+        .get_history(&name, query.before, limit)
+        .ok_or(ApiError::UnknownHangout(name))?;
+
+    let next_before = messages
+        .first()
+        .filter(|entry| entry.id > 0)
+        .map(|entry| entry.id);
+
+    Ok(Json(HistoryResp {
+        messages,
+        next_before,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    /// every word has to occur in a message
+    q: String,
+    /// search this hangout only, else all hangouts the user is in
+    hangout: Option<String>,
+    author: Option<String>,
+    /// unix timestamp in seconds, inclusive
+    since: Option<i64>,
+    /// unix timestamp in seconds, inclusive
+    until: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct SearchResp {
+    /// newest first
+    hits: Vec<chat::search::Hit>,
+}
+
+async fn search(
+    State(state): State<Arc<chat::State>>,
+    ApiUser(user_id): ApiUser,
+    query: Result<Query<SearchQuery>, QueryRejection>,
+) -> Result<Json<SearchResp>, ApiError> {
+    let Query(query) = query?;
+    let hangout = query.hangout.clone();
+    let query = chat::search::Query {
+        text: query.q,
+        hangout: query.hangout,
+        author: query.author,
+        since: query.since,
+        until: query.until,
+    };
+
+    let hits = state
+        .search(&user_id, &query)
+        .ok_or_else(|| ApiError::UnknownHangout(hangout.unwrap_or_default()))?;
+
+    Ok(Json(SearchResp { hits }))
+}
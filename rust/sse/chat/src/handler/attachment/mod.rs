@@ -0,0 +1,254 @@
+use axum::{
+    extract::{multipart::Field, ConnectInfo, Multipart, Path, State},
+    http::{
+        header::{CACHE_CONTROL, CONTENT_TYPE, X_CONTENT_TYPE_OPTIONS},
+        StatusCode,
+    },
+    response::IntoResponse,
+};
+use axum_extra::TypedHeader;
+use headers::Cookie;
+
+use std::{net::SocketAddr, sync::Arc};
+use tracing::error;
+
+use crate::chat;
+use crate::chat::attachment::{self, Attachment};
+
+use super::rendering::escape;
+
+/// maximum size of the form fields next to the file in bytes
+const MAX_FIELD_SIZE: usize = 1024;
+
+/// Upload is a file read from a multipart form
+pub(crate) struct Upload {
+    pub(crate) hangout: Option<String>,
+    pub(crate) name: String,
+    pub(crate) data: Vec<u8>,
+}
+
+/// UploadError is why a multipart form has not been read
+pub(crate) enum UploadError {
+    Malformed(String),
+    MissingFile,
+    TooLarge(usize),
+}
+
+/// reads the `file` field and, if present, the `hangout_id` field of the form.
+/// Reading stops as soon as the file exceeds the maximum size or the
+/// hangout_id exceeds MAX_FIELD_SIZE.
+pub(crate) async fn read_upload(
+    mut multipart: Multipart,
+    max_size: usize,
+) -> Result<Upload, UploadError> {
+    let mut hangout = None;
+    let mut file = None;
+
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|err| UploadError::Malformed(err.body_text()))?
+    {
+        match field.name() {
+            Some("hangout_id") => {
+                let data = read_field(field, MAX_FIELD_SIZE)
+                    .await
+                    .map_err(|err| match err {
+                        UploadError::TooLarge(max) => UploadError::Malformed(format!(
+                            "field hangout_id exceeds {} bytes",
+                            max
+                        )),
+                        err => err,
+                    })?;
+                hangout = Some(String::from_utf8(data).map_err(|_| {
+                    UploadError::Malformed("field hangout_id is not valid UTF-8".to_string())
+                })?);
+            }
+            Some("file") => {
+                let name = field.file_name().unwrap_or("attachment").to_string();
+                file = Some((name, read_field(field, max_size).await?));
+            }
+            _ => {}
+        }
+    }
+
+    let (name, data) = file.ok_or(UploadError::MissingFile)?;
+    Ok(Upload {
+        hangout,
+        name,
+        data,
+    })
+}
+
+async fn read_field(mut field: Field<'_>, max_size: usize) -> Result<Vec<u8>, UploadError> {
+    let mut data = Vec::new();
+
+    while let Some(chunk) = field
+        .chunk()
+        .await
+        .map_err(|err| UploadError::Malformed(err.body_text()))?
+    {
+        if data.len() + chunk.len() > max_size {
+            return Err(UploadError::TooLarge(max_size));
+        }
+        data.extend_from_slice(&chunk);
+    }
+
+    Ok(data)
+}
+
+/// stores the file and shares it in the hangout. The attachment
+/// is recorded in the history like a message, so it can be found
+/// by searching for its name.
+pub(crate) async fn publish_attachment(
+    state: &chat::State,
+    hangout: &str,
+    user_id: &str,
+    upload: Upload,
+) -> Result<(chat::Entry, Attachment), PublishError> {
+    // checked up front, files of unknown hangouts are not stored
+    state
+        .get_hangout_members(hangout)
+        .ok_or(PublishError::NotFound)?;
+
+    let attachment = state
+        .attachments
+        .save(&upload.name, &upload.data)
+        .await
+        .map_err(PublishError::Store)?;
+
+    let body = format!("[attachment] {} {}", attachment.name, attachment.url());
+    let (entry, typing) = state
+        .record_message(hangout, user_id, &body)
+        .ok_or(PublishError::NotFound)?;
+
+    if let Some(typing) = typing {
+        state.broadcast_to_hangout(hangout, chat::Message::Typing(typing));
+    }
+    state.broadcast_to_hangout(
+        hangout,
+        chat::Message::Attachment {
+            author: entry.author.clone(),
+            attachment: attachment.clone(),
+        },
+    );
+
+    Ok((entry, attachment))
+}
+
+pub(crate) enum PublishError {
+    NotFound,
+    Store(attachment::Refused),
+}
+
+pub async fn upload_attachment(
+    State(state): State<Arc<chat::State>>,
+    addr: Option<ConnectInfo<SocketAddr>>,
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    multipart: Multipart,
+) -> impl IntoResponse {
+    let Some(user_id) = cookie
+        .get("user_handle")
+        .filter(|user_id| state.get_user(user_id).is_some())
+    else {
+        return (StatusCode::UNAUTHORIZED, "no user handle claimed").into_response();
+    };
+
+    // the user and the rate limit are checked before the body is read,
+    // nobody else gets the server to buffer a file
+    let ip = addr.map(|ConnectInfo(addr)| addr.ip());
+    if let Err(limited) = state.check_rate(user_id, ip) {
+        return super::limited_response(limited);
+    }
+
+    let upload = match read_upload(multipart, state.attachments.max_size).await {
+        Ok(upload) => upload,
+        Err(UploadError::Malformed(reason)) => {
+            return (StatusCode::BAD_REQUEST, reason).into_response()
+        }
+        Err(UploadError::MissingFile) => {
+            return (StatusCode::BAD_REQUEST, "no file attached").into_response()
+        }
+        Err(UploadError::TooLarge(max)) => {
+            return (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("file exceeds {} bytes", max),
+            )
+                .into_response()
+        }
+    };
+    let Some(hangout) = upload.hangout.clone() else {
+        return (StatusCode::BAD_REQUEST, "no hangout given").into_response();
+    };
+
+    if let Err(limited) = state.check_length(&upload.name) {
+        return super::limited_response(limited);
+    }
+
+    match publish_attachment(&state, &hangout, user_id, upload).await {
+        Ok(_) => (StatusCode::OK, "attachment shared").into_response(),
+        Err(PublishError::NotFound) => {
+            (StatusCode::NOT_FOUND, "hangout or user not found").into_response()
+        }
+        Err(PublishError::Store(attachment::Refused::TooLarge(max))) => (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("file exceeds {} bytes", max),
+        )
+            .into_response(),
+        Err(PublishError::Store(attachment::Refused::UnsupportedType)) => (
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "only images, pdfs and text files can be shared",
+        )
+            .into_response(),
+        Err(PublishError::Store(attachment::Refused::Io(err))) => {
+            error!("storing attachment: {}", err);
+            (StatusCode::INTERNAL_SERVER_ERROR, "unable to store file").into_response()
+        }
+    }
+}
+
+/// serves a stored file. Files never change, hence they may be cached forever.
+pub async fn serve_attachment(
+    State(state): State<Arc<chat::State>>,
+    Path(key): Path<String>,
+) -> impl IntoResponse {
+    match state.attachments.open(&key).await {
+        Some((kind, data)) => (
+            [
+                (CONTENT_TYPE, kind.content_type()),
+                (X_CONTENT_TYPE_OPTIONS, "nosniff"),
+                (CACHE_CONTROL, "public, max-age=31536000, immutable"),
+            ],
+            data,
+        )
+            .into_response(),
+        None => (StatusCode::NOT_FOUND, "attachment not found").into_response(),
+    }
+}
+
+/// html of an attachment message: images are shown inline,
+/// text files with their beginning, anything else as link
+pub(crate) fn render(author: &str, attachment: &Attachment) -> String {
+    let url = attachment.url();
+    let name = escape(&attachment.name);
+
+    let preview = if attachment.is_image() {
+        format!(
+            "<br><img src=\"{}\" alt=\"{}\" style=\"max-width: 20em; max-height: 20em\">",
+            url, name
+        )
+    } else if let Some(text) = &attachment.preview {
+        format!("<pre>{}</pre>", escape(text))
+    } else {
+        String::new()
+    };
+
+    format!(
+        "<div><b>{}</b> shared <a href=\"{}\" target=\"_blank\">{}</a> ({} bytes){}</div>",
+        escape(author),
+        url,
+        name,
+        attachment.size,
+        preview
+    )
+}
@@ -1,17 +1,24 @@
+pub mod api;
+mod attachment;
 mod rendering;
 mod template;
+mod ws;
 
-use rendering::Response;
+pub use attachment::{serve_attachment, upload_attachment};
+pub use ws::connect_ws;
+
+use rendering::{escape, Response};
 
 use axum::{
-    extract::{Form, Path, State},
+    extract::{ConnectInfo, Form, MatchedPath, Path, Query, Request, State},
     http::{
-        header::{HeaderMap, HeaderValue, SET_COOKIE},
+        header::{HeaderMap, HeaderValue, CONTENT_TYPE, RETRY_AFTER, SET_COOKIE},
         StatusCode,
     },
+    middleware::Next,
     response::{
         sse::{Event, KeepAlive, Sse},
-        IntoResponse, Redirect,
+        IntoResponse,
     },
 };
 
@@ -21,21 +28,20 @@ use headers::Cookie;
 use futures::stream::Stream;
 use tokio_stream::StreamExt as _;
 
-use std::{convert::Infallible, time::Duration};
+use std::{convert::Infallible, net::SocketAddr, time::Instant};
 
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
+use tokio::sync::broadcast::Receiver;
 
 use crate::chat;
+use crate::chat::moderation::{Command, Verdict};
 
 pub async fn index(
     State(state): State<Arc<chat::State>>,
     TypedHeader(cookie): TypedHeader<Cookie>,
 ) -> impl IntoResponse {
-    let user_handle = match cookie.get("user_handle") {
-        Some(cookie) => Some(cookie.to_string()),
-        None => None,
-    };
+    let user_handle = cookie.get("user_handle").map(|cookie| cookie.to_string());
 
     Response(template::Index {
         rooms: state.get_hangout_short(),
@@ -50,8 +56,13 @@ pub struct ClaimUserHandleReq {
 }
 pub async fn claim_user_handle(
     State(state): State<Arc<chat::State>>,
+    addr: Option<ConnectInfo<SocketAddr>>,
     Form(req): Form<ClaimUserHandleReq>,
 ) -> impl IntoResponse {
+    if let Err(limited) = state.check_claim(addr.map(|ConnectInfo(addr)| addr.ip())) {
+        return limited_response(limited);
+    }
+
     let Some(user_id) = state.claim_user_handle(&req.user_handle) else {
         return (
             StatusCode::BAD_REQUEST,
@@ -84,18 +95,20 @@ pub struct CreateHangoutReq {
 
 pub async fn create_hangout(
     State(state): State<Arc<chat::State>>,
+    cookie: Option<TypedHeader<Cookie>>,
     Form(req): Form<CreateHangoutReq>,
 ) -> impl IntoResponse {
-    // TODO:: handle !None respone indicating that the hangout room already exisits
-    let _ = state.create_hangout(&req.hangout_name);
+    let owner = cookie
+        .as_ref()
+        .and_then(|TypedHeader(cookie)| cookie.get("user_handle"));
+
+    // an existing hangout is left untouched, either way the list is re-rendered
+    let _ = state.create_hangout(&req.hangout_name, owner);
     Response(template::HangoutList {
         rooms: state.get_hangout_short(),
     })
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct ConnectHangoutReq {}
-
 pub async fn load_hangout(
     State(state): State<Arc<chat::State>>,
     Path(name): Path<String>,
@@ -103,57 +116,491 @@ pub async fn load_hangout(
     state.init_hangout(&name);
 
     rendering::Response(template::Chat {
+        topic: state.get_topic(&name).unwrap_or_default(),
         hangout_id: name,
         user_handle: "unkown".to_string(),
     })
 }
 
+/// liveness probe, fails once the server is shutting down
+pub async fn healthz(State(state): State<Arc<chat::State>>) -> impl IntoResponse {
+    if state.is_shutting_down() {
+        return (StatusCode::SERVICE_UNAVAILABLE, "shutting down");
+    }
+    (StatusCode::OK, "ok")
+}
+
+pub async fn metrics(State(state): State<Arc<chat::State>>) -> impl IntoResponse {
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.render_metrics(),
+    )
+}
+
+/// middleware recording the latency of every handler by route
+pub async fn track_latency(
+    State(state): State<Arc<chat::State>>,
+    request: Request,
+    next: Next,
+) -> axum::response::Response {
+    // the matched route rather than the path keeps the number of series bounded
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let method = request.method().to_string();
+
+    let start = Instant::now();
+    let response = next.run(request).await;
+    state
+        .metrics
+        .observe(&method, &route, response.status().as_u16(), start.elapsed());
+
+    response
+}
+
+/// filled in by the search form of a hangout, empty
+/// fields are not used to narrow down the search
+#[derive(Serialize, Deserialize)]
+pub struct SearchForm {
+    #[serde(default)]
+    q: String,
+    #[serde(default)]
+    hangout: String,
+    #[serde(default)]
+    author: String,
+    /// YYYY-MM-DD
+    #[serde(default)]
+    since: String,
+    /// YYYY-MM-DD, inclusive
+    #[serde(default)]
+    until: String,
+}
+
+pub async fn search(
+    State(state): State<Arc<chat::State>>,
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Query(form): Query<SearchForm>,
+) -> impl IntoResponse {
+    let Some(user_id) = cookie.get("user_handle") else {
+        return (StatusCode::UNAUTHORIZED, "no user handle claimed").into_response();
+    };
+
+    let non_empty = |field: String| Some(field).filter(|field| !field.trim().is_empty());
+    let query = chat::search::Query {
+        text: form.q,
+        hangout: non_empty(form.hangout),
+        author: non_empty(form.author),
+        since: chat::search::parse_day(&form.since),
+        until: chat::search::parse_day(&form.until).map(|day| day + 86_399),
+    };
+
+    match state.search(user_id, &query) {
+        Some(hits) => Response(template::SearchResults { hits }).into_response(),
+        None => (StatusCode::NOT_FOUND, "hangout or user not found").into_response(),
+    }
+}
+
 pub async fn connect_to_hangout(
     State(state): State<Arc<chat::State>>,
     Path(hangout): Path<String>,
     TypedHeader(cookie): TypedHeader<Cookie>,
-) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, (StatusCode, &'static str)> {
     let Some(cookie_user) = cookie.get("user_handle") else {
-        // no cookie no access, redirect to Path("/")
-        // however, function returns Sse<...> and not impl IntoResponse..
-        panic!("No cookie found!");
+        return Err((StatusCode::UNAUTHORIZED, "no user handle claimed"));
     };
-    let (rx, _) = state.connect_to_hangout(&hangout, &cookie_user).unwrap();
+    let Some((rx, connection)) = join_hangout(&state, &hangout, cookie_user) else {
+        return Err((StatusCode::NOT_FOUND, "hangout or user not found"));
+    };
+    let user_id = cookie_user.to_string();
+    let metrics_state = state.clone();
 
     let stream = tokio_stream::wrappers::BroadcastStream::new(rx);
 
-    Sse::new(
+    // ending the stream on shutdown closes the connection,
+    // else graceful shutdown would wait on it forever
+    let stream = futures::StreamExt::take_until(stream, state.on_shutdown());
+
+    Ok(Sse::new(
         stream
-            .map(|msg| match msg.unwrap() {
-                chat::Message::ChatMessage(msg) => Event::default().event("message").data(msg),
-                chat::Message::UserJoin(user_name) => {
-                    Event::default().event("user_join").data(user_name)
+            .filter_map(move |msg| match msg {
+                Ok(msg) => Some(msg),
+                Err(_) => {
+                    metrics_state.metrics.lagged();
+                    None
                 }
             })
+            .take_while(move |msg| !is_kicked(msg, &user_id))
+            .map(move |msg| {
+                // the user stays online until the stream is dropped
+                let _ = &connection;
+                let (event, data) = render_event(&msg);
+                Event::default().event(event).data(data)
+            })
             .map(Ok),
     )
     .keep_alive(
         KeepAlive::new()
-            .interval(Duration::from_secs(1))
+            .interval(state.keep_alive)
             .text("keep-alive-text"),
-    )
+    ))
+}
+
+/// whether the message removes the user from the hangout
+fn is_kicked(msg: &chat::Message, user: &str) -> bool {
+    matches!(msg, chat::Message::Kicked { user_id, .. } if user_id == user)
+}
+
+/// Connection is an open stream of a user. The user
+/// stays online at least until it is dropped.
+struct Connection {
+    state: Arc<chat::State>,
+    user_id: String,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.state.disconnect(&self.user_id);
+    }
+}
+
+/// subscribes the user to the hangout and lets everyone
+/// in the hangout know about the updated user list.
+fn join_hangout(
+    state: &Arc<chat::State>,
+    hangout: &str,
+    user_id: &str,
+) -> Option<(Receiver<chat::Message>, Connection)> {
+    state.init_hangout(hangout)?;
+    let (rx, users) = state.connect_to_hangout(hangout, user_id)?;
+    let connection = Connection {
+        state: state.clone(),
+        user_id: user_id.to_string(),
+    };
+
+    // the receiver is subscribed at this point, so the joining
+    // user sees the updated list as well
+    let mut handles: Vec<String> = users.into_iter().map(|(_, handle)| handle).collect();
+    handles.sort();
+    handles.dedup();
+    state.broadcast_to_hangout(hangout, chat::Message::UserJoin(members_html(&handles)));
+
+    Some((rx, connection))
+}
+
+fn members_html(handles: &[String]) -> String {
+    handles
+        .iter()
+        .map(|handle| format!("<div>{}</div>", escape(handle)))
+        .collect()
+}
+
+/// maps a hangout message to the name of the event and
+/// the html fragment it carries. Used for SSE and WebSocket
+/// clients alike.
+fn render_event(msg: &chat::Message) -> (&'static str, String) {
+    match msg {
+        chat::Message::ChatMessage(msg) => ("message", msg.clone()),
+        chat::Message::UserJoin(user_name) => ("user_join", user_name.clone()),
+        chat::Message::Typing(handles) => ("typing", typing_text(handles)),
+        chat::Message::Read(markers) => (
+            "read",
+            markers
+                .iter()
+                .map(|(handle, id)| format!("<div>{} read up to #{}</div>", escape(handle), id))
+                .collect(),
+        ),
+        chat::Message::Topic(topic) => ("topic", escape(topic)),
+        chat::Message::Notice(notice) => {
+            ("message", format!("<div><i>{}</i></div>", escape(notice)))
+        }
+        chat::Message::Kicked { handle, .. } => (
+            "message",
+            format!("<div><i>{} has been kicked</i></div>", escape(handle)),
+        ),
+        chat::Message::Attachment { author, attachment } => {
+            ("message", attachment::render(author, attachment))
+        }
+    }
+}
+
+/// responds with 429 and Retry-After if the client has to slow down
+fn limited_response(limited: chat::limit::Limited) -> axum::response::Response {
+    let retry_after = match limited {
+        chat::limit::Limited::RetryAfter(retry_after) => Some(retry_after),
+        chat::limit::Limited::Full => Some(chat::limit::FULL_RETRY_AFTER),
+        chat::limit::Limited::TooLong(_) => None,
+    };
+
+    let (status, reason) = limited_reason(limited);
+    match retry_after {
+        Some(retry_after) => (
+            status,
+            [(RETRY_AFTER, retry_after_secs(retry_after))],
+            reason,
+        )
+            .into_response(),
+        None => (status, reason).into_response(),
+    }
+}
+
+/// status and body of the response to a limited request
+fn limited_reason(limited: chat::limit::Limited) -> (StatusCode, String) {
+    match limited {
+        chat::limit::Limited::RetryAfter(_) => (
+            StatusCode::TOO_MANY_REQUESTS,
+            "too many requests, slow down".to_string(),
+        ),
+        chat::limit::Limited::TooLong(max) => (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("message exceeds {} bytes", max),
+        ),
+        chat::limit::Limited::Full => (
+            StatusCode::TOO_MANY_REQUESTS,
+            "too many users online".to_string(),
+        ),
+    }
+}
+
+/// Retry-After in whole seconds, rounded up
+fn retry_after_secs(retry_after: std::time::Duration) -> String {
+    retry_after
+        .as_secs_f64()
+        .ceil()
+        .max(1.0)
+        .min(u32::MAX as f64)
+        .to_string()
+}
+
+fn typing_text(handles: &[String]) -> String {
+    match handles {
+        [] => String::new(),
+        [handle] => format!("{} is typing...", escape(handle)),
+        handles => format!("{} are typing...", escape(&handles.join(", "))),
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct SendReq {
     pub send_message: String,
     pub hangout_id: String,
-    pub user_handle: String,
 }
 
 pub async fn send_message(
     State(state): State<Arc<chat::State>>,
+    addr: Option<ConnectInfo<SocketAddr>>,
+    TypedHeader(cookie): TypedHeader<Cookie>,
     Form(req): Form<SendReq>,
 ) -> impl IntoResponse {
+    let Some(user_id) = cookie.get("user_handle") else {
+        return (StatusCode::UNAUTHORIZED, "no user handle claimed").into_response();
+    };
+
+    let ip = addr.map(|ConnectInfo(addr)| addr.ip());
+    if let Err(limited) = state.check_message(user_id, ip, &req.send_message) {
+        return limited_response(limited);
+    }
+
+    match publish_message(&state, &req.hangout_id, user_id, &req.send_message) {
+        Ok(_) => (StatusCode::OK, "message send to channel").into_response(),
+        Err(refused) => refused.reason().into_response(),
+    }
+}
+
+/// Refused is why a message has not been published
+pub enum Refused {
+    NotFound,
+    /// the moderation pipeline or a command refused the message
+    Rejected(String),
+}
+
+impl Refused {
+    /// status and body of the response to a refused message
+    fn reason(self) -> (StatusCode, String) {
+        match self {
+            Refused::NotFound => (
+                StatusCode::NOT_FOUND,
+                "hangout or user not found".to_string(),
+            ),
+            Refused::Rejected(reason) => (StatusCode::UNPROCESSABLE_ENTITY, reason),
+        }
+    }
+}
+
+/// runs the message through the moderation pipeline and publishes
+/// it to the hangout. Returns the recorded entry, or None if the
+/// message was a command which did not produce one.
+fn publish_message(
+    state: &chat::State,
+    hangout: &str,
+    user_id: &str,
+    body: &str,
+) -> Result<Option<chat::Entry>, Refused> {
+    match state
+        .moderate(hangout, user_id, body)
+        .ok_or(Refused::NotFound)?
+    {
+        Verdict::Pass(body) => record_message(state, hangout, user_id, &body).map(Some),
+        Verdict::Reject(reason) => Err(Refused::Rejected(reason)),
+        Verdict::Command(command) => run_command(state, hangout, user_id, command),
+    }
+}
+
+fn run_command(
+    state: &chat::State,
+    hangout: &str,
+    user_id: &str,
+    command: Command,
+) -> Result<Option<chat::Entry>, Refused> {
+    match command {
+        Command::Me(action) => {
+            record_message(state, hangout, user_id, &format!("/me {}", action)).map(Some)
+        }
+        Command::Nick(handle) => {
+            let previous = state
+                .rename_user(user_id, &handle)
+                .map_err(Refused::Rejected)?;
+
+            state.broadcast_to_hangout(
+                hangout,
+                chat::Message::Notice(format!("{} is now known as {}", previous, handle)),
+            );
+            broadcast_members(state, hangout);
+            Ok(None)
+        }
+        Command::Kick(handle) => {
+            let kicked = state.kick(hangout, &handle).map_err(Refused::Rejected)?;
+
+            state.broadcast_to_hangout(
+                hangout,
+                chat::Message::Kicked {
+                    user_id: kicked,
+                    handle,
+                },
+            );
+            broadcast_members(state, hangout);
+            Ok(None)
+        }
+        Command::Topic(topic) => {
+            state.set_topic(hangout, &topic).ok_or(Refused::NotFound)?;
+            state.broadcast_to_hangout(hangout, chat::Message::Topic(topic));
+            Ok(None)
+        }
+    }
+}
+
+fn broadcast_members(state: &chat::State, hangout: &str) {
+    if let Some(handles) = state.get_hangout_members(hangout) {
+        state.broadcast_to_hangout(hangout, chat::Message::UserJoin(members_html(&handles)));
+    }
+}
+
+fn record_message(
+    state: &chat::State,
+    hangout: &str,
+    user_id: &str,
+    body: &str,
+) -> Result<chat::Entry, Refused> {
+    let (entry, typing) = state
+        .record_message(hangout, user_id, body)
+        .ok_or(Refused::NotFound)?;
+
+    if let Some(typing) = typing {
+        state.broadcast_to_hangout(hangout, chat::Message::Typing(typing));
+    }
+
+    // actions sent with /me are recorded as such
+    let content = match entry.body.strip_prefix("/me ") {
+        Some(action) => format!("<i>* {} {}</i>", escape(&entry.author), escape(action)),
+        None => format!("<b>{}</b>: {}", escape(&entry.author), escape(&entry.body)),
+    };
+    let vals = serde_json::json!({ "hangout_id": hangout, "message_id": entry.id });
+
     state.broadcast_to_hangout(
-        &req.hangout_id,
-        chat::Message::ChatMessage(format!("<div>{}</div>", &req.send_message)),
+        hangout,
+        chat::Message::ChatMessage(format!(
+            "<div id=\"msg-{id}\" hx-post=\"/chat/read\" hx-trigger=\"revealed\" hx-swap=\"none\" \
+             hx-vals=\"{vals}\">{content}</div>",
+            id = entry.id,
+            vals = escape(&vals.to_string()),
+        )),
     );
 
-    (StatusCode::OK, "message send to channel")
+    Ok(entry)
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TypingReq {
+    pub hangout_id: String,
+}
+
+pub async fn typing(
+    State(state): State<Arc<chat::State>>,
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Form(req): Form<TypingReq>,
+) -> impl IntoResponse {
+    let Some(user_id) = cookie.get("user_handle") else {
+        return StatusCode::UNAUTHORIZED;
+    };
+
+    publish_typing(&state, &req.hangout_id, user_id);
+
+    StatusCode::OK
+}
+
+fn publish_typing(state: &Arc<chat::State>, hangout: &str, user_id: &str) {
+    // throttled: key strokes within TYPING_THROTTLE only keep the indicator alive
+    let Some((typing, started)) = state.user_typing(hangout, user_id) else {
+        return;
+    };
+    state.broadcast_to_hangout(hangout, chat::Message::Typing(typing));
+
+    // a single task expires an indicator, the one of the first key stroke
+    let Some(started) = started else {
+        return;
+    };
+    let state = state.clone();
+    let hangout = hangout.to_string();
+    let user_id = user_id.to_string();
+    tokio::spawn(async move {
+        let mut expires_at = tokio::time::Instant::now() + chat::TYPING_EXPIRY;
+        loop {
+            tokio::time::sleep_until(expires_at).await;
+            match state.expire_typing(&hangout, &user_id, started) {
+                Ok(Some(typing)) => {
+                    state.broadcast_to_hangout(&hangout, chat::Message::Typing(typing));
+                    return;
+                }
+                Ok(None) => return,
+                Err(next) => expires_at = next.into(),
+            }
+        }
+    });
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ReadReq {
+    pub hangout_id: String,
+    pub message_id: usize,
+}
+
+pub async fn mark_read(
+    State(state): State<Arc<chat::State>>,
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Form(req): Form<ReadReq>,
+) -> impl IntoResponse {
+    let Some(user_id) = cookie.get("user_handle") else {
+        return StatusCode::UNAUTHORIZED;
+    };
+
+    publish_read(&state, &req.hangout_id, user_id, req.message_id);
+
+    StatusCode::OK
+}
+
+fn publish_read(state: &chat::State, hangout: &str, user_id: &str, message_id: usize) {
+    if let Some(markers) = state.mark_read(hangout, user_id, message_id) {
+        state.broadcast_to_hangout(hangout, chat::Message::Read(markers));
+    }
 }
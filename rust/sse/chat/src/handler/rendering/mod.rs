@@ -21,3 +21,14 @@ where
         }
     }
 }
+
+/// escapes text for use in html content and quoted attribute
+/// values. Everything users send has to go through it before
+/// it ends up in a fragment pushed to the browsers.
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
@@ -20,4 +20,11 @@ pub(crate) struct Chat {
     pub(crate) hangout_id: String, // will crash if hangout name has space - would need url
     // encoding
     pub(crate) user_handle: String,
+    pub(crate) topic: String,
+}
+
+#[derive(Template)]
+#[template(path = "search_results.html")]
+pub(crate) struct SearchResults {
+    pub(crate) hits: Vec<crate::chat::search::Hit>,
 }
@@ -0,0 +1,183 @@
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        ConnectInfo, Path, State,
+    },
+    http::StatusCode,
+    response::IntoResponse,
+};
+use axum_extra::TypedHeader;
+use headers::Cookie;
+
+use futures::{SinkExt, StreamExt};
+use tokio::sync::{broadcast::Receiver, mpsc};
+use tokio_stream::wrappers::BroadcastStream;
+
+use serde::{Deserialize, Serialize};
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+use tracing::warn;
+
+use crate::chat;
+
+/// Frame is what a WebSocket client sends to the hangout,
+/// e.g. {"type": "message", "body": "hello"}
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Frame {
+    Message { body: String },
+    Typing,
+    Read { message_id: usize },
+}
+
+/// Event is what a WebSocket client receives. Event names
+/// and data are the same as the ones on the SSE stream.
+#[derive(Serialize)]
+struct Event<'a> {
+    event: &'a str,
+    data: String,
+}
+
+/// Refusal is sent to the client instead of publishing a frame it
+/// sent. Status and data are the ones an HTTP request would get,
+/// e.g. {"event": "error", "status": 429, "data": "too many requests, slow down"}
+#[derive(Serialize)]
+struct Refusal {
+    event: &'static str,
+    status: u16,
+    data: String,
+}
+
+impl Refusal {
+    fn new((status, data): (StatusCode, String)) -> Self {
+        Refusal {
+            event: "error",
+            status: status.as_u16(),
+            data,
+        }
+    }
+}
+
+/// number of refusals waiting to be sent, further ones are
+/// dropped until the client catches up
+const REFUSALS_CAPACITY: usize = 16;
+
+pub async fn connect_ws(
+    State(state): State<Arc<chat::State>>,
+    Path(hangout): Path<String>,
+    addr: Option<ConnectInfo<SocketAddr>>,
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    ws: WebSocketUpgrade,
+) -> impl IntoResponse {
+    let Some(user_id) = cookie.get("user_handle") else {
+        return (StatusCode::UNAUTHORIZED, "no user handle claimed").into_response();
+    };
+
+    let Some((rx, connection)) = super::join_hangout(&state, &hangout, user_id) else {
+        return (StatusCode::NOT_FOUND, "hangout or user not found").into_response();
+    };
+
+    let user_id = user_id.to_string();
+    let ip = addr.map(|ConnectInfo(addr)| addr.ip());
+    ws.on_upgrade(move |socket| async move {
+        serve(socket, state, hangout, user_id, ip, rx).await;
+        drop(connection);
+    })
+}
+
+/// serve pushes every message of the hangout to the socket while
+/// handling the frames the client sends. Whichever direction ends
+/// first closes the connection.
+async fn serve(
+    socket: WebSocket,
+    state: Arc<chat::State>,
+    hangout: String,
+    user_id: String,
+    ip: Option<IpAddr>,
+    rx: Receiver<chat::Message>,
+) {
+    let (mut sender, mut receiver) = socket.split();
+    let shutdown = state.on_shutdown();
+    let kicked_id = user_id.clone();
+    let send_state = state.clone();
+
+    let (refusals_tx, mut refusals) = mpsc::channel::<Refusal>(REFUSALS_CAPACITY);
+
+    let mut send_task = tokio::spawn(async move {
+        // closing the socket on shutdown ends both directions
+        let mut stream = std::pin::pin!(BroadcastStream::new(rx).take_until(shutdown));
+        loop {
+            let text = tokio::select! {
+                msg = stream.next() => {
+                    let Some(msg) = msg else {
+                        break;
+                    };
+                    let Ok(msg) = msg else {
+                        send_state.metrics.lagged();
+                        continue;
+                    };
+
+                    if super::is_kicked(&msg, &kicked_id) {
+                        break;
+                    }
+
+                    let (event, data) = super::render_event(&msg);
+                    serde_json::to_string(&Event { event, data })
+                }
+                Some(refusal) = refusals.recv() => serde_json::to_string(&refusal),
+            };
+            let Ok(text) = text else {
+                continue;
+            };
+
+            if sender.send(Message::Text(text)).await.is_err() {
+                return;
+            }
+        }
+
+        let _ = sender.send(Message::Close(None)).await;
+    });
+
+    let mut recv_task = tokio::spawn(async move {
+        let refuse = |reason: (StatusCode, String)| {
+            // a client which does not read its refusals misses some
+            let _ = refusals_tx.try_send(Refusal::new(reason));
+        };
+
+        while let Some(Ok(msg)) = receiver.next().await {
+            let text = match msg {
+                Message::Text(text) => text,
+                Message::Close(_) => break,
+                _ => continue,
+            };
+
+            match serde_json::from_str::<Frame>(&text) {
+                Ok(Frame::Message { body }) => {
+                    if let Err(limited) = state.check_message(&user_id, ip, &body) {
+                        warn!("Dropped message of \"{}\": {:?}", &user_id, limited);
+                        refuse(super::limited_reason(limited));
+                    } else if let Err(refused) =
+                        super::publish_message(&state, &hangout, &user_id, &body)
+                    {
+                        refuse(refused.reason());
+                    }
+                }
+                Ok(Frame::Typing) => super::publish_typing(&state, &hangout, &user_id),
+                Ok(Frame::Read { message_id }) => {
+                    super::publish_read(&state, &hangout, &user_id, message_id)
+                }
+                Err(err) => {
+                    warn!("Invalid frame from \"{}\": {}", &user_id, err);
+                    refuse((StatusCode::BAD_REQUEST, format!("invalid frame: {}", err)));
+                }
+            }
+        }
+    });
+
+    tokio::select! {
+        _ = &mut send_task => recv_task.abort(),
+        _ = &mut recv_task => send_task.abort(),
+    }
+}
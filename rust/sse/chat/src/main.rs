@@ -1,42 +1,235 @@
-use axum::routing::{get, post};
-use std::sync::Arc;
-use tokio::{self};
-use tower_http::services::ServeDir;
-use tracing::info;
+use axum::{
+    extract::{DefaultBodyLimit, MatchedPath, Request},
+    middleware,
+    routing::{get, post},
+};
+use clap::Parser;
+use std::{net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
+use tower_http::{
+    services::ServeDir,
+    trace::{DefaultOnResponse, TraceLayer},
+};
+use tracing::{error, info, info_span, warn, Level};
 use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt};
 
 mod chat;
 mod handler;
+#[cfg(test)]
+mod tests;
+
+use chat::limit::Limits;
+use chat::moderation::{Blocklist, Pipeline, SlashCommands, StripLinks};
+
+/// Every flag can also be set through the environment variable
+/// next to it. Templates are compiled into the binary by askama,
+/// hence only the asset directory can be changed at runtime.
+#[derive(Debug, Parser)]
+struct Args {
+    /// address the http server listens on
+    #[arg(long, env = "CHAT_ADDR", default_value = "127.0.0.1:3000")]
+    addr: SocketAddr,
+    /// directory served under /assets
+    #[arg(long, env = "CHAT_ASSETS", default_value = "assets")]
+    assets: PathBuf,
+    /// number of messages a hangout buffers for slow subscribers
+    #[arg(long, env = "CHAT_CHANNEL_CAPACITY", default_value_t = chat::CHANNEL_CAPACITY)]
+    channel_capacity: usize,
+    /// seconds between keep-alive messages on SSE streams
+    #[arg(long, env = "CHAT_KEEP_ALIVE", default_value_t = chat::KEEP_ALIVE.as_secs())]
+    keep_alive: u64,
+    /// connect to the relay on this address to share hangouts with
+    /// other chat processes. Without it hangouts are fanned out in-process only
+    #[arg(long, env = "CHAT_RELAY")]
+    relay: Option<String>,
+    /// host the relay other chat processes connect to on this address
+    #[arg(long, env = "CHAT_RELAY_LISTEN")]
+    relay_listen: Option<String>,
+    /// messages per second a session may send
+    #[arg(long, env = "CHAT_SESSION_MESSAGE_RATE", default_value_t = Limits::default().session_messages)]
+    session_message_rate: f64,
+    /// messages per second all sessions of one ip may send
+    #[arg(long, env = "CHAT_IP_MESSAGE_RATE", default_value_t = Limits::default().ip_messages)]
+    ip_message_rate: f64,
+    /// user handles one ip may claim per minute
+    #[arg(long, env = "CHAT_IP_CLAIM_RATE", default_value_t = Limits::default().ip_claims)]
+    ip_claim_rate: f64,
+    /// maximum length of a message in bytes
+    #[arg(long, env = "CHAT_MAX_MESSAGE_LEN", default_value_t = Limits::default().max_message_len)]
+    max_message_len: usize,
+    /// maximum number of claimed user handles
+    #[arg(long, env = "CHAT_MAX_ONLINE", default_value_t = Limits::default().max_online)]
+    max_online: usize,
+    /// seconds after which a user without open streams or requests gives up their handle
+    #[arg(long, env = "CHAT_IDLE_TIMEOUT", default_value_t = Limits::default().idle_timeout.as_secs())]
+    idle_timeout: u64,
+    /// comma separated words which are masked in messages
+    #[arg(long, env = "CHAT_BLOCKLIST", value_delimiter = ',')]
+    blocklist: Vec<String>,
+    /// replace links in messages with a placeholder
+    #[arg(long, env = "CHAT_STRIP_LINKS")]
+    strip_links: bool,
+    /// directory shared files are stored in
+    #[arg(long, env = "CHAT_ATTACHMENTS", default_value = "attachments")]
+    attachments: PathBuf,
+    /// maximum size of a shared file in bytes
+    #[arg(long, env = "CHAT_MAX_ATTACHMENT_SIZE", default_value_t = chat::attachment::MAX_SIZE)]
+    max_attachment_size: usize,
+}
 
 #[tokio::main]
 async fn main() {
     tracing_subscriber::registry()
         .with(
-            tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| "chat".into()),
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| "chat,tower_http=info".into()),
         )
         .with(fmt::layer())
         .init();
 
-    let shared_state = Arc::new(chat::State::new());
+    let args = Args::parse();
+
+    if let Some(addr) = args.relay_listen {
+        tokio::spawn(async move {
+            if let Err(err) = chat::pubsub::serve_relay(addr).await {
+                error!("relay stopped: {}", err);
+            }
+        });
+    }
+
+    let pubsub: Arc<dyn chat::pubsub::PubSub> = match args.relay {
+        Some(addr) => match chat::pubsub::TcpRelay::connect(addr, args.channel_capacity).await {
+            Ok(relay) => Arc::new(relay),
+            Err(err) => {
+                error!("connecting to relay: {}", err);
+                return;
+            }
+        },
+        None => Arc::new(chat::pubsub::InProcess::new(args.channel_capacity)),
+    };
+
+    let limits = Limits {
+        session_messages: args.session_message_rate,
+        ip_messages: args.ip_message_rate,
+        ip_claims: args.ip_claim_rate,
+        max_message_len: args.max_message_len,
+        max_online: args.max_online,
+        idle_timeout: Duration::from_secs(args.idle_timeout),
+    };
+
+    // commands come first, else the other filters would rewrite them
+    let mut moderation = Pipeline::new()
+        .filter(SlashCommands)
+        .filter(Blocklist::new(args.blocklist));
+    if args.strip_links {
+        moderation = moderation.filter(StripLinks);
+    }
+
+    let shared_state = Arc::new(
+        chat::State::with_pubsub(pubsub, Duration::from_secs(args.keep_alive), limits)
+            .with_moderation(moderation)
+            .with_attachments(chat::attachment::Store::new(
+                args.attachments,
+                args.max_attachment_size,
+            )),
+    );
+
+    if !args.assets.is_dir() {
+        warn!("asset directory {} does not exist", args.assets.display());
+    }
+
+    let listener = match tokio::net::TcpListener::bind(args.addr).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            error!("binding {}: {}", args.addr, err);
+            return;
+        }
+    };
 
-    let assets = std::env::current_dir().unwrap();
-    let router = axum::Router::new()
+    info!("starting http server on {}", args.addr);
+    let shutdown = {
+        let state = shared_state.clone();
+        async move {
+            shutdown_signal().await;
+            info!("shutting down, closing open streams");
+            state.shutdown();
+        }
+    };
+
+    // the peer address is needed for per-ip rate limits
+    let app = router(shared_state, args.assets).into_make_service_with_connect_info::<SocketAddr>();
+    if let Err(err) = axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown)
+        .await
+    {
+        error!("http server stopped: {}", err);
+    }
+}
+
+fn router(state: Arc<chat::State>, assets: PathBuf) -> axum::Router {
+    axum::Router::new()
         .route("/", get(handler::index))
         .route("/hangout", post(handler::create_hangout))
         .route("/connect/:hangout", get(handler::load_hangout))
         .route("/sse/:hangout", get(handler::connect_to_hangout))
+        .route("/ws/:hangout", get(handler::connect_ws))
         .route("/chat/message", post(handler::send_message))
+        .route("/chat/typing", post(handler::typing))
+        .route("/chat/read", post(handler::mark_read))
+        .route(
+            "/chat/attachment",
+            // uploads are limited by the attachment store while reading
+            post(handler::upload_attachment).layer(DefaultBodyLimit::disable()),
+        )
+        .route("/attachments/:key", get(handler::serve_attachment))
+        .route("/healthz", get(handler::healthz))
+        .route("/metrics", get(handler::metrics))
         .route("/user", post(handler::claim_user_handle))
-        .nest_service(
-            "/assets",
-            ServeDir::new(format!("{}/assets", assets.to_str().unwrap())),
+        .route("/search", get(handler::search))
+        .nest("/api/v1", handler::api::router())
+        .nest_service("/assets", ServeDir::new(assets))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            handler::track_latency,
+        ))
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(|request: &Request| {
+                    let route = request
+                        .extensions()
+                        .get::<MatchedPath>()
+                        .map(MatchedPath::as_str)
+                        .unwrap_or("unmatched");
+                    info_span!("request", method = %request.method(), route)
+                })
+                .on_response(DefaultOnResponse::new().level(Level::INFO)),
         )
-        .with_state(shared_state);
+        .with_state(state)
+}
 
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:3000")
-        .await
-        .unwrap();
+/// resolves on Ctrl-C or SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(err) => {
+                error!("installing SIGTERM handler: {}", err);
+                std::future::pending::<()>().await
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
 
-    info!("starting http server on 127.0.0.1:3000");
-    axum::serve(listener, router).await.unwrap()
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
 }
@@ -0,0 +1,712 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{
+    body::{Body, BodyDataStream},
+    http::{
+        header::{CONTENT_TYPE, COOKIE, RETRY_AFTER, SET_COOKIE},
+        Request, StatusCode,
+    },
+    Router,
+};
+use futures::{SinkExt, StreamExt};
+use tokio_tungstenite::tungstenite::{client::IntoClientRequest, Message as WsMessage};
+use tower::ServiceExt;
+
+use crate::chat;
+
+/// time a stream gets to deliver an expected event
+const EVENT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// builds the router on a fresh state, as main does
+fn app() -> Router {
+    app_with(chat::limit::Limits::default())
+}
+
+fn app_with(limits: chat::limit::Limits) -> Router {
+    let state = chat::State::with_pubsub(
+        Arc::new(chat::pubsub::InProcess::new(chat::CHANNEL_CAPACITY)),
+        chat::KEEP_ALIVE,
+        limits,
+    )
+    // uploads of the tests stay out of the working directory
+    .with_attachments(chat::attachment::Store::new(
+        std::env::temp_dir().join("chat-tests-attachments"),
+        chat::attachment::MAX_SIZE,
+    ));
+
+    crate::router(Arc::new(state), "assets".into())
+}
+
+fn form(uri: &str, body: &str, cookie: Option<&str>) -> Request<Body> {
+    let mut request = Request::post(uri).header(CONTENT_TYPE, "application/x-www-form-urlencoded");
+    if let Some(user_id) = cookie {
+        request = request.header(COOKIE, format!("user_handle={}", user_id));
+    }
+    request.body(Body::from(body.to_string())).unwrap()
+}
+
+fn get(uri: &str, cookie: Option<&str>) -> Request<Body> {
+    let mut request = Request::get(uri);
+    if let Some(user_id) = cookie {
+        request = request.header(COOKIE, format!("user_handle={}", user_id));
+    }
+    request.body(Body::empty()).unwrap()
+}
+
+/// claims the handle and returns the user id set as cookie
+async fn claim(app: &Router, handle: &str) -> String {
+    let response = app
+        .clone()
+        .oneshot(form("/user", &format!("user_handle={}", handle), None))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let cookie = response.headers()[SET_COOKIE].to_str().unwrap();
+    cookie
+        .strip_prefix("user_handle=")
+        .expect("user_handle cookie")
+        .to_string()
+}
+
+async fn create_hangout(app: &Router, name: &str, user_id: &str) {
+    let response = app
+        .clone()
+        .oneshot(form(
+            "/hangout",
+            &format!("hangout_name={}", name),
+            Some(user_id),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+async fn connect(app: &Router, hangout: &str, user_id: &str) -> BodyDataStream {
+    let response = app
+        .clone()
+        .oneshot(get(&format!("/sse/{}", hangout), Some(user_id)))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[CONTENT_TYPE], "text/event-stream");
+
+    response.into_body().into_data_stream()
+}
+
+/// reads the stream until an event with the name carrying the text
+/// arrives and returns it
+async fn expect_event(stream: &mut BodyDataStream, event: &str, text: &str) -> String {
+    let mut seen = String::new();
+    let wanted = format!("event: {}\ndata: ", event);
+
+    let found = tokio::time::timeout(EVENT_TIMEOUT, async {
+        while let Some(chunk) = stream.next().await {
+            seen.push_str(&String::from_utf8_lossy(&chunk.unwrap()));
+            if let Some(raw) = seen
+                .split("\n\n")
+                .find(|raw| raw.starts_with(&wanted) && raw.contains(text))
+            {
+                return Some(raw.to_string());
+            }
+        }
+        None
+    })
+    .await;
+
+    match found {
+        Ok(Some(raw)) => raw,
+        _ => panic!("no {} event with \"{}\", got:\n{}", event, text, seen),
+    }
+}
+
+#[tokio::test]
+async fn message_reaches_every_stream_of_the_hangout() {
+    let app = app();
+    let ann = claim(&app, "ann").await;
+    let bob = claim(&app, "bob").await;
+    create_hangout(&app, "lobby", &ann).await;
+
+    let mut ann_stream = connect(&app, "lobby", &ann).await;
+    let mut bob_stream = connect(&app, "lobby", &bob).await;
+    // bob joining is announced to both
+    expect_event(&mut ann_stream, "user_join", "<div>bob</div>").await;
+    expect_event(&mut bob_stream, "user_join", "<div>bob</div>").await;
+
+    let response = app
+        .clone()
+        .oneshot(form(
+            "/chat/message",
+            "send_message=hello+there&hangout_id=lobby",
+            Some(&bob),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    expect_event(&mut ann_stream, "message", "<b>bob</b>: hello there").await;
+    expect_event(&mut bob_stream, "message", "<b>bob</b>: hello there").await;
+}
+
+#[tokio::test]
+async fn typing_and_read_receipts_reach_the_hangout() {
+    let app = app();
+    let ann = claim(&app, "ann").await;
+    let bob = claim(&app, "bob").await;
+    let cid = claim(&app, "cid").await;
+    create_hangout(&app, "lobby", &ann).await;
+    let mut ann_stream = connect(&app, "lobby", &ann).await;
+    let mut bob_stream = connect(&app, "lobby", &bob).await;
+
+    let post = |uri: &'static str, body: &'static str, user_id: &String| {
+        app.clone().oneshot(form(uri, body, Some(user_id)))
+    };
+
+    let response = post("/chat/typing", "hangout_id=lobby", &ann)
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    expect_event(&mut bob_stream, "typing", "ann is typing...").await;
+
+    // sending a message ends the indicator
+    post("/chat/message", "send_message=hello&hangout_id=lobby", &ann)
+        .await
+        .unwrap();
+    let typing = expect_event(&mut bob_stream, "typing", "").await;
+    assert!(!typing.contains("ann"), "{}", typing);
+
+    let response = post("/chat/read", "hangout_id=lobby&message_id=0", &ann)
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    expect_event(&mut bob_stream, "read", "ann read up to #0").await;
+
+    // cid never joined, the marker is left out
+    post("/chat/read", "hangout_id=lobby&message_id=0", &cid)
+        .await
+        .unwrap();
+    // markers follow a renamed user
+    post(
+        "/chat/message",
+        "send_message=/nick anna&hangout_id=lobby",
+        &ann,
+    )
+    .await
+    .unwrap();
+    post("/chat/read", "hangout_id=lobby&message_id=0", &bob)
+        .await
+        .unwrap();
+
+    let read = expect_event(&mut ann_stream, "read", "bob read up to #0").await;
+    assert!(read.contains("anna read up to #0"), "{}", read);
+    assert!(!read.contains("cid"), "{}", read);
+}
+
+#[test]
+fn typing_indicator_is_expired_by_the_key_stroke_which_started_it() {
+    let state = chat::State::with_pubsub(
+        Arc::new(chat::pubsub::InProcess::new(chat::CHANNEL_CAPACITY)),
+        chat::KEEP_ALIVE,
+        chat::limit::Limits::default(),
+    );
+    let ann = state.claim_user_handle("ann").unwrap();
+    state.create_hangout("lobby", Some(&ann)).unwrap();
+
+    let (typing, started) = state.user_typing("lobby", &ann).unwrap();
+    assert_eq!(typing, vec!["ann".to_string()]);
+    let started = started.expect("first key stroke starts the indicator");
+    // throttled, the indicator is only kept alive
+    assert!(state.user_typing("lobby", &ann).is_none());
+
+    state.record_message("lobby", &ann, "hello").unwrap();
+    let (_, restarted) = state.user_typing("lobby", &ann).unwrap();
+    let restarted = restarted.expect("typing again starts a new indicator");
+
+    // the task of the first indicator ends, the new one is left to its own
+    assert_eq!(state.expire_typing("lobby", &ann, started), Ok(None));
+    assert!(state.expire_typing("lobby", &ann, restarted).is_err());
+}
+
+#[tokio::test]
+async fn user_input_is_escaped() {
+    let app = app();
+    let ann = claim(&app, "%3Cb%3Eann").await;
+    create_hangout(&app, "it%27s", &ann).await;
+    let mut stream = connect(&app, "it's", &ann).await;
+    expect_event(&mut stream, "user_join", "<div>&lt;b&gt;ann</div>").await;
+
+    let response = app
+        .clone()
+        .oneshot(form(
+            "/chat/message",
+            "send_message=%3Cscript%3Ex%3C%2Fscript%3E&hangout_id=it%27s",
+            Some(&ann),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    expect_event(
+        &mut stream,
+        "message",
+        "hx-vals=\"{&quot;hangout_id&quot;:&quot;it&#39;s&quot;,&quot;message_id&quot;:0}\">\
+         <b>&lt;b&gt;ann</b>: &lt;script&gt;x&lt;/script&gt;</div>",
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn missing_cookie_is_unauthorized() {
+    let app = app();
+    let ann = claim(&app, "ann").await;
+    create_hangout(&app, "lobby", &ann).await;
+
+    let response = app.clone().oneshot(get("/sse/lobby", None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = app
+        .clone()
+        .oneshot(form(
+            "/chat/message",
+            "send_message=hi&hangout_id=lobby",
+            None,
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn unknown_hangout_is_not_found() {
+    let app = app();
+    let ann = claim(&app, "ann").await;
+
+    let response = app
+        .clone()
+        .oneshot(get("/sse/nowhere", Some(&ann)))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = app
+        .clone()
+        .oneshot(form(
+            "/chat/message",
+            "send_message=hi&hangout_id=nowhere",
+            Some(&ann),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn duplicate_handle_is_refused() {
+    let app = app();
+    claim(&app, "ann").await;
+
+    let response = app
+        .clone()
+        .oneshot(form("/user", "user_handle=ann", None))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert!(response.headers().get(SET_COOKIE).is_none());
+}
+
+#[tokio::test]
+async fn handles_are_freed_once_users_leave() {
+    let app = app_with(chat::limit::Limits {
+        max_online: 2,
+        idle_timeout: Duration::ZERO,
+        ..Default::default()
+    });
+
+    let ann = claim(&app, "ann").await;
+    create_hangout(&app, "lobby", &ann).await;
+    let ann_stream = connect(&app, "lobby", &ann).await;
+    let bob = claim(&app, "bob").await;
+    let bob_stream = connect(&app, "lobby", &bob).await;
+
+    // both have open streams, hence stay online
+    let response = app
+        .clone()
+        .oneshot(form("/user", "user_handle=cid", None))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    drop(ann_stream);
+    drop(bob_stream);
+    claim(&app, "cid").await;
+    // bob's handle is free again
+    claim(&app, "bob").await;
+
+    let response = app
+        .clone()
+        .oneshot(get("/sse/lobby", Some(&ann)))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn search_is_limited_to_joined_hangouts() {
+    let app = app();
+    let ann = claim(&app, "ann").await;
+    let bob = claim(&app, "bob").await;
+    create_hangout(&app, "lobby", &ann).await;
+    let _stream = connect(&app, "lobby", &ann).await;
+
+    let response = app
+        .clone()
+        .oneshot(form(
+            "/chat/message",
+            "send_message=secret&hangout_id=lobby",
+            Some(&ann),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let search = "/api/v1/search?q=secret&hangout=lobby";
+    let response = app.clone().oneshot(get(search, Some(&bob))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = app.clone().oneshot(get(search, Some(&ann))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["hits"].as_array().map(Vec::len), Some(1));
+}
+
+#[tokio::test]
+async fn history_is_limited_to_joined_hangouts() {
+    let app = app();
+    let ann = claim(&app, "ann").await;
+    let bob = claim(&app, "bob").await;
+    create_hangout(&app, "lobby", &ann).await;
+    let _stream = connect(&app, "lobby", &ann).await;
+
+    let response = app
+        .clone()
+        .oneshot(form(
+            "/chat/message",
+            "send_message=secret&hangout_id=lobby",
+            Some(&ann),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    for uri in [
+        "/api/v1/hangouts/lobby/messages",
+        "/api/v1/hangouts/lobby/members",
+    ] {
+        let response = app.clone().oneshot(get(uri, None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{}", uri);
+
+        let response = app.clone().oneshot(get(uri, Some(&bob))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND, "{}", uri);
+    }
+
+    let response = app
+        .clone()
+        .oneshot(get("/api/v1/hangouts/lobby/messages", Some(&ann)))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["messages"][0]["body"], "secret");
+
+    let response = app
+        .clone()
+        .oneshot(get("/api/v1/hangouts/lobby/members", Some(&ann)))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["members"], serde_json::json!(["ann"]));
+}
+
+#[tokio::test]
+async fn oversized_upload_field_is_refused() {
+    let app = app();
+    let ann = claim(&app, "ann").await;
+
+    let body = format!(
+        "--x\r\nContent-Disposition: form-data; name=\"hangout_id\"\r\n\r\n{}\r\n\
+         --x\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\r\n\
+         hi\r\n--x--\r\n",
+        "a".repeat(1 << 20)
+    );
+    let request = Request::post("/chat/attachment")
+        .header(CONTENT_TYPE, "multipart/form-data; boundary=x")
+        .header(COOKIE, format!("user_handle={}", ann))
+        .body(Body::from(body))
+        .unwrap();
+
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    assert_eq!(body, "field hangout_id exceeds 1024 bytes");
+}
+
+/// a multipart upload of the file to the hangout
+fn upload(hangout: &str, file: &[u8], cookie: &str) -> Request<Body> {
+    let mut body = format!(
+        "--x\r\nContent-Disposition: form-data; name=\"hangout_id\"\r\n\r\n{}\r\n\
+         --x\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\r\n",
+        hangout
+    )
+    .into_bytes();
+    body.extend_from_slice(file);
+    body.extend_from_slice(b"\r\n--x--\r\n");
+
+    Request::post("/chat/attachment")
+        .header(CONTENT_TYPE, "multipart/form-data; boundary=x")
+        .header(COOKIE, format!("user_handle={}", cookie))
+        .body(Body::from(body))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn uploads_are_checked_before_the_body_is_read() {
+    let app = app_with(chat::limit::Limits {
+        session_messages: 0.5,
+        ..Default::default()
+    });
+    let ann = claim(&app, "ann").await;
+    create_hangout(&app, "lobby", &ann).await;
+    let _stream = connect(&app, "lobby", &ann).await;
+    let oversized = vec![b'a'; chat::attachment::MAX_SIZE + 1];
+
+    // were the body read first, the file would be refused as too large
+    let response = app
+        .clone()
+        .oneshot(upload("lobby", &oversized, "nobody"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = app
+        .clone()
+        .oneshot(upload("lobby", b"hello", &ann))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = app
+        .clone()
+        .oneshot(upload("lobby", &oversized, &ann))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(response.headers().contains_key(RETRY_AFTER));
+}
+
+#[tokio::test]
+async fn malformed_api_query_is_bad_request() {
+    let app = app();
+    let ann = claim(&app, "ann").await;
+    create_hangout(&app, "lobby", &ann).await;
+
+    let response = app
+        .clone()
+        .oneshot(get("/api/v1/hangouts/lobby/messages?limit=abc", Some(&ann)))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(response.headers()[CONTENT_TYPE], "application/json");
+
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["error"]["code"], "invalid_parameters");
+}
+
+/// waits for the next error frame on the WebSocket
+async fn expect_ws_error<S>(socket: &mut S) -> serde_json::Value
+where
+    S: futures::Stream<Item = Result<WsMessage, tokio_tungstenite::tungstenite::Error>> + Unpin,
+{
+    tokio::time::timeout(EVENT_TIMEOUT, async {
+        while let Some(Ok(msg)) = socket.next().await {
+            let WsMessage::Text(text) = msg else {
+                continue;
+            };
+            let frame: serde_json::Value = serde_json::from_str(&text).unwrap();
+            if frame["event"] == "error" {
+                return frame;
+            }
+        }
+        panic!("socket closed without an error frame");
+    })
+    .await
+    .expect("no error frame")
+}
+
+#[tokio::test]
+async fn websocket_refusals_are_sent_as_error_frames() {
+    let app = app_with(chat::limit::Limits {
+        session_messages: 0.5,
+        ..Default::default()
+    });
+    let ann = claim(&app, "ann").await;
+    create_hangout(&app, "lobby", &ann).await;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await });
+
+    let mut request = format!("ws://{}/ws/lobby", addr)
+        .into_client_request()
+        .unwrap();
+    request
+        .headers_mut()
+        .insert(COOKIE, format!("user_handle={}", ann).parse().unwrap());
+    let (mut socket, _) = tokio_tungstenite::connect_async(request).await.unwrap();
+
+    let long = "x".repeat(chat::limit::Limits::default().max_message_len + 1);
+    let refused = [
+        (
+            serde_json::json!({"type": "message", "body": "/shrug"}).to_string(),
+            422,
+            "unknown command /shrug".to_string(),
+        ),
+        // the refused command took the only token of the burst
+        (
+            serde_json::json!({"type": "message", "body": "hello"}).to_string(),
+            429,
+            "too many requests, slow down".to_string(),
+        ),
+        (
+            serde_json::json!({"type": "message", "body": long}).to_string(),
+            413,
+            format!("message exceeds {} bytes", long.len() - 1),
+        ),
+    ];
+
+    // the status and data are the ones of the HTTP path
+    for (frame, status, data) in refused {
+        socket.send(WsMessage::Text(frame)).await.unwrap();
+        let error = expect_ws_error(&mut socket).await;
+        assert_eq!(error["status"], status);
+        assert_eq!(error["data"], data);
+    }
+
+    socket
+        .send(WsMessage::Text("not a frame".to_string()))
+        .await
+        .unwrap();
+    let error = expect_ws_error(&mut socket).await;
+    assert_eq!(error["status"], 400);
+}
+
+#[tokio::test]
+async fn throttled_messages_are_told_when_to_retry() {
+    let app = app_with(chat::limit::Limits {
+        session_messages: 0.5,
+        ..Default::default()
+    });
+    let ann = claim(&app, "ann").await;
+    create_hangout(&app, "lobby", &ann).await;
+
+    let send = || {
+        app.clone().oneshot(form(
+            "/chat/message",
+            "send_message=hello&hangout_id=lobby",
+            Some(&ann),
+        ))
+    };
+
+    let response = send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // the burst of one message is used up and a token
+    // comes back every other second
+    let response = send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.headers()[RETRY_AFTER], "2");
+
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    assert_eq!(&body[..], b"too many requests, slow down");
+}
+
+/// value of the unlabelled metric in the text exposition format
+fn metric(metrics: &str, name: &str) -> u64 {
+    metrics
+        .lines()
+        .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+        .unwrap_or_else(|| panic!("{} missing from {}", name, metrics))
+        .parse()
+        .unwrap()
+}
+
+async fn scrape(app: &Router) -> String {
+    let response = app.clone().oneshot(get("/metrics", None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()[CONTENT_TYPE],
+        "text/plain; version=0.0.4"
+    );
+
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    String::from_utf8(body.to_vec()).unwrap()
+}
+
+#[tokio::test]
+async fn health_and_metrics_are_served() {
+    let app = app();
+
+    let response = app.clone().oneshot(get("/healthz", None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers()[CONTENT_TYPE]
+        .to_str()
+        .unwrap()
+        .starts_with("text/plain"));
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    assert_eq!(&body[..], b"ok");
+
+    let ann = claim(&app, "ann").await;
+    create_hangout(&app, "lobby", &ann).await;
+
+    let before = scrape(&app).await;
+    assert_eq!(metric(&before, "chat_online_users"), 1);
+    assert_eq!(metric(&before, "chat_hangouts"), 1);
+
+    let response = app
+        .clone()
+        .oneshot(form(
+            "/chat/message",
+            "send_message=hello&hangout_id=lobby",
+            Some(&ann),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let after = scrape(&app).await;
+    assert_eq!(
+        metric(&after, "chat_messages_sent_total"),
+        metric(&before, "chat_messages_sent_total") + 1
+    );
+}